@@ -0,0 +1,38 @@
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=DIAGONATOR_GIT_HASH={}", git_hash);
+
+    let build_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    println!("cargo:rustc-env=DIAGONATOR_BUILD_TIME={}", build_time);
+
+    let features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    println!("cargo:rustc-env=DIAGONATOR_FEATURES={}", features.join(","));
+
+    #[cfg(feature = "grpc")]
+    {
+        // the vendored protoc is used so that building doesn't require protoc to be installed
+        env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().unwrap());
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/diagonator.proto"], &["proto"])
+            .unwrap();
+    }
+}
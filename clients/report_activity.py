@@ -0,0 +1,30 @@
+#!/usr/bin/env python3
+
+import re
+import subprocess
+import sys
+import time
+
+import requests
+
+SERVER_URL = "http://localhost:3000"
+IDLE_THRESHOLD_MS = 5000
+
+
+def get_idle_ms():
+    if sys.platform == "darwin":
+        # HIDIdleTime is reported in nanoseconds
+        output = subprocess.run(
+            ["ioreg", "-c", "IOHIDSystem"], capture_output=True, text=True
+        ).stdout
+        return int(re.search(r'"HIDIdleTime" = (\d+)', output).group(1)) // 1000000
+    return int(subprocess.run(["xprintidle"], capture_output=True).stdout)
+
+
+was_idle = True
+while True:
+    is_idle = get_idle_ms() >= IDLE_THRESHOLD_MS
+    if was_idle and not is_idle:
+        requests.post(SERVER_URL, json={"type": "ReportActivity"})
+    was_idle = is_idle
+    time.sleep(1)
@@ -0,0 +1,42 @@
+#!/usr/bin/env python3
+
+import subprocess
+import sys
+import time
+
+import requests
+
+SERVER_URL = "http://localhost:3000"
+POLL_INTERVAL_SECONDS = 30
+
+
+def get_ssid():
+    if sys.platform == "darwin":
+        output = subprocess.run(
+            ["networksetup", "-getairportnetwork", "en0"],
+            capture_output=True,
+            text=True,
+        ).stdout
+        prefix = "Current Wi-Fi Network: "
+        return output.strip()[len(prefix) :] if output.startswith(prefix) else None
+    output = subprocess.run(
+        ["nmcli", "-t", "-f", "active,ssid", "dev", "wifi"],
+        capture_output=True,
+        text=True,
+    ).stdout
+    for line in output.splitlines():
+        active, _, ssid = line.partition(":")
+        if active == "yes":
+            return ssid
+    return None
+
+
+last_ssid = None
+first = True
+while True:
+    ssid = get_ssid()
+    if first or ssid != last_ssid:
+        requests.post(SERVER_URL, json={"type": "ReportLocation", "ssid": ssid})
+        last_ssid = ssid
+        first = False
+    time.sleep(POLL_INTERVAL_SECONDS)
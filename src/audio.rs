@@ -0,0 +1,73 @@
+use crate::config::AudioMuteConfig;
+use crate::manager::{CurrentInfo, CurrentState, CurrentStateReason};
+use tokio::process::Command;
+
+const CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+
+fn default_command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+async fn run_command(command: &[String]) -> Option<String> {
+    let (program, args) = command.split_first()?;
+    match Command::new(program).args(args).output().await {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            eprintln!("Audio command {:?} failed with {}", command, output.status);
+            None
+        }
+        Err(err) => {
+            eprintln!(
+                "Received error '{}' when running audio command {:?}",
+                err, command
+            );
+            None
+        }
+    }
+}
+
+/// Checks whether the audio is muted, e.g. from `pactl`'s `Mute: yes` or `wpctl`'s `[MUTED]`.
+async fn is_muted(query_command: &[String]) -> bool {
+    run_command(query_command).await.is_some_and(|output| {
+        let output = output.to_lowercase();
+        output.contains("yes") || output.contains("muted")
+    })
+}
+
+/// Mutes the system audio while the server is locked because of a locked time range, and
+/// restores the previous mute state once that lock ends. The audio is muted again on every check,
+/// so unmuting it during the lock doesn't last.
+pub async fn run_audio_mute(config: AudioMuteConfig, current_info: impl Fn() -> CurrentInfo) {
+    let mute_command = config
+        .mute_command
+        .unwrap_or_else(|| default_command(&["pactl", "set-sink-mute", "@DEFAULT_SINK@", "1"]));
+    let unmute_command = config
+        .unmute_command
+        .unwrap_or_else(|| default_command(&["pactl", "set-sink-mute", "@DEFAULT_SINK@", "0"]));
+    let query_command = config
+        .query_command
+        .unwrap_or_else(|| default_command(&["pactl", "get-sink-mute", "@DEFAULT_SINK@"]));
+    // whether the audio was muted before the lock, while the lock lasts
+    let mut muted_before_lock = None;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let info = current_info();
+        let hard_lock = info.state() == CurrentState::Locked
+            && matches!(info.reason(), CurrentStateReason::LockedTimeRange { .. });
+        if hard_lock {
+            if muted_before_lock.is_none() {
+                eprintln!("Muting audio during the locked time range");
+                muted_before_lock = Some(is_muted(&query_command).await);
+            }
+            run_command(&mute_command).await;
+        } else if let Some(muted) = muted_before_lock.take() {
+            eprintln!("Restoring audio after the locked time range");
+            if !muted {
+                run_command(&unmute_command).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::manager::{CurrentInfo, CurrentState, CurrentStateReason, PendingDeactivation};
+use crate::server::{Request, Response};
+use crate::time::Duration;
+
+/// Parses a chat message into a request. Commands start with `prefix`, e.g. `!status`.
+/// Returns `None` if the message is not a command.
+pub fn parse_command(text: &str, prefix: char) -> Option<Result<Request, String>> {
+    let mut words = text.trim().strip_prefix(prefix)?.split_whitespace();
+    // Telegram adds the bot's username to commands sent in groups, e.g. `/status@some_bot`
+    let command = words.next()?.split('@').next()?;
+    let id_argument = |id: &str| id.parse().map_err(|_| format!("'{}' is not an ID.", id));
+    Some(match (command, words.next(), words.next()) {
+        ("status", None, None) => Ok(Request::GetInfo),
+        ("complete", Some(id), None) => {
+            id_argument(id).map(|id| Request::CompleteRequirement { id, answers: None })
+        }
+        ("deactivate", Some(duration), None) => parse_duration(duration)
+            .map(|duration| Request::Deactivate { duration })
+            .ok_or_else(|| format!("'{}' is not a duration like 30m or 2h.", duration)),
+        ("approve", Some(id), None) => {
+            id_argument(id).map(|id| Request::ApproveDeactivation { id })
+        }
+        ("reject", Some(id), None) => id_argument(id).map(|id| Request::RejectDeactivation { id }),
+        _ => Err(format!(
+            "Available commands: {0}status, {0}complete <requirement ID>, {0}deactivate <duration, e.g. 30m or 2h>, {0}approve <deactivation ID>, {0}reject <deactivation ID>",
+            prefix
+        )),
+    })
+}
+
+/// Parses a number of minutes or hours, written as e.g. `30m` or `2h`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, minutes_per_unit) = match text.strip_suffix('h') {
+        Some(hours) => (hours, 60),
+        None => (text.strip_suffix('m')?, 1),
+    };
+    let number: i64 = number.parse().ok()?;
+    (number > 0).then(|| Duration::from_minutes(number * minutes_per_unit))
+}
+
+pub fn describe_response(response: &Response) -> String {
+    match response {
+        Response::Success => "Done.".to_owned(),
+        Response::Error { msg, .. }
+        | Response::InternalError { msg }
+        | Response::QuotaExceeded { msg, .. } => msg.clone(),
+        Response::Info { info } => describe_info(info),
+        Response::Pending { id } => format!("Deactivation {} is waiting for approval.", id),
+        response => serde_json::to_string(response).unwrap(),
+    }
+}
+
+/// Asks for a pending deactivation to be approved with commands starting with `prefix`.
+pub fn describe_pending_deactivation(pending: &PendingDeactivation, prefix: char) -> String {
+    format!(
+        "Deactivation {1} for {2} minutes is waiting for approval. Send {0}approve {1} or {0}reject {1}.",
+        prefix,
+        pending.id(),
+        pending.duration().as_minutes()
+    )
+}
+
+/// Describes the current state and the incomplete requirements in a few lines of text.
+pub fn describe_info(info: &CurrentInfo) -> String {
+    let mut text = match info.state() {
+        CurrentState::Unlocked => "Unlocked",
+        CurrentState::Locked => "Locked",
+        CurrentState::Unlockable => "Unlockable",
+    }
+    .to_owned();
+    if let Some(until) = info.until() {
+        text.push_str(&format!(" until {}", until.format_local("%H:%M")));
+    }
+    let requirement_name = |id: &u64| {
+        info.requirements()
+            .iter()
+            .find(|req| req.id() == *id)
+            .map_or_else(|| id.to_string(), |req| format!("'{}'", req.name()))
+    };
+    let reason = match info.reason() {
+        CurrentStateReason::BreakTimer => "break timer".to_owned(),
+        CurrentStateReason::RequirementNotMet { id }
+        | CurrentStateReason::CriticalRequirementNotMet { id } => {
+            format!("requirement {}", requirement_name(id))
+        }
+        CurrentStateReason::LockedTimeRange { id } => format!("locked time range {}", id),
+        CurrentStateReason::NoConstraints => "no constraints".to_owned(),
+    };
+    text.push_str(&format!(" ({})", reason));
+    for req in info.requirements().iter().filter(|req| !req.is_complete()) {
+        text.push_str(&format!(
+            "\n[{}] {}, due {}",
+            req.id(),
+            req.name(),
+            req.due().format_local("%H:%M")
+        ));
+    }
+    text
+}
@@ -0,0 +1,107 @@
+use crate::time::{Duration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Observer,
+    User,
+    Partner,
+    Admin,
+}
+
+/// What a request needs to be allowed to do.
+#[derive(Clone, Copy)]
+pub enum Permission {
+    Read,
+    Write,
+    Partner,
+    Admin,
+}
+
+impl Role {
+    pub fn allows(self, permission: Permission) -> bool {
+        match permission {
+            Permission::Read => true,
+            Permission::Write => matches!(self, Self::User | Self::Admin),
+            Permission::Partner => matches!(self, Self::Partner),
+            Permission::Admin => matches!(self, Self::Admin),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientInfo {
+    label: String,
+    role: Role,
+    subscribed: bool,
+    last_activity: Timestamp,
+}
+
+struct ClientEntry {
+    role: Role,
+    subscriptions: u32,
+    last_activity: Timestamp,
+}
+
+/// How many days a client that isn't subscribed is remembered after its last request
+const FORGET_AFTER_DAYS: i64 = 7;
+
+/// Keeps track of the clients that have talked to the server, identified by their token labels.
+/// Clients that aren't subscribed are forgotten once they haven't sent a request for
+/// `FORGET_AFTER_DAYS`, so that the registry doesn't keep growing.
+pub struct ClientRegistry {
+    clients: BTreeMap<String, ClientEntry>,
+}
+
+impl ClientRegistry {
+    pub fn new() -> Self {
+        Self {
+            clients: BTreeMap::new(),
+        }
+    }
+    fn prune(&mut self, current_time: Timestamp) {
+        let forget_after = Duration::from_minutes(FORGET_AFTER_DAYS * 24 * 60);
+        self.clients.retain(|_, entry| {
+            entry.subscriptions > 0 || current_time - entry.last_activity < forget_after
+        });
+    }
+    fn entry(&mut self, label: &str, role: Role, current_time: Timestamp) -> &mut ClientEntry {
+        if !self.clients.contains_key(label) {
+            self.prune(current_time);
+        }
+        let entry = self.clients.entry(label.to_owned()).or_insert(ClientEntry {
+            role,
+            subscriptions: 0,
+            last_activity: current_time,
+        });
+        entry.role = role;
+        entry.last_activity = current_time;
+        entry
+    }
+    pub fn record_activity(&mut self, label: &str, role: Role, current_time: Timestamp) {
+        self.entry(label, role, current_time);
+    }
+    pub fn subscribe(&mut self, label: &str, role: Role, current_time: Timestamp) {
+        self.entry(label, role, current_time).subscriptions += 1;
+    }
+    pub fn unsubscribe(&mut self, label: &str, current_time: Timestamp) {
+        if let Some(entry) = self.clients.get_mut(label) {
+            entry.subscriptions = entry.subscriptions.saturating_sub(1);
+            entry.last_activity = current_time;
+        }
+    }
+    pub fn list(&mut self, current_time: Timestamp) -> Vec<ClientInfo> {
+        self.prune(current_time);
+        self.clients
+            .iter()
+            .map(|(label, entry)| ClientInfo {
+                label: label.clone(),
+                role: entry.role,
+                subscribed: entry.subscriptions > 0,
+                last_activity: entry.last_activity,
+            })
+            .collect()
+    }
+}
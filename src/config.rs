@@ -1,61 +1,687 @@
-use crate::time::HourMinute;
+use crate::clients::Role;
+use crate::refusal::RefusalCode;
+use crate::time::{parse_date, HourMinute, LocalDate, TimeOfDay};
+use chrono::{Datelike, Weekday};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
 use std::fmt::Display;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use toml_edit::easy as toml;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RequirementConfig {
     pub name: String,
     pub due: HourMinute,
+    pub order: Option<i64>,
+    pub pinned: Option<bool>,
+    pub lock_from_start: Option<bool>,
+    pub activity: Option<String>,
+    pub activity_minutes: Option<i64>,
+    pub count: Option<u64>,
+    pub expires: Option<HourMinute>,
+    pub estimate_minutes: Option<i64>,
+    /// Run when the requirement becomes due while it is still incomplete
+    pub on_due: Option<Vec<String>>,
+    pub on_complete: Option<Vec<String>>,
+    /// Run when the requirement expires or the day ends before it is completed
+    pub on_missed: Option<Vec<String>>,
+    /// The name of the requirement group that the requirement belongs to
+    pub group: Option<String>,
+    /// Ways to complete the requirement, any one of which completes it
+    pub alternatives: Option<Vec<AlternativeConfig>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// One of several ways to complete a requirement, such as reading for 30 minutes or using a
+/// language app for 20 minutes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlternativeConfig {
+    pub name: String,
+    pub activity: Option<String>,
+    pub activity_minutes: Option<i64>,
+    pub count: Option<u64>,
+}
+
+/// When a group of requirements is satisfied.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMode {
+    /// Once all of its requirements are completed.
+    #[default]
+    All,
+    /// Once any one of its requirements is completed, after which the others no longer lock the
+    /// screen.
+    Any,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RequirementGroupConfig {
+    pub name: String,
+    pub mode: Option<GroupMode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LockedTimeRangeConfig {
-    pub start: Option<HourMinute>,
-    pub end: Option<HourMinute>,
+    pub start: Option<TimeOfDay>,
+    pub end: Option<TimeOfDay>,
+    pub exceptions: Option<AppListConfig>,
+    pub kind: Option<RangeKind>,
+}
+
+/// What happens during a range in `locked_time_ranges`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RangeKind {
+    /// The screen is locked.
+    #[default]
+    Lock,
+    /// The screen stays unlocked, but the break timer is paused.
+    Meeting,
+}
+
+/// Applications (e.g. window classes) and URLs, such as those that enforcement clients should
+/// block or keep available while locked.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AppListConfig {
+    pub applications: Option<Vec<String>>,
+    pub urls: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DailyReviewConfig {
+    pub name: Option<String>,
+    pub due: HourMinute,
+    pub prompts: Vec<String>,
+}
+
+/// The weights of the parts of the daily focus score.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FocusScoreConfig {
+    /// The focus time that earns the full weight of focus time
+    pub focus_goal_minutes: Option<i64>,
+    pub focus_weight: Option<u32>,
+    pub break_compliance_weight: Option<u32>,
+    pub on_time_weight: Option<u32>,
+    pub deactivation_weight: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WeeklyTemplateConfig {
+    pub monday: Option<Vec<LockedTimeRangeConfig>>,
+    pub tuesday: Option<Vec<LockedTimeRangeConfig>>,
+    pub wednesday: Option<Vec<LockedTimeRangeConfig>>,
+    pub thursday: Option<Vec<LockedTimeRangeConfig>>,
+    pub friday: Option<Vec<LockedTimeRangeConfig>>,
+    pub saturday: Option<Vec<LockedTimeRangeConfig>>,
+    pub sunday: Option<Vec<LockedTimeRangeConfig>>,
+}
+
+impl WeeklyTemplateConfig {
+    pub fn for_weekday(&self, weekday: Weekday) -> &[LockedTimeRangeConfig] {
+        let blocks = match weekday {
+            Weekday::Mon => &self.monday,
+            Weekday::Tue => &self.tuesday,
+            Weekday::Wed => &self.wednesday,
+            Weekday::Thu => &self.thursday,
+            Weekday::Fri => &self.friday,
+            Weekday::Sat => &self.saturday,
+            Weekday::Sun => &self.sunday,
+        };
+        blocks.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrunchModeConfig {
+    pub work_period_minutes: Option<i64>,
+    pub break_minutes: Option<i64>,
+    pub requirements: Option<Vec<RequirementConfig>>,
+    pub locked_time_ranges: Option<Vec<LockedTimeRangeConfig>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub work_period_minutes: Option<i64>,
+    pub break_minutes: Option<i64>,
+    pub requirements: Option<Vec<RequirementConfig>>,
+    pub locked_time_ranges: Option<Vec<LockedTimeRangeConfig>>,
+    pub planning_gate: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileRuleConfig {
+    pub profile: String,
+    pub ssid: Option<String>,
+    pub location: Option<String>,
+}
+
+impl ProfileRuleConfig {
+    pub fn matches(&self, ssid: Option<&str>, location: Option<&str>) -> bool {
+        (self.ssid.is_some() && self.ssid.as_deref() == ssid)
+            || (self.location.is_some() && self.location.as_deref() == location)
+    }
+}
+
+/// Makes a profile active on certain weekdays (e.g. `"sat"` or `"saturday"`) and/or between two
+/// dates in the `YYYY-MM-DD` format, both inclusive.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileScheduleConfig {
+    pub profile: String,
+    pub weekdays: Option<Vec<String>>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl ProfileScheduleConfig {
+    pub fn matches(&self, date: &LocalDate) -> bool {
+        let weekday = date.weekday();
+        self.weekdays.as_ref().is_none_or(|weekdays| {
+            weekdays
+                .iter()
+                .any(|name| name.parse::<Weekday>().ok() == Some(weekday))
+        }) && self
+            .from
+            .as_deref()
+            .and_then(parse_date)
+            .is_none_or(|from| from <= *date)
+            && self
+                .to
+                .as_deref()
+                .and_then(parse_date)
+                .is_none_or(|to| *date <= to)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DayOverrideConfig {
+    pub date: String,
+    pub skip_requirements: Option<Vec<String>>,
+    pub requirements: Option<Vec<RequirementConfig>>,
+    pub locked_time_ranges: Option<Vec<LockedTimeRangeConfig>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StopwatchConfig {
+    pub work_minutes_per_break_minute: i64,
+    pub min_break_minutes: Option<i64>,
+    pub max_break_minutes: Option<i64>,
+    pub max_work_period_minutes: Option<i64>,
+}
+
+/// Lengthens the following breaks when breaks are cut short or spent deactivated, until the
+/// missed rest is made up.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestDebtConfig {
+    /// The most rest debt that can accrue
+    pub max_minutes: i64,
+    /// The most that a single break is lengthened by, all of the debt by default
+    pub max_extension_minutes: Option<i64>,
+}
+
+/// Lets breaks be ended early, banking the rest of them as credit that can be spent on a longer
+/// break later in the day.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BreakCreditConfig {
+    /// The most break credit that can be banked
+    pub max_minutes: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimerPresetConfig {
+    pub name: String,
+    pub work_period_minutes: i64,
+    pub break_minutes: i64,
+}
+
+/// Timer presets that are available without being defined in the configuration file.
+fn builtin_presets() -> [TimerPresetConfig; 3] {
+    [
+        ("pomodoro", 25, 5),
+        ("52/17", 52, 17),
+        ("ultradian", 90, 20),
+    ]
+    .map(
+        |(name, work_period_minutes, break_minutes)| TimerPresetConfig {
+            name: name.to_owned(),
+            work_period_minutes,
+            break_minutes,
+        },
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeactivationApprovalConfig {
+    pub partner_token: String,
+    pub timeout_minutes: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LimitsConfig {
+    pub max_deactivation_minutes: Option<i64>,
+    pub max_weekly_deactivation_minutes: Option<i64>,
+    pub max_work_period_minutes: Option<i64>,
+    pub min_break_minutes: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenConfig {
+    pub token: String,
+    pub label: String,
+    pub role: Role,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HookConfig {
+    pub event: String,
+    pub command: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebhookConfig {
+    pub event: String,
+    pub url: String,
+    pub template: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatUserConfig {
+    pub user_id: String,
+    pub role: Role,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MatrixConfig {
+    pub homeserver: String,
+    pub access_token: String,
+    pub room_id: String,
+    pub users: Vec<ChatUserConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TelegramConfig {
+    pub bot_token: String,
+    pub chat_id: i64,
+    pub users: Vec<ChatUserConfig>,
+    pub remind_before_due_minutes: Option<i64>,
+    pub api_url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    Tls,
+    StartTls,
+    None,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EmailDigestConfig {
+    pub smtp_server: String,
+    pub smtp_port: Option<u16>,
+    pub security: Option<SmtpSecurity>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub send_at: HourMinute,
+}
+
+/// The day that weeks start on, for the features that count per week.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WeekStart {
+    #[default]
+    Monday,
+    Sunday,
+    Saturday,
+}
+
+impl WeekStart {
+    pub fn weekday(self) -> Weekday {
+        match self {
+            Self::Monday => Weekday::Mon,
+            Self::Sunday => Weekday::Sun,
+            Self::Saturday => Weekday::Sat,
+        }
+    }
+}
+
+/// How the state file is encoded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateFormat {
+    /// Readable and easy to inspect when debugging.
+    #[serde(rename = "json")]
+    Json,
+    /// A more compact binary encoding for large histories.
+    #[serde(rename = "msgpack")]
+    MessagePack,
+    /// bincode, through a self-describing value, since bincode can't decode the saved state's
+    /// optional fields and tagged enums directly.
+    #[serde(rename = "bincode")]
+    Bincode,
+}
+
+/// What the server does when it can't restore its saved state at startup.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StateRecovery {
+    /// Lock until today's requirements are completed again.
+    FailClosed,
+    /// Start the day from scratch.
+    FailOpen,
+    /// Lock until an admin decides whether to fail open or closed.
+    AskAdmin,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain
+    pub certificate: PathBuf,
+    /// PEM file with the private key
+    pub private_key: PathBuf,
+}
+
+/// An additional endpoint that the server listens on besides `bind_on`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListenerConfig {
+    pub bind_on: String,
+    /// Whether to use the `tls` certificate, false by default
+    pub tls: Option<bool>,
+    /// The roles of the clients that may connect through this listener, all roles by default
+    pub roles: Option<Vec<Role>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusPageConfig {
+    pub directory: PathBuf,
+    pub interval_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DisplayConfig {
+    pub bind_on: Option<String>,
+    pub broadcast_to: Option<String>,
+    pub broadcast_interval_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AudioMuteConfig {
+    pub mute_command: Option<Vec<String>>,
+    pub unmute_command: Option<Vec<String>>,
+    pub query_command: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IdleDetectionConfig {
+    /// How long without keyboard or mouse input counts as being idle, 60 seconds by default
+    pub idle_threshold_seconds: Option<u64>,
+    /// Whether locking the screen during a work period starts the break
+    pub lock_timer_on_screen_lock: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbusConfig {
+    /// The role of D-Bus clients, `user` by default
+    pub role: Option<Role>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EventLogConfig {
+    pub path: PathBuf,
+    /// The size at which the log is rotated, 1024 KiB by default
+    pub max_size_kb: Option<u64>,
+    /// The age of the oldest event at which the log is rotated, 7 days by default
+    pub max_age_days: Option<i64>,
+    /// The number of compressed archives that are kept, all of them by default
+    pub max_archives: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MdnsConfig {
+    /// The name that clients show for the server, the host name by default
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DiagonatorConfig {
     pub bind_on: String,
+    pub tls: Option<TlsConfig>,
+    pub listeners: Option<Vec<ListenerConfig>>,
+    pub grpc_bind_on: Option<String>,
+    pub keep_alive_seconds: Option<u64>,
+    pub max_connections: Option<usize>,
+    pub idle_timeout_seconds: Option<u64>,
     pub requirements: Option<Vec<RequirementConfig>>,
+    pub requirement_groups: Option<Vec<RequirementGroupConfig>>,
     pub locked_time_ranges: Option<Vec<LockedTimeRangeConfig>>,
+    pub exceptions: Option<AppListConfig>,
+    pub blocklist: Option<AppListConfig>,
     pub work_period_minutes: i64,
     pub break_minutes: i64,
+    pub long_break_minutes: Option<i64>,
+    pub periods_per_long_break: Option<u64>,
+    pub location: Option<Location>,
+    pub require_intent: Option<bool>,
+    pub planning_gate: Option<bool>,
+    pub daily_review: Option<DailyReviewConfig>,
+    pub focus_score: Option<FocusScoreConfig>,
+    pub warn_before_break_end_seconds: Option<i64>,
+    pub warn_before_day_end_minutes: Option<i64>,
+    pub pressure_minutes: Option<i64>,
+    pub hooks: Option<Vec<HookConfig>>,
+    pub webhooks: Option<Vec<WebhookConfig>>,
+    pub matrix: Option<MatrixConfig>,
+    pub telegram: Option<TelegramConfig>,
+    pub email_digest: Option<EmailDigestConfig>,
+    pub status_page: Option<StatusPageConfig>,
+    pub display: Option<DisplayConfig>,
+    pub audio_mute: Option<AudioMuteConfig>,
+    pub idle_detection: Option<IdleDetectionConfig>,
+    pub dbus: Option<DbusConfig>,
+    pub mdns: Option<MdnsConfig>,
+    pub state_file: Option<PathBuf>,
+    pub state_format: Option<StateFormat>,
+    pub state_recovery: Option<StateRecovery>,
+    pub event_log: Option<EventLogConfig>,
+    pub auto_unlock_after_break: Option<bool>,
+    pub auto_unlock_at: Option<HourMinute>,
+    pub auto_unlock_requires_activity: Option<bool>,
+    pub admin_token: Option<String>,
+    pub auth_token: Option<String>,
+    pub weekly_template: Option<WeeklyTemplateConfig>,
+    pub week_starts_on: Option<WeekStart>,
+    pub crunch_mode: Option<CrunchModeConfig>,
+    pub deactivation_approval: Option<DeactivationApprovalConfig>,
+    pub config_loosening_delay_hours: Option<i64>,
+    pub tokens: Option<Vec<TokenConfig>>,
+    pub limits: Option<LimitsConfig>,
+    pub check_for_updates: Option<bool>,
+    pub hide_completed_requirements_after_minutes: Option<i64>,
+    pub merge_locked_time_ranges: Option<bool>,
+    pub min_work_period_minutes: Option<i64>,
+    pub stopwatch: Option<StopwatchConfig>,
+    pub rest_debt: Option<RestDebtConfig>,
+    pub break_credit: Option<BreakCreditConfig>,
+    pub preset: Option<String>,
+    pub presets: Option<Vec<TimerPresetConfig>>,
+    pub day_overrides: Option<Vec<DayOverrideConfig>>,
+    pub profiles: Option<Vec<ProfileConfig>>,
+    pub profile_rules: Option<Vec<ProfileRuleConfig>>,
+    pub profile_schedule: Option<Vec<ProfileScheduleConfig>>,
+    /// Replaces the default messages of refusals, keyed by their code
+    pub refusal_messages: Option<HashMap<RefusalCode, String>>,
+    /// The file that the configuration was loaded from, if any
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    /// The layers that the configuration was merged from, if it was loaded from a file
+    #[serde(skip)]
+    pub layers: Option<ConfigLayers>,
+}
+
+/// The layers that a configuration was merged from, which are kept so that patches only change
+/// the user's layer, and the system-wide layer and its limits still apply afterwards.
+#[derive(Debug, Clone)]
+pub struct ConfigLayers {
+    /// The system-wide configuration file, if it is used as the base layer
+    system: Option<toml::Value>,
+    /// The configuration file, with the loosening changes that already took effect
+    user: toml::Value,
+    /// The day override from `today.toml`, which is added after merging the layers
+    today: Option<DayOverrideConfig>,
+}
+
+impl ConfigLayers {
+    /// Merges the layers into a configuration the same way as when it is loaded.
+    fn config(&self) -> Result<DiagonatorConfig, LoadConfigError> {
+        let value = match &self.system {
+            Some(system) => merge_layers(system, self.user.clone()),
+            None => self.user.clone(),
+        };
+        let mut config = config_from_value(value)?;
+        if let Some(today) = &self.today {
+            config
+                .day_overrides
+                .get_or_insert_with(Vec::new)
+                .push(today.clone());
+            validate_config(&config)?;
+        }
+        Ok(config)
+    }
 }
 
 impl Default for DiagonatorConfig {
     fn default() -> Self {
         Self {
             bind_on: "0.0.0.0:3000".to_owned(),
+            tls: None,
+            listeners: None,
+            grpc_bind_on: None,
+            keep_alive_seconds: None,
+            max_connections: None,
+            idle_timeout_seconds: None,
             requirements: Some(vec![
                 RequirementConfig {
                     name: "Name of requirement 1".to_owned(),
                     due: HourMinute::new(8, 30).unwrap(),
+                    order: None,
+                    pinned: None,
+                    lock_from_start: None,
+                    activity: None,
+                    activity_minutes: None,
+                    count: None,
+                    expires: None,
+                    estimate_minutes: None,
+                    on_due: None,
+                    on_complete: None,
+                    on_missed: None,
+                    group: None,
+                    alternatives: None,
                 },
                 RequirementConfig {
                     name: "Name of requirement 2".to_owned(),
                     due: HourMinute::new(20, 00).unwrap(),
+                    order: None,
+                    pinned: None,
+                    lock_from_start: None,
+                    activity: None,
+                    activity_minutes: None,
+                    count: None,
+                    expires: None,
+                    estimate_minutes: None,
+                    on_due: None,
+                    on_complete: None,
+                    on_missed: None,
+                    group: None,
+                    alternatives: None,
                 },
             ]),
+            requirement_groups: None,
             locked_time_ranges: Some(vec![
                 LockedTimeRangeConfig {
                     start: None,
-                    end: Some(HourMinute::new(4, 30).unwrap()),
+                    end: Some(TimeOfDay::Clock(HourMinute::new(4, 30).unwrap())),
+                    exceptions: None,
+                    kind: None,
                 },
                 LockedTimeRangeConfig {
-                    start: Some(HourMinute::new(12, 00).unwrap()),
-                    end: Some(HourMinute::new(13, 00).unwrap()),
+                    start: Some(TimeOfDay::Clock(HourMinute::new(12, 00).unwrap())),
+                    end: Some(TimeOfDay::Clock(HourMinute::new(13, 00).unwrap())),
+                    exceptions: None,
+                    kind: None,
                 },
                 LockedTimeRangeConfig {
-                    start: Some(HourMinute::new(22, 00).unwrap()),
+                    start: Some(TimeOfDay::Clock(HourMinute::new(22, 00).unwrap())),
                     end: None,
+                    exceptions: None,
+                    kind: None,
                 },
             ]),
+            exceptions: None,
+            blocklist: None,
             work_period_minutes: 25,
             break_minutes: 5,
+            long_break_minutes: None,
+            periods_per_long_break: None,
+            location: None,
+            require_intent: None,
+            planning_gate: None,
+            daily_review: None,
+            focus_score: None,
+            warn_before_break_end_seconds: None,
+            warn_before_day_end_minutes: None,
+            pressure_minutes: None,
+            hooks: None,
+            webhooks: None,
+            matrix: None,
+            telegram: None,
+            email_digest: None,
+            status_page: None,
+            display: None,
+            audio_mute: None,
+            idle_detection: None,
+            dbus: None,
+            mdns: None,
+            state_file: None,
+            state_format: None,
+            state_recovery: None,
+            event_log: None,
+            auto_unlock_after_break: None,
+            auto_unlock_at: None,
+            auto_unlock_requires_activity: None,
+            admin_token: None,
+            auth_token: None,
+            weekly_template: None,
+            week_starts_on: None,
+            crunch_mode: None,
+            deactivation_approval: None,
+            config_loosening_delay_hours: None,
+            tokens: None,
+            limits: None,
+            check_for_updates: None,
+            hide_completed_requirements_after_minutes: None,
+            merge_locked_time_ranges: None,
+            min_work_period_minutes: None,
+            stopwatch: None,
+            rest_debt: None,
+            break_credit: None,
+            preset: None,
+            presets: None,
+            day_overrides: None,
+            profiles: None,
+            profile_rules: None,
+            profile_schedule: None,
+            refusal_messages: None,
+            path: None,
+            layers: None,
         }
     }
 }
@@ -68,6 +694,7 @@ pub enum LoadConfigError {
     WriteError(PathBuf, std::io::Error),
     ReadError(PathBuf, std::io::Error),
     CreateDirError(PathBuf, std::io::Error),
+    InvalidConfig(String),
 }
 
 impl Display for LoadConfigError {
@@ -110,6 +737,9 @@ impl Display for LoadConfigError {
                     path.display()
                 )
             }
+            Self::InvalidConfig(msg) => {
+                write!(f, "Invalid configuration: {}", msg)
+            }
         }
     }
 }
@@ -135,19 +765,1159 @@ fn make_default_config(config_file_path: &PathBuf) -> Result<(), LoadConfigError
         .map_err(|err| LoadConfigError::WriteError(config_file_path.clone(), err))
 }
 
-pub fn load_config() -> Result<DiagonatorConfig, LoadConfigError> {
-    let mut config_file_path = dirs::config_dir().ok_or(LoadConfigError::ConfigDirNotFound)?;
-    config_file_path.push("diagonator-server");
-    fs::create_dir_all(&config_file_path)
-        .map_err(|err| LoadConfigError::CreateDirError(config_file_path.clone(), err))?;
-    config_file_path.push("config.toml");
+#[cfg(not(windows))]
+fn system_config_path() -> PathBuf {
+    PathBuf::from("/etc/diagonator-server/config.toml")
+}
+
+#[cfg(windows)]
+fn system_config_path() -> PathBuf {
+    let program_data = env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+    Path::new(&program_data)
+        .join("diagonator-server")
+        .join("config.toml")
+}
+
+/// Loads the configuration from the first available source:
+/// 1. the file passed with `--config`
+/// 2. the file specified by the `DIAGONATOR_CONFIG` environment variable
+/// 3. the user's configuration directory (the file is created if it doesn't exist)
+/// 4. the system-wide configuration file
+/// 5. the built-in default configuration
+///
+/// If the system-wide configuration file exists, it is also used as a base layer under the
+/// configuration files from sources 1-3.
+pub fn load_config(explicit_path: Option<PathBuf>) -> Result<DiagonatorConfig, LoadConfigError> {
+    let system_path = system_config_path();
+    let system_layer = if system_path.exists() {
+        eprintln!("Using {} as the base configuration", system_path.display());
+        Some(toml::from_str(&read_file(&system_path)?)?)
+    } else {
+        None
+    };
+    if let Some(path) =
+        explicit_path.or_else(|| env::var_os("DIAGONATOR_CONFIG").map(PathBuf::from))
+    {
+        return load_config_file(&path, true, system_layer.as_ref());
+    }
+    match prepare_user_config_file() {
+        Ok(path) => return load_config_file(&path, true, system_layer.as_ref()),
+        Err(err) => eprintln!("Unable to use user configuration file: {}", err),
+    }
+    if system_layer.is_some() {
+        return load_config_file(&system_path, false, None);
+    }
+    eprintln!("WARNING: No configuration file is available, so the built-in default configuration will be used!");
+    Ok(DiagonatorConfig::default())
+}
+
+/// Parses a setting given on the command line as a TOML value, so that numbers, booleans, and
+/// arrays keep their types, while anything else is taken as a string.
+fn parse_setting(value: &str) -> toml::Value {
+    toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
+/// Builds the configuration of an `--ephemeral` run, which doesn't read or write any files: the
+/// built-in default configuration, with the given settings (e.g. `("break_minutes", "1")` or
+/// `("display.bind_on", "0.0.0.0:3978")`) and requirements (e.g. `("Stretch", "10:30")`)
+/// instead of the default ones. Nothing is persisted, so the state file and event log are
+/// always disabled.
+pub fn ephemeral_config(
+    settings: &[(String, String)],
+    requirements: &[(String, String)],
+) -> Result<DiagonatorConfig, LoadConfigError> {
+    let mut value = toml::Value::try_from(DiagonatorConfig::default()).unwrap();
+    for (key, setting) in settings {
+        let mut table = value.as_table_mut().unwrap();
+        let mut parts: Vec<&str> = key.split('.').collect();
+        let last = parts.pop().unwrap_or_default();
+        for part in parts {
+            table = table
+                .entry(part.to_owned())
+                .or_insert_with(|| toml::Value::Table(toml::value::Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| {
+                    LoadConfigError::InvalidConfig(format!("'{}' is not a table", part))
+                })?;
+        }
+        table.insert(last.to_owned(), parse_setting(setting));
+    }
+    if !requirements.is_empty() {
+        let requirements = requirements
+            .iter()
+            .map(|(name, due)| {
+                let mut requirement = toml::value::Table::new();
+                requirement.insert("name".to_owned(), toml::Value::String(name.clone()));
+                requirement.insert("due".to_owned(), toml::Value::String(due.clone()));
+                toml::Value::Table(requirement)
+            })
+            .collect();
+        value
+            .as_table_mut()
+            .unwrap()
+            .insert("requirements".to_owned(), toml::Value::Array(requirements));
+    }
+    let mut config = config_from_value(value)?;
+    config.state_file = None;
+    config.event_log = None;
+    Ok(config)
+}
+
+fn prepare_user_config_file() -> Result<PathBuf, LoadConfigError> {
+    let mut config_dir = dirs::config_dir().ok_or(LoadConfigError::ConfigDirNotFound)?;
+    config_dir.push("diagonator-server");
+    fs::create_dir_all(&config_dir)
+        .map_err(|err| LoadConfigError::CreateDirError(config_dir.clone(), err))?;
+    let config_file_path = config_dir.join("config.toml");
     if !config_file_path.exists() {
         make_default_config(&config_file_path)?;
     }
+    Ok(config_file_path)
+}
+
+fn load_config_file(
+    config_file_path: &Path,
+    delay_loosening: bool,
+    base_layer: Option<&toml::Value>,
+) -> Result<DiagonatorConfig, LoadConfigError> {
     eprintln!("Loading configuration from {}", config_file_path.display());
-    let contents = fs::read_to_string(&config_file_path)
-        .map_err(|err| LoadConfigError::ReadError(config_file_path, err))?;
+    let contents = read_file(config_file_path)?;
 
-    let config = toml::from_str(&contents)?;
+    let config_dir = config_file_path.parent().unwrap_or(Path::new("."));
+    let mut config = parse_config(&contents, base_layer)?;
+    let mut user_contents = contents;
+    if delay_loosening {
+        let applied_contents = delay_loosening_changes(config_dir, user_contents.clone())?;
+        if applied_contents != user_contents {
+            config = parse_config(&applied_contents, base_layer)?;
+            user_contents = applied_contents;
+        }
+    }
+    config.layers = Some(ConfigLayers {
+        system: base_layer.cloned(),
+        user: toml::from_str(&user_contents)?,
+        today: None,
+    });
+    load_today_file(config_dir, &mut config)?;
+    config.path = Some(config_file_path.to_owned());
     Ok(config)
 }
+
+/// Adds the day override in `today.toml` next to the configuration file, if there is one.
+/// The file is ignored if `config_loosening_delay_hours` is set, since it would take effect
+/// without the delay.
+fn load_today_file(
+    config_dir: &Path,
+    config: &mut DiagonatorConfig,
+) -> Result<(), LoadConfigError> {
+    let today_path = config_dir.join("today.toml");
+    if !today_path.exists() {
+        return Ok(());
+    }
+    if config.config_loosening_delay_hours.is_some() {
+        eprintln!(
+            "Ignoring {} because config_loosening_delay_hours is set, use day_overrides in the configuration file instead",
+            today_path.display()
+        );
+        return Ok(());
+    }
+    eprintln!("Loading day override from {}", today_path.display());
+    let day_override: DayOverrideConfig = toml::from_str(&read_file(&today_path)?)?;
+    if let Some(layers) = &mut config.layers {
+        layers.today = Some(day_override.clone());
+    }
+    config
+        .day_overrides
+        .get_or_insert_with(Vec::new)
+        .push(day_override);
+    validate_config(config)
+}
+
+fn parse_config(
+    contents: &str,
+    base_layer: Option<&toml::Value>,
+) -> Result<DiagonatorConfig, LoadConfigError> {
+    let mut value: toml::Value = toml::from_str(contents)?;
+    if let Some(base_layer) = base_layer {
+        value = merge_layers(base_layer, value);
+    }
+    config_from_value(value)
+}
+
+fn config_from_value(value: toml::Value) -> Result<DiagonatorConfig, LoadConfigError> {
+    let mut config: DiagonatorConfig = value.try_into().map_err(toml::de::Error::from)?;
+    resolve_presets(&mut config)?;
+    apply_limits(&mut config);
+    validate_config(&config)?;
+    Ok(config)
+}
+
+/// Merges a configuration layer over a base layer. Tables are merged key by key and values from
+/// the layer replace values from the base, except that requirements and locked time ranges from
+/// both layers are kept, and the stricter of the two values is used for each limit.
+fn merge_layers(base: &toml::Value, layer: toml::Value) -> toml::Value {
+    let (base, mut layer) = match (base.as_table(), layer) {
+        (Some(base), toml::Value::Table(layer)) => (base, layer),
+        (_, layer) => return layer,
+    };
+    for (key, base_value) in base {
+        let merged = match (key.as_str(), layer.remove(key)) {
+            (_, None) => base_value.clone(),
+            ("limits", Some(layer_value)) => merge_limits(base_value, layer_value),
+            ("requirements" | "locked_time_ranges", Some(toml::Value::Array(items))) => {
+                let base_items = base_value.as_array().map(Vec::as_slice).unwrap_or_default();
+                toml::Value::Array(base_items.iter().cloned().chain(items).collect())
+            }
+            (_, Some(layer_value)) => merge_layers(base_value, layer_value),
+        };
+        layer.insert(key.clone(), merged);
+    }
+    toml::Value::Table(layer)
+}
+
+fn merge_limits(base: &toml::Value, layer: toml::Value) -> toml::Value {
+    let (Some(base), toml::Value::Table(mut layer)) = (base.as_table(), layer) else {
+        return base.clone();
+    };
+    for (key, base_value) in base {
+        let layer_value = layer.get(key).and_then(toml::Value::as_integer);
+        let merged = match (base_value.as_integer(), layer_value) {
+            (Some(base), Some(value)) if key.starts_with("max_") => base.min(value).into(),
+            (Some(base), Some(value)) if key.starts_with("min_") => base.max(value).into(),
+            _ => base_value.clone(),
+        };
+        layer.insert(key.clone(), merged);
+    }
+    toml::Value::Table(layer)
+}
+
+/// Adds the built-in timer presets that aren't overridden by a custom preset with the same name,
+/// and uses the durations of the selected preset as the work period and break durations.
+fn resolve_presets(config: &mut DiagonatorConfig) -> Result<(), LoadConfigError> {
+    let presets = config.presets.get_or_insert_with(Vec::new);
+    for builtin in builtin_presets() {
+        if !presets.iter().any(|preset| preset.name == builtin.name) {
+            presets.push(builtin);
+        }
+    }
+    if let Some(name) = &config.preset {
+        let Some(preset) = presets.iter().find(|preset| &preset.name == name) else {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "unknown timer preset '{}'",
+                name
+            )));
+        };
+        config.work_period_minutes = preset.work_period_minutes;
+        config.break_minutes = preset.break_minutes;
+    }
+    Ok(())
+}
+
+/// Clamps the work period and break durations, including those of the timer presets and
+/// profiles, to the configured limits.
+fn apply_limits(config: &mut DiagonatorConfig) {
+    let Some(limits) = &config.limits else {
+        return;
+    };
+    let crunch_mode = config.crunch_mode.as_mut();
+    let (crunch_work_period, crunch_break) = match crunch_mode {
+        Some(crunch) => (
+            crunch.work_period_minutes.as_mut(),
+            crunch.break_minutes.as_mut(),
+        ),
+        None => (None, None),
+    };
+    if let Some(max) = limits.max_work_period_minutes {
+        let preset_work_periods = config
+            .presets
+            .iter_mut()
+            .flatten()
+            .map(|preset| &mut preset.work_period_minutes);
+        let profile_work_periods = config
+            .profiles
+            .iter_mut()
+            .flatten()
+            .filter_map(|profile| profile.work_period_minutes.as_mut());
+        for minutes in std::iter::once(&mut config.work_period_minutes)
+            .chain(crunch_work_period)
+            .chain(preset_work_periods)
+            .chain(profile_work_periods)
+        {
+            if *minutes > max {
+                eprintln!(
+                    "Work period of {} minutes exceeds the limit, using {} minutes instead",
+                    minutes, max
+                );
+                *minutes = max;
+            }
+        }
+    }
+    if let Some(min) = limits.min_break_minutes {
+        let preset_breaks = config
+            .presets
+            .iter_mut()
+            .flatten()
+            .map(|preset| &mut preset.break_minutes);
+        let profile_breaks = config
+            .profiles
+            .iter_mut()
+            .flatten()
+            .filter_map(|profile| profile.break_minutes.as_mut());
+        for minutes in std::iter::once(&mut config.break_minutes)
+            .chain(config.long_break_minutes.as_mut())
+            .chain(crunch_break)
+            .chain(preset_breaks)
+            .chain(profile_breaks)
+        {
+            if *minutes < min {
+                eprintln!(
+                    "Break of {} minutes is below the limit, using {} minutes instead",
+                    minutes, min
+                );
+                *minutes = min;
+            }
+        }
+    }
+    if let Some(stopwatch) = &mut config.stopwatch {
+        if let Some(max) = limits.max_work_period_minutes {
+            stopwatch.max_work_period_minutes = Some(
+                stopwatch
+                    .max_work_period_minutes
+                    .map_or(max, |minutes| minutes.min(max)),
+            );
+        }
+        if let Some(min) = limits.min_break_minutes {
+            stopwatch.min_break_minutes = Some(
+                stopwatch
+                    .min_break_minutes
+                    .map_or(min, |minutes| minutes.max(min)),
+            );
+        }
+    }
+}
+
+impl DiagonatorConfig {
+    /// Every requirement in the configuration, including those of profiles, crunch mode, and day
+    /// overrides.
+    pub fn all_requirements(&self) -> impl Iterator<Item = &RequirementConfig> {
+        let profiles = self.profiles.iter().flatten();
+        let day_overrides = self.day_overrides.iter().flatten();
+        self.requirements
+            .iter()
+            .flatten()
+            .chain(profiles.flat_map(|profile| profile.requirements.iter().flatten()))
+            .chain(
+                self.crunch_mode
+                    .iter()
+                    .flat_map(|crunch| crunch.requirements.iter().flatten()),
+            )
+            .chain(day_overrides.flat_map(|day| day.requirements.iter().flatten()))
+    }
+}
+
+/// A patch that was merged into the configuration, but doesn't take effect until it is applied.
+pub struct PreparedPatch {
+    config: DiagonatorConfig,
+    layers: ConfigLayers,
+    value: toml::Value,
+    /// The configuration file and the patch to apply to it, if the patch is persisted
+    persist: Option<(PathBuf, serde_json::Map<String, serde_json::Value>)>,
+}
+
+impl PreparedPatch {
+    /// Applies the patch to the configuration file if it is persisted, keeping the file's
+    /// comments and formatting.
+    pub fn persist(&self) -> Result<(), LoadConfigError> {
+        let Some((path, patch)) = &self.persist else {
+            return Ok(());
+        };
+        let mut document: toml_edit::Document =
+            read_file(path)?.parse().map_err(toml::de::Error::from)?;
+        patch_document(document.as_table_mut(), patch)?;
+        write_file(path, &document.to_string())
+    }
+}
+
+/// The configuration that is currently in effect, in a form that settings can be merged into
+/// while the server is running.
+pub struct RunningConfig {
+    /// The layers that patches are applied to, where configurations that weren't loaded from a
+    /// file are a single layer
+    layers: ConfigLayers,
+    /// The configuration in effect, which patches are compared with
+    value: toml::Value,
+    path: Option<PathBuf>,
+}
+
+impl RunningConfig {
+    pub fn new(config: &DiagonatorConfig) -> Self {
+        let value = toml::Value::try_from(config).unwrap();
+        let layers = config.layers.clone().unwrap_or_else(|| ConfigLayers {
+            system: None,
+            user: value.clone(),
+            today: None,
+        });
+        Self {
+            layers,
+            value,
+            path: config.path.clone(),
+        }
+    }
+    /// Merges a patch into the user's layer of the configuration and checks the new
+    /// configuration, which is merged with the system-wide layer again. The patch is a JSON
+    /// object or a string with a TOML fragment. Tables are merged key by key, other values
+    /// replace the current ones, and `null` removes a setting. If `persist` is true, the patch is
+    /// also applied to the configuration file by `PreparedPatch::persist`. The patch only takes
+    /// effect once it is applied with `apply`.
+    pub fn prepare_patch(
+        &self,
+        patch: serde_json::Value,
+        persist: bool,
+    ) -> Result<PreparedPatch, LoadConfigError> {
+        let patch = match patch {
+            serde_json::Value::String(fragment) => {
+                let fragment: toml::Value = toml::from_str(&fragment)?;
+                serde_json::to_value(fragment)
+                    .map_err(|err| LoadConfigError::InvalidConfig(err.to_string()))?
+            }
+            patch => patch,
+        };
+        let serde_json::Value::Object(patch) = patch else {
+            return Err(LoadConfigError::InvalidConfig(
+                "the patch must be an object or a string with a TOML fragment".to_owned(),
+            ));
+        };
+        let mut layers = self.layers.clone();
+        if let Some(table) = layers.user.as_table_mut() {
+            patch_table(table, &patch)?;
+        }
+        let mut config = layers.config()?;
+        let value = toml::Value::try_from(&config).unwrap();
+        if self.value.get("config_loosening_delay_hours").is_some()
+            && !is_at_least_as_strict(&self.value, &value)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "the patch loosens restrictions, which config_loosening_delay_hours only allows by editing the configuration file".to_owned(),
+            ));
+        }
+        let persist =
+            match (persist, &self.path) {
+                (false, _) => None,
+                (true, Some(path)) => Some((path.clone(), patch)),
+                (true, None) => return Err(LoadConfigError::InvalidConfig(
+                    "the configuration wasn't loaded from a file, so the patch can't be persisted"
+                        .to_owned(),
+                )),
+            };
+        config.path = self.path.clone();
+        Ok(PreparedPatch {
+            config,
+            layers,
+            value,
+            persist,
+        })
+    }
+    /// Puts a prepared patch into effect and returns the new configuration.
+    pub fn apply(&mut self, patch: PreparedPatch) -> DiagonatorConfig {
+        self.layers = patch.layers;
+        self.value = patch.value;
+        patch.config
+    }
+    /// Returns whether switching from the given durations to the timer preset `name` keeps the
+    /// configuration at least as strict, checked the same way as a patch that changes the
+    /// durations. Unknown presets are left for `SetPreset` to refuse.
+    pub fn allows_preset(&self, name: &str, work_period_minutes: i64, break_minutes: i64) -> bool {
+        if self.value.get("config_loosening_delay_hours").is_none() {
+            return true;
+        }
+        let Some(table) = self.value.as_table() else {
+            return true;
+        };
+        let Some(preset) = array_items(table, "presets")
+            .iter()
+            .find(|preset| preset.get("name").and_then(toml::Value::as_str) == Some(name))
+        else {
+            return true;
+        };
+        let with_durations = |work_period_minutes: toml::Value, break_minutes: toml::Value| {
+            let mut table = table.clone();
+            table.insert("work_period_minutes".to_owned(), work_period_minutes);
+            table.insert("break_minutes".to_owned(), break_minutes);
+            toml::Value::Table(table)
+        };
+        let (Some(preset_work_period_minutes), Some(preset_break_minutes)) = (
+            preset.get("work_period_minutes"),
+            preset.get("break_minutes"),
+        ) else {
+            return true;
+        };
+        is_at_least_as_strict(
+            &with_durations(work_period_minutes.into(), break_minutes.into()),
+            &with_durations(
+                preset_work_period_minutes.clone(),
+                preset_break_minutes.clone(),
+            ),
+        )
+    }
+}
+
+fn patch_table(
+    table: &mut toml::value::Table,
+    patch: &serde_json::Map<String, serde_json::Value>,
+) -> Result<(), LoadConfigError> {
+    for (key, value) in patch {
+        if value.is_null() {
+            table.remove(key);
+            continue;
+        }
+        if let serde_json::Value::Object(fields) = value {
+            // tables that don't exist yet are patched as empty ones, so that their nulls are
+            // dropped instead of being converted
+            if !matches!(table.get(key), Some(toml::Value::Table(_))) {
+                table.insert(key.clone(), toml::Value::Table(toml::value::Table::new()));
+            }
+            if let Some(toml::Value::Table(existing)) = table.get_mut(key) {
+                patch_table(existing, fields)?;
+            }
+            continue;
+        }
+        let value = toml::Value::try_from(value)
+            .map_err(|err| LoadConfigError::InvalidConfig(format!("{}: {}", key, err)))?;
+        table.insert(key.clone(), value);
+    }
+    Ok(())
+}
+
+/// Applies a patch to a configuration file's document the same way as `patch_table`, leaving the
+/// rest of the document untouched.
+fn patch_document(
+    table: &mut dyn toml_edit::TableLike,
+    patch: &serde_json::Map<String, serde_json::Value>,
+) -> Result<(), LoadConfigError> {
+    for (key, value) in patch {
+        if value.is_null() {
+            table.remove(key);
+            continue;
+        }
+        if let serde_json::Value::Object(fields) = value {
+            if !table.get(key).is_some_and(toml_edit::Item::is_table_like) {
+                table.insert(key, toml_edit::Item::Table(toml_edit::Table::new()));
+            }
+            if let Some(existing) = table
+                .get_mut(key)
+                .and_then(toml_edit::Item::as_table_like_mut)
+            {
+                patch_document(existing, fields)?;
+            }
+            continue;
+        }
+        let mut document = toml::to_document(&serde_json::json!({ "value": value }))?;
+        if let Some(item) = document.remove("value") {
+            table.insert(key, item);
+        }
+    }
+    Ok(())
+}
+
+fn read_file(path: &Path) -> Result<String, LoadConfigError> {
+    fs::read_to_string(path).map_err(|err| LoadConfigError::ReadError(path.to_owned(), err))
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), LoadConfigError> {
+    fs::write(path, contents).map_err(|err| LoadConfigError::WriteError(path.to_owned(), err))
+}
+
+/// Decides which configuration takes effect. The configuration currently in effect is kept in
+/// `applied-config.toml`. If it sets `config_loosening_delay_hours`, changes that make the
+/// configuration less strict are kept in `pending-config.toml` and only take effect once that
+/// many hours have passed since they were first loaded. Changes that make the configuration
+/// stricter take effect immediately.
+fn delay_loosening_changes(config_dir: &Path, contents: String) -> Result<String, LoadConfigError> {
+    let applied_path = config_dir.join("applied-config.toml");
+    let pending_path = config_dir.join("pending-config.toml");
+    let apply = |contents: String| -> Result<String, LoadConfigError> {
+        write_file(&applied_path, &contents)?;
+        if pending_path.exists() {
+            fs::remove_file(&pending_path)
+                .map_err(|err| LoadConfigError::WriteError(pending_path.clone(), err))?;
+        }
+        Ok(contents)
+    };
+    if !applied_path.exists() {
+        return apply(contents);
+    }
+    let applied_contents = read_file(&applied_path)?;
+    let applied: toml::Value = toml::from_str(&applied_contents)?;
+    let new: toml::Value = toml::from_str(&contents)?;
+    let Some(delay_hours) = applied
+        .get("config_loosening_delay_hours")
+        .and_then(toml::Value::as_integer)
+    else {
+        return apply(contents);
+    };
+    if is_at_least_as_strict(&applied, &new) {
+        return apply(contents);
+    }
+    let pending_since = if pending_path.exists() && read_file(&pending_path)? == contents {
+        fs::metadata(&pending_path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|err| LoadConfigError::ReadError(pending_path.clone(), err))?
+    } else {
+        write_file(&pending_path, &contents)?;
+        SystemTime::now()
+    };
+    // a delay too long to represent never passes
+    let takes_effect = (delay_hours.max(0) as u64)
+        .checked_mul(3600)
+        .and_then(|seconds| pending_since.checked_add(std::time::Duration::from_secs(seconds)));
+    if takes_effect.is_some_and(|takes_effect| SystemTime::now() >= takes_effect) {
+        eprintln!("Applying configuration change that loosens restrictions");
+        apply(contents)
+    } else {
+        eprintln!(
+            "Configuration change loosens restrictions, so it will only take effect when the server is started {} hours after the change was first loaded. Using the previous configuration until then.",
+            delay_hours
+        );
+        Ok(applied_contents)
+    }
+}
+
+fn array_items<'a>(table: &'a toml::value::Table, key: &str) -> &'a [toml::Value] {
+    table
+        .get(key)
+        .and_then(toml::Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+/// Returns whether the `new` configuration is at least as strict as the `old` one: requirements
+/// and locked time ranges may only be added, work periods may only get shorter, breaks and the
+/// loosening delay may only get longer, refusal messages may change freely, and every other
+/// setting must be unchanged.
+fn is_at_least_as_strict(old: &toml::Value, new: &toml::Value) -> bool {
+    let (Some(old), Some(new)) = (old.as_table(), new.as_table()) else {
+        return false;
+    };
+    let integer = |table: &toml::value::Table, key: &str| {
+        table
+            .get(key)
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(0)
+    };
+    old.keys().chain(new.keys()).all(|key| match key.as_str() {
+        "work_period_minutes" => integer(new, key) <= integer(old, key),
+        "break_minutes" | "config_loosening_delay_hours" => integer(new, key) >= integer(old, key),
+        "planning_gate" => {
+            new.get(key).and_then(toml::Value::as_bool).unwrap_or(false)
+                || !old.get(key).and_then(toml::Value::as_bool).unwrap_or(false)
+        }
+        "refusal_messages" => true,
+        "requirements" | "locked_time_ranges" => {
+            let new_items = array_items(new, key);
+            array_items(old, key)
+                .iter()
+                .all(|item| new_items.contains(item))
+        }
+        _ => old.get(key) == new.get(key),
+    })
+}
+
+/// Checks the activity and count goals of a requirement or an alternative, which is described
+/// by `what` in error messages.
+fn validate_goal(
+    what: &str,
+    activity: &Option<String>,
+    activity_minutes: Option<i64>,
+    count: Option<u64>,
+) -> Result<(), LoadConfigError> {
+    if activity.is_some() != activity_minutes.is_some() {
+        return Err(LoadConfigError::InvalidConfig(format!(
+            "{} needs both an activity and activity_minutes",
+            what
+        )));
+    }
+    if count == Some(0) || (count.is_some() && activity.is_some()) {
+        return Err(LoadConfigError::InvalidConfig(format!(
+            "{} needs a positive count and can't also have an activity",
+            what
+        )));
+    }
+    Ok(())
+}
+
+fn validate_config(config: &DiagonatorConfig) -> Result<(), LoadConfigError> {
+    let template_ranges = config.weekly_template.iter().flat_map(|template| {
+        WEEKDAYS
+            .iter()
+            .flat_map(|weekday| template.for_weekday(*weekday))
+    });
+    let crunch_ranges = config
+        .crunch_mode
+        .iter()
+        .flat_map(|crunch| crunch.locked_time_ranges.iter().flatten());
+    let override_ranges = config
+        .day_overrides
+        .iter()
+        .flatten()
+        .flat_map(|day_override| day_override.locked_time_ranges.iter().flatten());
+    let profile_ranges = config
+        .profiles
+        .iter()
+        .flatten()
+        .flat_map(|profile| profile.locked_time_ranges.iter().flatten());
+    let uses_sun_times = config
+        .locked_time_ranges
+        .iter()
+        .flatten()
+        .chain(template_ranges)
+        .chain(crunch_ranges)
+        .chain(override_ranges)
+        .chain(profile_ranges)
+        .any(|ltr| {
+            ltr.start
+                .iter()
+                .chain(ltr.end.iter())
+                .any(TimeOfDay::is_sun_relative)
+        });
+    if let Some(location) = &config.location {
+        if !(-90.0..=90.0).contains(&location.latitude)
+            || !(-180.0..=180.0).contains(&location.longitude)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "the location's latitude must be between -90 and 90, and its longitude between -180 and 180".to_owned(),
+            ));
+        }
+    }
+    if uses_sun_times && config.location.is_none() {
+        return Err(LoadConfigError::InvalidConfig(
+            "locked time ranges relative to sunrise/sunset require a location".to_owned(),
+        ));
+    }
+    if config
+        .stopwatch
+        .as_ref()
+        .is_some_and(|stopwatch| stopwatch.work_minutes_per_break_minute <= 0)
+    {
+        return Err(LoadConfigError::InvalidConfig(
+            "work_minutes_per_break_minute must be positive".to_owned(),
+        ));
+    }
+    match (config.long_break_minutes, config.periods_per_long_break) {
+        (None, None) => {}
+        (Some(minutes), Some(periods)) if minutes > 0 && periods > 0 => {}
+        _ => {
+            return Err(LoadConfigError::InvalidConfig(
+                "long_break_minutes and periods_per_long_break must be set together and be positive"
+                    .to_owned(),
+            ));
+        }
+    }
+    if let Some(rest_debt) = &config.rest_debt {
+        if rest_debt.max_minutes <= 0
+            || rest_debt
+                .max_extension_minutes
+                .is_some_and(|minutes| minutes <= 0)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "the max_minutes and max_extension_minutes of rest_debt must be positive"
+                    .to_owned(),
+            ));
+        }
+    }
+    if config
+        .break_credit
+        .as_ref()
+        .is_some_and(|break_credit| break_credit.max_minutes <= 0)
+    {
+        return Err(LoadConfigError::InvalidConfig(
+            "the max_minutes of break_credit must be positive".to_owned(),
+        ));
+    }
+    if let Some(focus_score) = &config.focus_score {
+        if focus_score
+            .focus_goal_minutes
+            .is_some_and(|minutes| minutes <= 0)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "focus_goal_minutes must be positive".to_owned(),
+            ));
+        }
+        if focus_score.focus_weight == Some(0)
+            && focus_score.break_compliance_weight == Some(0)
+            && focus_score.on_time_weight == Some(0)
+            && focus_score.deactivation_weight == Some(0)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "at least one of the focus_score weights must be positive".to_owned(),
+            ));
+        }
+    }
+    if config
+        .config_loosening_delay_hours
+        .is_some_and(|hours| !(0..=MAX_LOOSENING_DELAY_HOURS).contains(&hours))
+    {
+        return Err(LoadConfigError::InvalidConfig(format!(
+            "config_loosening_delay_hours must be between 0 and {}",
+            MAX_LOOSENING_DELAY_HOURS
+        )));
+    }
+    let groups = config.requirement_groups.as_deref().unwrap_or_default();
+    for (i, group) in groups.iter().enumerate() {
+        if groups[..i].iter().any(|other| other.name == group.name) {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "requirement group '{}' is defined more than once",
+                group.name
+            )));
+        }
+    }
+    for req in config.all_requirements() {
+        if let Some(group) = req
+            .group
+            .as_ref()
+            .filter(|group| !groups.iter().any(|other| other.name == **group))
+        {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "requirement '{}' belongs to the unknown requirement group '{}'",
+                req.name, group
+            )));
+        }
+        validate_goal(
+            &format!("requirement '{}'", req.name),
+            &req.activity,
+            req.activity_minutes,
+            req.count,
+        )?;
+        if let Some(alternatives) = &req.alternatives {
+            if alternatives.is_empty() {
+                return Err(LoadConfigError::InvalidConfig(format!(
+                    "requirement '{}' needs at least one alternative",
+                    req.name
+                )));
+            }
+            if req.activity.is_some() || req.count.is_some() {
+                return Err(LoadConfigError::InvalidConfig(format!(
+                    "requirement '{}' has alternatives, so it can't also have an activity or a count",
+                    req.name
+                )));
+            }
+            for (i, alternative) in alternatives.iter().enumerate() {
+                if alternatives[..i]
+                    .iter()
+                    .any(|other| other.name == alternative.name)
+                {
+                    return Err(LoadConfigError::InvalidConfig(format!(
+                        "requirement '{}' has more than one alternative named '{}'",
+                        req.name, alternative.name
+                    )));
+                }
+                validate_goal(
+                    &format!("alternative '{}'", alternative.name),
+                    &alternative.activity,
+                    alternative.activity_minutes,
+                    alternative.count,
+                )?;
+            }
+        }
+        if req
+            .expires
+            .as_ref()
+            .is_some_and(|expires| *expires <= req.due)
+        {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "requirement '{}' must expire after it is due",
+                req.name
+            )));
+        }
+        if req.estimate_minutes.is_some_and(|minutes| minutes <= 0) {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "requirement '{}' needs a positive estimate_minutes",
+                req.name
+            )));
+        }
+    }
+    for rule in config.profile_rules.iter().flatten() {
+        if rule.ssid.is_none() && rule.location.is_none() {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "the rule for profile '{}' needs an ssid or a location",
+                rule.profile
+            )));
+        }
+        if !config
+            .profiles
+            .iter()
+            .flatten()
+            .any(|profile| profile.name == rule.profile)
+        {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "unknown profile '{}'",
+                rule.profile
+            )));
+        }
+    }
+    if config.keep_alive_seconds == Some(0) {
+        return Err(LoadConfigError::InvalidConfig(
+            "keep_alive_seconds must be positive".to_owned(),
+        ));
+    }
+    if config.max_connections == Some(0) || config.idle_timeout_seconds == Some(0) {
+        return Err(LoadConfigError::InvalidConfig(
+            "max_connections and idle_timeout_seconds must be positive".to_owned(),
+        ));
+    }
+    for rule in config.profile_schedule.iter().flatten() {
+        validate_profile_schedule(config, rule)?;
+    }
+    if let Some(digest) = &config.email_digest {
+        for address in [&digest.from, &digest.to] {
+            if address.parse::<lettre::message::Mailbox>().is_err() {
+                return Err(LoadConfigError::InvalidConfig(format!(
+                    "invalid email address '{}'",
+                    address
+                )));
+            }
+        }
+    }
+    if let Some(event_log) = &config.event_log {
+        if event_log.max_size_kb == Some(0) || event_log.max_age_days.is_some_and(|days| days <= 0)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "the event log's max_size_kb and max_age_days must be positive".to_owned(),
+            ));
+        }
+        // the log compares them in bytes and seconds
+        if event_log
+            .max_size_kb
+            .is_some_and(|kb| kb.checked_mul(1024).is_none())
+            || event_log
+                .max_age_days
+                .is_some_and(|days| days.checked_mul(24 * 60 * 60).is_none())
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "the event log's max_size_kb or max_age_days is too large".to_owned(),
+            ));
+        }
+    }
+    if let Some(crunch) = &config.crunch_mode {
+        // crunch mode is meant to tighten the schedule, so it can't be used to get out of it
+        if crunch
+            .work_period_minutes
+            .is_some_and(|minutes| minutes > config.work_period_minutes)
+            || crunch
+                .break_minutes
+                .is_some_and(|minutes| minutes < config.break_minutes)
+        {
+            return Err(LoadConfigError::InvalidConfig(
+                "crunch mode can't have longer work periods or shorter breaks than the base configuration".to_owned(),
+            ));
+        }
+    }
+    if config.require_intent.unwrap_or(false)
+        && (config.auto_unlock_after_break.unwrap_or(false) || config.auto_unlock_at.is_some())
+    {
+        return Err(LoadConfigError::InvalidConfig(
+            "require_intent can't be combined with auto_unlock_after_break or auto_unlock_at, because only an UnlockTimer request can bring an intent".to_owned(),
+        ));
+    }
+    if cfg!(not(target_os = "macos")) && config.idle_detection.is_some() {
+        return Err(LoadConfigError::InvalidConfig(
+            "idle_detection is only supported on macOS, use the report_activity.py client on other platforms".to_owned(),
+        ));
+    }
+    if cfg!(not(feature = "telegram")) && config.telegram.is_some() {
+        return Err(LoadConfigError::InvalidConfig(
+            "the Telegram bot is configured, but this build doesn't include the telegram feature"
+                .to_owned(),
+        ));
+    }
+    validate_bind_on(&config.bind_on, config.tls.is_some())?;
+    for listener in config.listeners.iter().flatten() {
+        let tls = listener.tls.unwrap_or(false);
+        if tls && config.tls.is_none() {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "the listener on '{}' uses TLS, but there is no tls table",
+                listener.bind_on
+            )));
+        }
+        if listener.roles.as_ref().is_some_and(Vec::is_empty) {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "the listener on '{}' doesn't allow any roles",
+                listener.bind_on
+            )));
+        }
+        validate_bind_on(&listener.bind_on, tls)?;
+    }
+    if config
+        .display
+        .as_ref()
+        .is_some_and(|display| display.bind_on.is_none() && display.broadcast_to.is_none())
+    {
+        return Err(LoadConfigError::InvalidConfig(
+            "the display needs a bind_on address or a broadcast_to address".to_owned(),
+        ));
+    }
+    for day_override in config.day_overrides.iter().flatten() {
+        validate_day_override(day_override)?;
+    }
+    if let Some(template) = &config.weekly_template {
+        for weekday in WEEKDAYS {
+            validate_weekly_blocks(weekday, template.for_weekday(weekday))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the index of the socket that `bind_on` refers to if it is `systemd` (the first socket)
+/// or `systemd:<index>`, i.e. one of the sockets that systemd passes to the server when it is
+/// socket-activated.
+pub fn systemd_socket_index(bind_on: &str) -> Option<Result<usize, String>> {
+    if bind_on == "systemd" {
+        return Some(Ok(0));
+    }
+    let index = bind_on.strip_prefix("systemd:")?;
+    Some(
+        index
+            .parse()
+            .map_err(|_| format!("invalid systemd socket index '{}'", index)),
+    )
+}
+
+fn validate_bind_on(bind_on: &str, tls: bool) -> Result<(), LoadConfigError> {
+    if let Some(index) = systemd_socket_index(bind_on) {
+        if cfg!(not(unix)) {
+            return Err(LoadConfigError::InvalidConfig(
+                "systemd socket activation is only supported on Unix".to_owned(),
+            ));
+        }
+        index.map_err(LoadConfigError::InvalidConfig)?;
+    }
+    if cfg!(not(unix)) && bind_on.starts_with("unix:") {
+        return Err(LoadConfigError::InvalidConfig(
+            "Unix domain sockets are only supported on Unix".to_owned(),
+        ));
+    }
+    if cfg!(not(windows)) && bind_on.starts_with("pipe:") {
+        return Err(LoadConfigError::InvalidConfig(
+            "named pipes are only supported on Windows".to_owned(),
+        ));
+    }
+    if tls && (bind_on.starts_with("unix:") || bind_on.starts_with("pipe:")) {
+        return Err(LoadConfigError::InvalidConfig(
+            "TLS is only supported when binding on a TCP address".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_day_override(day_override: &DayOverrideConfig) -> Result<(), LoadConfigError> {
+    if parse_date(&day_override.date).is_none() {
+        return Err(LoadConfigError::InvalidConfig(format!(
+            "invalid day override date '{}', expected YYYY-MM-DD",
+            day_override.date
+        )));
+    }
+    Ok(())
+}
+
+fn validate_profile_schedule(
+    config: &DiagonatorConfig,
+    rule: &ProfileScheduleConfig,
+) -> Result<(), LoadConfigError> {
+    if !config
+        .profiles
+        .iter()
+        .flatten()
+        .any(|profile| profile.name == rule.profile)
+    {
+        return Err(LoadConfigError::InvalidConfig(format!(
+            "unknown profile '{}'",
+            rule.profile
+        )));
+    }
+    if let Some(weekday) = rule
+        .weekdays
+        .iter()
+        .flatten()
+        .find(|weekday| weekday.parse::<Weekday>().is_err())
+    {
+        return Err(LoadConfigError::InvalidConfig(format!(
+            "invalid weekday '{}' in the schedule of profile '{}'",
+            weekday, rule.profile
+        )));
+    }
+    let parse = |date: &String| {
+        parse_date(date).ok_or_else(|| {
+            LoadConfigError::InvalidConfig(format!(
+                "invalid date '{}' in the schedule of profile '{}', expected YYYY-MM-DD",
+                date, rule.profile
+            ))
+        })
+    };
+    let from = rule.from.as_ref().map(parse).transpose()?;
+    let to = rule.to.as_ref().map(parse).transpose()?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if to < from {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "the schedule of profile '{}' ends before it starts",
+                rule.profile
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Ten years, which is longer than any commitment needs to last
+const MAX_LOOSENING_DELAY_HOURS: i64 = 10 * 366 * 24;
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// Checks that the clock-time blocks of a weekday in the weekly template don't overlap.
+/// Blocks relative to sunrise/sunset move from day to day, so they are not checked.
+fn validate_weekly_blocks(
+    weekday: Weekday,
+    blocks: &[LockedTimeRangeConfig],
+) -> Result<(), LoadConfigError> {
+    const START_OF_DAY: HourMinute = HourMinute::START_OF_DAY;
+    let mut clock_blocks = Vec::new();
+    for block in blocks {
+        let start = match &block.start {
+            Some(TimeOfDay::Clock(hm)) => hm,
+            Some(TimeOfDay::Sun { .. }) => continue,
+            None => &START_OF_DAY,
+        };
+        let end = match &block.end {
+            Some(TimeOfDay::Clock(hm)) => Some(hm),
+            Some(TimeOfDay::Sun { .. }) => continue,
+            None => None,
+        };
+        if end.is_some_and(|end| end <= start) {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "block starting at {} on {} in the weekly template does not end after it starts",
+                start, weekday
+            )));
+        }
+        clock_blocks.push((start, end));
+    }
+    clock_blocks.sort();
+    for pair in clock_blocks.windows(2) {
+        let (_, end) = pair[0];
+        let (next_start, _) = pair[1];
+        if end.is_none_or(|end| end > next_start) {
+            return Err(LoadConfigError::InvalidConfig(format!(
+                "overlapping blocks on {} in the weekly template",
+                weekday
+            )));
+        }
+    }
+    Ok(())
+}
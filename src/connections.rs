@@ -0,0 +1,118 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Limits on the connections that the server accepts, so that forgotten clients or a flood of
+/// connections don't exhaust the resources of a long-running server.
+#[derive(Clone)]
+pub struct ConnectionLimits {
+    slots: Option<Arc<Semaphore>>,
+    idle_timeout: Option<Duration>,
+}
+
+/// Keeps a connection counted towards `max_connections` until it is dropped.
+pub struct ConnectionSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionLimits {
+    pub fn new(max_connections: Option<usize>, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            slots: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            idle_timeout,
+        }
+    }
+    /// Reserves a slot for a new connection, or returns `None` if `max_connections`
+    /// connections are already open.
+    pub fn admit(&self) -> Option<ConnectionSlot> {
+        let permit = match &self.slots {
+            Some(slots) => Some(slots.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some(ConnectionSlot { _permit: permit })
+    }
+    /// Serves the connection until it is finished or, if there is an idle timeout, until nothing
+    /// was read from or written to it for that long. Returns whether it was closed for being
+    /// idle. Connections that are upgraded, e.g. to a WebSocket, are finished as far as this is
+    /// concerned, since they check the client's liveness themselves, but they keep their slot
+    /// until the upgraded connection is closed, because the slot is owned by the stream.
+    pub async fn serve<S, F, Fut>(&self, stream: S, slot: ConnectionSlot, serve: F) -> bool
+    where
+        F: FnOnce(IdleStream<S>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let last_activity = Arc::new(Mutex::new(Instant::now()));
+        let stream = IdleStream {
+            inner: stream,
+            last_activity: last_activity.clone(),
+            _slot: slot,
+        };
+        let Some(idle_timeout) = self.idle_timeout else {
+            serve(stream).await;
+            return false;
+        };
+        let idle = async {
+            loop {
+                let deadline = *last_activity.lock().unwrap() + idle_timeout;
+                if Instant::now() >= deadline {
+                    break;
+                }
+                tokio::time::sleep_until(deadline).await;
+            }
+        };
+        tokio::select! {
+            _ = serve(stream) => false,
+            _ = idle => true,
+        }
+    }
+}
+
+/// A stream that records when it was last read from or written to.
+pub struct IdleStream<S> {
+    inner: S,
+    last_activity: Arc<Mutex<Instant>>,
+    _slot: ConnectionSlot,
+}
+
+impl<S> IdleStream<S> {
+    fn record_activity<T>(&self, poll: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if poll.is_ready() {
+            *self.last_activity.lock().unwrap() = Instant::now();
+        }
+        poll
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for IdleStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.record_activity(poll)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for IdleStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.record_activity(poll)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::manager::CurrentInfo;
+use crate::server::{Request, Response};
+use crate::time::{Duration, HourMinute};
+use futures_util::future::BoxFuture;
+use tokio::sync::watch;
+use zbus::fdo;
+use zbus::object_server::SignalEmitter;
+
+const BUS_NAME: &str = "org.diagonator.Server";
+const OBJECT_PATH: &str = "/org/diagonator/Server";
+
+type Handler = Box<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>;
+
+/// The `org.diagonator.Server` interface, with a method for each common request and a `Call`
+/// method for any other request of the socket protocol, sent as JSON.
+struct DbusServer {
+    handle: Handler,
+}
+
+impl DbusServer {
+    async fn call(&self, request: Request) -> fdo::Result<Response> {
+        match (self.handle)(request).await {
+            Response::Error { msg, .. } | Response::InternalError { msg } => {
+                Err(fdo::Error::Failed(msg))
+            }
+            Response::QuotaExceeded { msg, .. } => Err(fdo::Error::LimitsExceeded(msg)),
+            response => Ok(response),
+        }
+    }
+}
+
+#[zbus::interface(name = "org.diagonator.Server")]
+impl DbusServer {
+    /// Returns the current information as JSON, in the same format as `GetInfo`.
+    async fn get_info(&self) -> fdo::Result<String> {
+        match self.call(Request::GetInfo).await? {
+            Response::Info { info } => Ok(serde_json::to_string(&info).unwrap()),
+            _ => Err(fdo::Error::Failed("Unexpected response.".to_owned())),
+        }
+    }
+    /// An empty intent is the same as not specifying one.
+    async fn unlock_timer(&self, intent: String) -> fdo::Result<()> {
+        let intent = (!intent.is_empty()).then_some(intent);
+        self.call(Request::UnlockTimer { intent }).await?;
+        Ok(())
+    }
+    async fn lock_timer(&self) -> fdo::Result<()> {
+        self.call(Request::LockTimer).await?;
+        Ok(())
+    }
+    async fn complete_requirement(&self, id: u64) -> fdo::Result<()> {
+        self.call(Request::CompleteRequirement { id, answers: None })
+            .await?;
+        Ok(())
+    }
+    async fn complete_requirement_by_name(&self, name: String) -> fdo::Result<()> {
+        self.call(Request::CompleteRequirementByName {
+            name,
+            answers: None,
+        })
+        .await?;
+        Ok(())
+    }
+    async fn increment_requirement(&self, name: String) -> fdo::Result<()> {
+        self.call(Request::IncrementRequirement { name }).await?;
+        Ok(())
+    }
+    /// `due` is a time of day in HH:MM format.
+    async fn add_requirement(&self, name: String, due: String) -> fdo::Result<()> {
+        let due: HourMinute = serde_json::from_value(serde_json::Value::String(due))
+            .map_err(|err| fdo::Error::InvalidArgs(err.to_string()))?;
+        self.call(Request::AddRequirement { name, due }).await?;
+        Ok(())
+    }
+    /// Returns the ID of the pending deactivation if it needs to be approved by a partner first,
+    /// and 0 otherwise.
+    async fn deactivate(&self, seconds: i64) -> fdo::Result<u64> {
+        let duration = Duration::from_seconds(seconds);
+        match self.call(Request::Deactivate { duration }).await? {
+            Response::Pending { id } => Ok(id),
+            _ => Ok(0),
+        }
+    }
+    async fn start_focus(&self, label: String) -> fdo::Result<()> {
+        self.call(Request::StartFocus { label }).await?;
+        Ok(())
+    }
+    async fn report_activity(&self) -> fdo::Result<()> {
+        self.call(Request::ReportActivity).await?;
+        Ok(())
+    }
+    async fn begin_activity(&self, name: String) -> fdo::Result<()> {
+        self.call(Request::BeginActivity { name }).await?;
+        Ok(())
+    }
+    async fn end_activity(&self) -> fdo::Result<()> {
+        self.call(Request::EndActivity).await?;
+        Ok(())
+    }
+    /// Sends any request of the socket protocol as JSON, e.g. `{"type": "GetStats"}`, and
+    /// returns its response as JSON.
+    #[zbus(name = "Call")]
+    async fn call_json(&self, request: String) -> fdo::Result<String> {
+        let request: Request = serde_json::from_str(&request)
+            .map_err(|err| fdo::Error::InvalidArgs(err.to_string()))?;
+        let response = self.call(request).await?;
+        Ok(serde_json::to_string(&response).unwrap())
+    }
+    /// Sent whenever the information changes, with the current state (e.g. `"Locked"`), the
+    /// time when it ends (0 if it doesn't), and the information as JSON.
+    #[zbus(signal)]
+    async fn state_changed(
+        emitter: &SignalEmitter<'_>,
+        state: &str,
+        until: i64,
+        info: &str,
+    ) -> zbus::Result<()>;
+}
+
+async fn serve(
+    handle: Handler,
+    mut info_updates: watch::Receiver<(CurrentInfo, u64)>,
+) -> zbus::Result<()> {
+    let connection = zbus::connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, DbusServer { handle })?
+        .build()
+        .await?;
+    eprintln!("Serving {} on the D-Bus session bus", BUS_NAME);
+    let emitter = SignalEmitter::new(&connection, OBJECT_PATH)?;
+    while info_updates.changed().await.is_ok() {
+        let (info, _) = info_updates.borrow_and_update().clone();
+        let state = serde_json::to_value(info.state()).unwrap();
+        let until = info.until().map_or(0, |until| until.as_seconds());
+        let info = serde_json::to_string(&info).unwrap();
+        DbusServer::state_changed(&emitter, state.as_str().unwrap_or_default(), until, &info)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Exposes the server as `org.diagonator.Server` on the D-Bus session bus, so that desktop
+/// applets and `busctl` can use it without a token.
+pub async fn run_dbus_service(
+    handle: impl Fn(Request) -> BoxFuture<'static, Response> + Send + Sync + 'static,
+    info_updates: watch::Receiver<(CurrentInfo, u64)>,
+) {
+    if let Err(err) = serve(Box::new(handle), info_updates).await {
+        eprintln!("Received error '{}' from the D-Bus service", err);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::config::DisplayConfig;
+use crate::manager::{CurrentInfo, CurrentState, CurrentStateReason, Pressure};
+use crate::time::Timestamp;
+use serde::{Deserialize, Serialize};
+use tokio::net::UdpSocket;
+
+const FRAME_VERSION: u8 = 1;
+const NO_DEADLINE: u32 = u32::MAX;
+const DEFAULT_BROADCAST_INTERVAL_SECONDS: u64 = 5;
+
+/// Encodes the current state into a fixed-size 8-byte frame for microcontroller displays:
+///
+/// | bytes | content                                                        |
+/// |-------|----------------------------------------------------------------|
+/// | 0     | frame version (1)                                              |
+/// | 1     | state: 0 = unlocked, 1 = locked, 2 = unlockable                |
+/// | 2-5   | seconds until the state ends (big-endian, `0xFFFFFFFF` if never) |
+/// | 6-7   | number of incomplete requirements (big-endian)                 |
+fn encode_frame(info: &CurrentInfo, current_time: Timestamp) -> [u8; 8] {
+    let state = match info.state() {
+        CurrentState::Unlocked => 0,
+        CurrentState::Locked => 1,
+        CurrentState::Unlockable => 2,
+    };
+    let remaining = info.until().map_or(NO_DEADLINE, |until| {
+        (until - current_time)
+            .as_seconds()
+            .clamp(0, i64::from(NO_DEADLINE - 1)) as u32
+    });
+    let incomplete = info
+        .requirements()
+        .iter()
+        .filter(|req| !req.is_complete())
+        .count()
+        .min(u16::MAX.into()) as u16;
+    let mut frame = [0; 8];
+    frame[0] = FRAME_VERSION;
+    frame[1] = state;
+    frame[2..6].copy_from_slice(&remaining.to_be_bytes());
+    frame[6..8].copy_from_slice(&incomplete.to_be_bytes());
+    frame
+}
+
+/// The color that a kiosk client should fill its screen with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayColor {
+    /// Unlocked, with no lock coming up soon
+    Green,
+    /// Unlocked, but the screen will lock within `pressure_minutes`
+    Yellow,
+    /// Locked
+    Red,
+    /// The break is over, so the timer can be unlocked
+    Blue,
+    /// Deactivated
+    Gray,
+}
+
+/// A presentation of the current info for TVs, tablets, and other kiosk clients, which can show
+/// it as is without any logic of their own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DisplayInfo {
+    /// A few words to show in large text, e.g. "Break" or "Complete Exercise"
+    headline: String,
+    color: DisplayColor,
+    /// The fraction of the current state that has passed, from 0 to 1, if it ends at a known time
+    progress: Option<f64>,
+    /// What happens next, e.g. "Locks at 14:30"
+    next_event: Option<String>,
+}
+
+impl DisplayInfo {
+    pub fn new(info: &CurrentInfo, state_since: Timestamp, current_time: Timestamp) -> Self {
+        let requirement_name = |id: &u64| {
+            info.requirements()
+                .iter()
+                .find(|req| req.id() == *id)
+                .map_or_else(
+                    || format!("requirement {}", id),
+                    |req| req.name().to_owned(),
+                )
+        };
+        let at = |time: Timestamp| time.format_local("%H:%M");
+        let (headline, color, next_event) = match (info.state(), info.deactivated_until()) {
+            (_, Some(until)) => (
+                "Deactivated".to_owned(),
+                DisplayColor::Gray,
+                Some(format!("Reactivates at {}", at(until))),
+            ),
+            (CurrentState::Unlocked, None) => (
+                match (info.focus_label(), info.reason()) {
+                    (Some(label), _) => format!("Focus: {}", label),
+                    (None, CurrentStateReason::NoConstraints) => "Free".to_owned(),
+                    (None, _) => "Focus".to_owned(),
+                },
+                match info.pressure() {
+                    Pressure::Approaching => DisplayColor::Yellow,
+                    Pressure::Relaxed | Pressure::Locked => DisplayColor::Green,
+                },
+                info.next_lock()
+                    .map(|next_lock| format!("Locks at {}", at(next_lock))),
+            ),
+            (CurrentState::Locked, None) => match info.reason() {
+                CurrentStateReason::BreakTimer => (
+                    "Break".to_owned(),
+                    DisplayColor::Red,
+                    info.until()
+                        .map(|until| format!("Break ends at {}", at(until))),
+                ),
+                CurrentStateReason::RequirementNotMet { id }
+                | CurrentStateReason::CriticalRequirementNotMet { id } => (
+                    format!("Complete {}", requirement_name(id)),
+                    DisplayColor::Red,
+                    Some(match info.until() {
+                        Some(until) => format!("Unlocks at {}", at(until)),
+                        None => "Unlocks once it is completed".to_owned(),
+                    }),
+                ),
+                CurrentStateReason::LockedTimeRange { .. } | CurrentStateReason::NoConstraints => (
+                    "Locked".to_owned(),
+                    DisplayColor::Red,
+                    info.until()
+                        .map(|until| format!("Unlocks at {}", at(until))),
+                ),
+            },
+            (CurrentState::Unlockable, None) => (
+                "Break is over".to_owned(),
+                DisplayColor::Blue,
+                Some("Unlock to start the next work period".to_owned()),
+            ),
+        };
+        let progress = info
+            .until()
+            .filter(|until| *until > state_since)
+            .map(|until| {
+                let elapsed = (current_time - state_since).as_seconds() as f64;
+                let total = (until - state_since).as_seconds() as f64;
+                (elapsed / total).clamp(0.0, 1.0)
+            });
+        Self {
+            headline,
+            color,
+            progress,
+            next_event,
+        }
+    }
+}
+
+/// Answers every UDP datagram received on `bind_on` with a status frame, and broadcasts a
+/// frame to `broadcast_to` at a regular interval.
+pub async fn run_display_server(config: DisplayConfig, current_info: impl Fn() -> CurrentInfo) {
+    let bind_on = config.bind_on.as_deref().unwrap_or("0.0.0.0:0");
+    let socket = match UdpSocket::bind(bind_on).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            eprintln!("Unable to bind the display socket to {}: {}", bind_on, err);
+            return;
+        }
+    };
+    if config.bind_on.is_some() {
+        eprintln!("Serving display frames over UDP on {}", bind_on);
+    }
+    if let Err(err) = socket.set_broadcast(true) {
+        eprintln!(
+            "Unable to enable broadcasting on the display socket: {}",
+            err
+        );
+    }
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        config
+            .broadcast_interval_seconds
+            .unwrap_or(DEFAULT_BROADCAST_INTERVAL_SECONDS)
+            .max(1),
+    ));
+    let mut buf = [0; 64];
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let Ok((_, peer)) = received else {
+                    continue;
+                };
+                let frame = encode_frame(&current_info(), Timestamp::now());
+                if let Err(err) = socket.send_to(&frame, peer).await {
+                    eprintln!("Unable to send a display frame to {}: {}", peer, err);
+                }
+            }
+            _ = interval.tick(), if config.broadcast_to.is_some() => {
+                let broadcast_to = config.broadcast_to.as_deref().unwrap();
+                let frame = encode_frame(&current_info(), Timestamp::now());
+                if let Err(err) = socket.send_to(&frame, broadcast_to).await {
+                    eprintln!("Unable to broadcast a display frame to {}: {}", broadcast_to, err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::config::{EmailDigestConfig, SmtpSecurity};
+use crate::stats::DayStats;
+use crate::time::{LocalDate, Timestamp};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+fn build_mailer(config: &EmailDigestConfig) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+    let mut builder = match config.security.unwrap_or(SmtpSecurity::StartTls) {
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_server),
+        SmtpSecurity::StartTls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_server)
+        }
+        SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+            &config.smtp_server,
+        )),
+    }
+    .map_err(|err| format!("Unable to connect to {}: {}", config.smtp_server, err))?;
+    if let Some(port) = config.smtp_port {
+        builder = builder.port(port);
+    }
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
+
+async fn send_digest(
+    mailer: &AsyncSmtpTransport<Tokio1Executor>,
+    config: &EmailDigestConfig,
+    stats: &DayStats,
+    date: &LocalDate,
+) -> Result<(), String> {
+    // the addresses were already checked when the configuration was loaded
+    let message = Message::builder()
+        .from(config.from.parse().unwrap())
+        .to(config.to.parse().unwrap())
+        .subject(format!("Diagonator digest for {}", date.format("%Y-%m-%d")))
+        .header(ContentType::TEXT_PLAIN)
+        .body(stats.summary())
+        .map_err(|err| err.to_string())?;
+    mailer.send(message).await.map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+/// Emails a summary of the previous day's statistics every day at the configured time.
+pub async fn run_email_digest(
+    config: EmailDigestConfig,
+    day_stats: impl Fn(&LocalDate) -> Option<DayStats>,
+) {
+    let mailer = match build_mailer(&config) {
+        Ok(mailer) => mailer,
+        Err(msg) => {
+            eprintln!("Unable to set up the daily digest email: {}", msg);
+            return;
+        }
+    };
+    loop {
+        let now = Timestamp::now();
+        let today = now.get_date();
+        let mut send_time = Timestamp::from_date_hm(&today, &config.send_at);
+        if send_time <= now {
+            send_time = Timestamp::from_date_hm(&today.succ(), &config.send_at);
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(
+            (send_time - now).as_seconds() as u64,
+        ))
+        .await;
+        let yesterday = send_time.get_date().pred();
+        let Some(stats) = day_stats(&yesterday) else {
+            eprintln!(
+                "No statistics were recorded on {}, so no daily digest was sent",
+                yesterday.format("%Y-%m-%d")
+            );
+            continue;
+        };
+        match send_digest(&mailer, &config, &stats, &yesterday).await {
+            Ok(()) => eprintln!("Sent the daily digest to {}", config.to),
+            Err(msg) => eprintln!("Unable to send the daily digest: {}", msg),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use axum::http::{header, HeaderMap, HeaderName};
+use axum::response::IntoResponse;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// How requests and responses are encoded. Clients that poll frequently, e.g. on low-power
+/// devices, can use MessagePack instead of JSON, which is cheaper to encode and parse.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    /// Finds the first known media type in a header such as `Accept`, which can list several.
+    fn from_header(headers: &HeaderMap, name: HeaderName) -> Option<Self> {
+        let value = headers.get(name)?.to_str().ok()?;
+        value.split(',').find_map(|media_type| {
+            match media_type.split(';').next().unwrap_or_default().trim() {
+                "application/msgpack" | "application/x-msgpack" => Some(Self::MessagePack),
+                "application/json" => Some(Self::Json),
+                _ => None,
+            }
+        })
+    }
+    /// The encoding of the request body, given by its `Content-Type`, JSON by default.
+    pub fn of_request(headers: &HeaderMap) -> Self {
+        Self::from_header(headers, header::CONTENT_TYPE).unwrap_or(Self::Json)
+    }
+    /// The encoding that the client wants for the response, given by its `Accept` header, or
+    /// the encoding of the request if it doesn't name one.
+    pub fn of_response(headers: &HeaderMap) -> Self {
+        Self::from_header(headers, header::ACCEPT).unwrap_or_else(|| Self::of_request(headers))
+    }
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+        }
+    }
+    pub fn decode<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, String> {
+        match self {
+            Self::Json => serde_json::from_slice(body).map_err(|err| err.to_string()),
+            Self::MessagePack => rmp_serde::from_slice(body).map_err(|err| err.to_string()),
+        }
+    }
+    /// Structs are encoded as MessagePack maps, so that their fields have the same names as in
+    /// JSON.
+    pub fn encode<T: Serialize>(self, value: &T) -> Vec<u8> {
+        match self {
+            Self::Json => serde_json::to_vec(value).unwrap(),
+            Self::MessagePack => rmp_serde::to_vec_named(value).unwrap(),
+        }
+    }
+    pub fn respond<T: Serialize>(self, value: &T) -> axum::response::Response {
+        (
+            [(header::CONTENT_TYPE, self.content_type())],
+            self.encode(value),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,238 @@
+use crate::config::EventLogConfig;
+use crate::events::Event;
+use crate::time::{Duration, Timestamp};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_MAX_SIZE_KB: u64 = 1024;
+const DEFAULT_MAX_AGE_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedEvent {
+    at: Timestamp,
+    event: Event,
+}
+
+/// A compressed archive of events that were rotated out of the log.
+#[derive(Serialize, Deserialize, Debug)]
+struct Archive {
+    /// The name of the archive, which is next to the log
+    file: String,
+    first: Timestamp,
+    last: Timestamp,
+    count: usize,
+}
+
+/// An append-only journal of events, with one JSON event per line. Once the log grows too
+/// large or too old, it is compressed into an archive, and an index of the archives records the
+/// time range of each one, so that reading recent events doesn't decompress older archives.
+pub struct EventLog {
+    path: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+    max_archives: Option<usize>,
+    /// The times of the first and last events in the log, if any
+    range: Option<(Timestamp, Timestamp)>,
+    count: usize,
+    archives: Vec<Archive>,
+    /// How many times the log was rotated since it was opened
+    rotations: u64,
+}
+
+/// The files that hold the events at or after a time, which can be read without holding on to
+/// the log while it keeps being appended to.
+pub struct EventLogSnapshot {
+    since: Timestamp,
+    archives: Vec<PathBuf>,
+    log: Option<PathBuf>,
+    rotations: u64,
+}
+
+impl EventLogSnapshot {
+    /// Reads the events, skipping archives that were deleted for `max_archives` in the meantime
+    /// and a line that is still being appended at the end of the log.
+    pub fn read(&self) -> Result<Vec<LoggedEvent>, String> {
+        let mut events = Vec::new();
+        for path in &self.archives {
+            let file = match File::open(path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(format!("Unable to open {}: {}", path.display(), err)),
+            };
+            events.extend(read_lines(GzDecoder::new(file))?);
+        }
+        if let Some(path) = &self.log {
+            let mut contents = match std::fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(err) => return Err(err.to_string()),
+            };
+            contents.truncate(contents.rfind('\n').map_or(0, |end| end + 1));
+            events.extend(read_lines(contents.as_bytes())?);
+        }
+        events.retain(|event| event.at >= self.since);
+        Ok(events)
+    }
+    /// Returns whether the log wasn't rotated since the snapshot was taken, so that the events
+    /// that were read are complete.
+    pub fn is_current(&self, log: &EventLog) -> bool {
+        self.rotations == log.rotations
+    }
+}
+
+fn read_lines(reader: impl Read) -> Result<Vec<LoggedEvent>, String> {
+    let mut events = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        if !line.is_empty() {
+            events.push(serde_json::from_str(&line).map_err(|err| err.to_string())?);
+        }
+    }
+    Ok(events)
+}
+
+/// Reads the events in the file, returning no events if it doesn't exist yet.
+fn read_events(path: &Path) -> Result<Vec<LoggedEvent>, String> {
+    match File::open(path) {
+        Ok(file) => read_lines(file),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+impl EventLog {
+    pub fn open(config: EventLogConfig) -> Result<Self, String> {
+        let mut log = Self {
+            path: config.path,
+            max_size: config.max_size_kb.unwrap_or(DEFAULT_MAX_SIZE_KB) * 1024,
+            max_age: Duration::from_minutes(
+                config.max_age_days.unwrap_or(DEFAULT_MAX_AGE_DAYS) * 24 * 60,
+            ),
+            max_archives: config.max_archives,
+            range: None,
+            count: 0,
+            archives: Vec::new(),
+            rotations: 0,
+        };
+        let events = read_events(&log.path)?;
+        log.range = events
+            .first()
+            .zip(events.last())
+            .map(|(first, last)| (first.at, last.at));
+        log.count = events.len();
+        log.archives = match std::fs::read(log.index_path()) {
+            Ok(index) => serde_json::from_slice(&index).map_err(|err| err.to_string())?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.to_string()),
+        };
+        Ok(log)
+    }
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+    fn index_path(&self) -> PathBuf {
+        self.sibling(".index.json")
+    }
+    fn needs_rotation(&self, current_time: Timestamp) -> bool {
+        let size = std::fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+        self.range
+            .is_some_and(|(first, _)| size >= self.max_size || current_time - first >= self.max_age)
+    }
+    /// Compresses the log into a new archive, starts an empty log, and deletes the oldest
+    /// archives beyond `max_archives`.
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some((first, last)) = self.range else {
+            return Ok(());
+        };
+        let name = format!(
+            "{}.{}",
+            self.path.file_name().unwrap_or_default().to_string_lossy(),
+            first.as_seconds()
+        );
+        // logs that are rotated within the same second get numbered archives
+        let file = (0..)
+            .map(|n| match n {
+                0 => format!("{}.gz", name),
+                n => format!("{}-{}.gz", name, n),
+            })
+            .find(|file| !self.path.with_file_name(file).exists())
+            .unwrap();
+        let mut encoder = GzEncoder::new(
+            File::create(self.path.with_file_name(&file))?,
+            Compression::default(),
+        );
+        io::copy(&mut File::open(&self.path)?, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        self.archives.push(Archive {
+            file,
+            first,
+            last,
+            count: self.count,
+        });
+        if let Some(max_archives) = self.max_archives {
+            let excess = self.archives.len().saturating_sub(max_archives);
+            for archive in self.archives.drain(..excess) {
+                std::fs::remove_file(self.path.with_file_name(&archive.file))?;
+            }
+        }
+        // the index is replaced at once, so that it never refers to a half-written archive
+        let temp_path = self.sibling(".index.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec(&self.archives).unwrap())?;
+        std::fs::rename(&temp_path, self.index_path())?;
+        File::create(&self.path)?;
+        self.range = None;
+        self.count = 0;
+        self.rotations += 1;
+        Ok(())
+    }
+    pub fn append(&mut self, current_time: Timestamp, events: &[Event]) -> io::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        if self.needs_rotation(current_time) {
+            self.rotate()?;
+        }
+        let mut lines = String::new();
+        for event in events {
+            let logged = LoggedEvent {
+                at: current_time,
+                event: event.clone(),
+            };
+            lines += &serde_json::to_string(&logged).unwrap();
+            lines.push('\n');
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(lines.as_bytes())?;
+        let first = self.range.map_or(current_time, |(first, _)| first);
+        self.range = Some((first, current_time));
+        self.count += events.len();
+        Ok(())
+    }
+    /// Takes a snapshot of the files with the events that happened at or after `since`, or of
+    /// all events if it is `None`, only including the archives that contain such events.
+    pub fn snapshot(&self, since: Option<Timestamp>) -> EventLogSnapshot {
+        let since = since.unwrap_or_default();
+        EventLogSnapshot {
+            since,
+            archives: self
+                .archives
+                .iter()
+                .filter(|archive| archive.last >= since)
+                .map(|archive| self.path.with_file_name(&archive.file))
+                .collect(),
+            log: Some(self.path.clone())
+                .filter(|_| self.range.is_some_and(|(_, last)| last >= since)),
+            rotations: self.rotations,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::manager::{Requirement, Source};
+use crate::time::{Duration, Timestamp};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type")]
+pub enum Event {
+    BreakEndingSoon {
+        at: Timestamp,
+    },
+    DeactivationRequested {
+        id: u64,
+        duration: Duration,
+    },
+    RequirementAdded {
+        id: u64,
+        name: String,
+        source: Source,
+    },
+    ProfileChanged {
+        profile: Option<String>,
+    },
+    WorkPeriodCompleted {
+        start: Timestamp,
+        end: Timestamp,
+        label: Option<String>,
+        intent: Option<String>,
+        too_short: bool,
+    },
+    DayEnding {
+        at: Timestamp,
+        missed_requirements: Vec<Requirement>,
+    },
+    DayStarted {
+        date: String,
+        requirements: Vec<Requirement>,
+    },
+    RequirementDue {
+        id: u64,
+        name: String,
+        due: Timestamp,
+    },
+    RequirementCompleted {
+        id: u64,
+        name: String,
+    },
+    RequirementMissed {
+        id: u64,
+        name: String,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BreakEndingSoon { .. } => "BreakEndingSoon",
+            Self::DeactivationRequested { .. } => "DeactivationRequested",
+            Self::RequirementAdded { .. } => "RequirementAdded",
+            Self::ProfileChanged { .. } => "ProfileChanged",
+            Self::WorkPeriodCompleted { .. } => "WorkPeriodCompleted",
+            Self::DayEnding { .. } => "DayEnding",
+            Self::DayStarted { .. } => "DayStarted",
+            Self::RequirementDue { .. } => "RequirementDue",
+            Self::RequirementCompleted { .. } => "RequirementCompleted",
+            Self::RequirementMissed { .. } => "RequirementMissed",
+        }
+    }
+}
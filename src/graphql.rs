@@ -0,0 +1,222 @@
+use crate::server::{Request, Response};
+use crate::stats::{DayStats, ReviewAnswer as DayReviewAnswer, WorkPeriod as DayWorkPeriod};
+use crate::time::{parse_date, Duration};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Object, Schema, ServerError, SimpleObject,
+};
+use axum::body::Bytes;
+use axum::Json;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::future::Future;
+
+type StatsSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+lazy_static! {
+    static ref SCHEMA: StatsSchema =
+        Schema::build(Query, EmptyMutation, EmptySubscription).finish();
+}
+
+#[derive(SimpleObject)]
+struct LabelTime {
+    label: String,
+    seconds: i64,
+}
+
+fn by_label(times: &BTreeMap<String, Duration>) -> Vec<LabelTime> {
+    times
+        .iter()
+        .map(|(label, time)| LabelTime {
+            label: label.clone(),
+            seconds: time.as_seconds(),
+        })
+        .collect()
+}
+
+/// Times are Unix timestamps in seconds.
+#[derive(SimpleObject)]
+struct WorkPeriod {
+    start: i64,
+    end: i64,
+    label: Option<String>,
+    intent: Option<String>,
+    too_short: bool,
+}
+
+impl From<&DayWorkPeriod> for WorkPeriod {
+    fn from(period: &DayWorkPeriod) -> Self {
+        Self {
+            start: period.start.as_seconds(),
+            end: period.end.as_seconds(),
+            label: period.label.clone(),
+            intent: period.intent.clone(),
+            too_short: period.too_short,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct ReviewAnswer {
+    prompt: String,
+    answer: String,
+}
+
+impl From<&DayReviewAnswer> for ReviewAnswer {
+    fn from(answer: &DayReviewAnswer) -> Self {
+        Self {
+            prompt: answer.prompt.clone(),
+            answer: answer.answer.clone(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+struct AlternativeTaken {
+    requirement: String,
+    alternative: String,
+}
+
+/// The statistics of a single day, with durations in seconds.
+#[derive(SimpleObject)]
+struct Day {
+    date: String,
+    focus_seconds: i64,
+    focus_by_label: Vec<LabelTime>,
+    work_periods: Vec<WorkPeriod>,
+    short_work_periods: u64,
+    review_answers: Vec<ReviewAnswer>,
+    completed_requirements: Vec<String>,
+    missed_requirements: Vec<String>,
+    deactivation_seconds: Vec<i64>,
+    alternatives_taken: Vec<AlternativeTaken>,
+    focus_score: Option<u32>,
+}
+
+impl From<&DayStats> for Day {
+    fn from(day: &DayStats) -> Self {
+        Self {
+            date: day.date().to_owned(),
+            focus_seconds: day.focus_time().as_seconds(),
+            focus_by_label: by_label(day.focus_time_by_label()),
+            work_periods: day.work_periods().iter().map(WorkPeriod::from).collect(),
+            short_work_periods: day.short_work_periods(),
+            review_answers: day
+                .review_answers()
+                .iter()
+                .map(ReviewAnswer::from)
+                .collect(),
+            completed_requirements: day.completed_requirements().to_vec(),
+            missed_requirements: day.missed_requirements().to_vec(),
+            deactivation_seconds: day
+                .deactivations()
+                .iter()
+                .map(|duration| duration.as_seconds())
+                .collect(),
+            alternatives_taken: day
+                .alternatives_taken()
+                .iter()
+                .map(|(requirement, alternative)| AlternativeTaken {
+                    requirement: requirement.clone(),
+                    alternative: alternative.clone(),
+                })
+                .collect(),
+            focus_score: day.focus_score(),
+        }
+    }
+}
+
+/// The focus time of a range of days, in seconds.
+#[derive(SimpleObject)]
+struct FocusTime {
+    days: usize,
+    total_seconds: i64,
+    by_label: Vec<LabelTime>,
+}
+
+/// Returns the recorded days from `from` to `to`, both inclusive and in YYYY-MM-DD format.
+/// Either end may be omitted to leave the range open.
+fn days_in_range<'a>(
+    ctx: &Context<'a>,
+    from: Option<String>,
+    to: Option<String>,
+) -> async_graphql::Result<Vec<&'a DayStats>> {
+    let parse = |date: Option<String>| match date {
+        Some(date) => match parse_date(&date) {
+            Some(_) => Ok(Some(date)),
+            None => Err(format!("Invalid date '{}', expected YYYY-MM-DD.", date)),
+        },
+        None => Ok(None),
+    };
+    let (from, to) = (parse(from)?, parse(to)?);
+    // dates in YYYY-MM-DD format compare the same way as strings
+    Ok(ctx
+        .data::<Vec<DayStats>>()?
+        .iter()
+        .filter(|day| from.as_deref().is_none_or(|from| day.date() >= from))
+        .filter(|day| to.as_deref().is_none_or(|to| day.date() <= to))
+        .collect())
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    /// The statistics of each recorded day in the range.
+    async fn days(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<String>,
+        to: Option<String>,
+    ) -> async_graphql::Result<Vec<Day>> {
+        Ok(days_in_range(ctx, from, to)?
+            .into_iter()
+            .map(Day::from)
+            .collect())
+    }
+    /// The focus time of the recorded days in the range, in total and by label.
+    async fn focus_time(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<String>,
+        to: Option<String>,
+    ) -> async_graphql::Result<FocusTime> {
+        let days = days_in_range(ctx, from, to)?;
+        let mut total = Duration::ZERO;
+        let mut labels = BTreeMap::new();
+        for day in &days {
+            total += day.focus_time();
+            for (label, time) in day.focus_time_by_label() {
+                *labels.entry(label.clone()).or_insert(Duration::ZERO) += *time;
+            }
+        }
+        Ok(FocusTime {
+            days: days.len(),
+            total_seconds: total.as_seconds(),
+            by_label: by_label(&labels),
+        })
+    }
+}
+
+/// Answers a GraphQL query about the recorded statistics, which are fetched with a `GetStats`
+/// request so that the query is authorized in the same way. Refused requests are turned into
+/// GraphQL errors.
+pub async fn handle_graphql<H, F>(body: Bytes, handle: H) -> Json<async_graphql::Response>
+where
+    H: FnOnce(Request) -> F,
+    F: Future<Output = Response>,
+{
+    let error = |msg: String| {
+        Json(async_graphql::Response::from_errors(vec![
+            ServerError::new(msg, None),
+        ]))
+    };
+    let request: async_graphql::Request = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => return error(format!("Invalid request: {}", err)),
+    };
+    match handle(Request::GetStats).await {
+        Response::Stats { days, .. } => Json(SCHEMA.execute(request.data(days)).await),
+        Response::Error { msg, .. } | Response::InternalError { msg } => error(msg),
+        _ => error("Unexpected response.".to_owned()),
+    }
+}
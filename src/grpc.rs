@@ -0,0 +1,394 @@
+use crate::config::RangeKind;
+use crate::manager::{self, BreakTimer, CurrentInfo, CurrentState, CurrentStateReason};
+use crate::server::{changes_since, Request, Response};
+use crate::time::{Duration, Timestamp};
+use axum::http::HeaderMap;
+use futures_util::stream::BoxStream;
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio::sync::watch;
+use tokio::time::{timeout_at, Instant};
+use tonic::Status;
+
+mod proto {
+    tonic::include_proto!("diagonator");
+}
+
+use proto::diagonator_server::{Diagonator, DiagonatorServer};
+use proto::{
+    AddRequirementRequest, BeginActivityRequest, CompleteRequirementByNameRequest,
+    CompleteRequirementRequest, DeactivateReply, DeactivateRequest, DeactivationId, Empty,
+    IncrementRequirementRequest, Info, InfoUpdate, JsonReply, JsonRequest, ReportLocationRequest,
+    StartFocusRequest, SubmitPlanRequest, Timer, UnlockTimerRequest,
+};
+
+type GrpcResult<T> = Result<tonic::Response<T>, Status>;
+
+fn seconds(time: Timestamp) -> i64 {
+    time.as_seconds()
+}
+
+fn unexpected_response() -> Status {
+    Status::internal("The server returned an unexpected response.")
+}
+
+/// Parses the deadline that the client set in the `grpc-timeout` metadata, e.g. `30S`.
+fn timeout(metadata: &tonic::metadata::MetadataMap) -> Option<std::time::Duration> {
+    let timeout = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (value, unit) = timeout.split_at(timeout.len().checked_sub(1)?);
+    let value: u64 = value.parse().ok()?;
+    Some(match unit {
+        "H" => std::time::Duration::from_secs(value * 3600),
+        "M" => std::time::Duration::from_secs(value * 60),
+        "S" => std::time::Duration::from_secs(value),
+        "m" => std::time::Duration::from_millis(value),
+        "u" => std::time::Duration::from_micros(value),
+        "n" => std::time::Duration::from_nanos(value),
+        _ => return None,
+    })
+}
+
+/// Empty answers are sent as `None`, so that requirements without prompts can be completed.
+fn answers(answers: Vec<String>) -> Option<Vec<String>> {
+    (!answers.is_empty()).then_some(answers)
+}
+
+impl From<&CurrentInfo> for Info {
+    fn from(info: &CurrentInfo) -> Self {
+        let (kind, id) = match info.reason() {
+            CurrentStateReason::BreakTimer => (proto::ReasonKind::BreakTimer, None),
+            CurrentStateReason::RequirementNotMet { id } => {
+                (proto::ReasonKind::RequirementNotMet, Some(*id))
+            }
+            CurrentStateReason::CriticalRequirementNotMet { id } => {
+                (proto::ReasonKind::CriticalRequirementNotMet, Some(*id))
+            }
+            CurrentStateReason::LockedTimeRange { id } => {
+                (proto::ReasonKind::LockedTimeRange, Some(*id))
+            }
+            CurrentStateReason::NoConstraints => (proto::ReasonKind::NoConstraints, None),
+        };
+        Self {
+            state: match info.state() {
+                CurrentState::Unlocked => proto::State::Unlocked,
+                CurrentState::Locked => proto::State::Locked,
+                CurrentState::Unlockable => proto::State::Unlockable,
+            }
+            .into(),
+            until: info.until().map(seconds),
+            reason: Some(proto::Reason {
+                kind: kind.into(),
+                id,
+            }),
+            requirements: info
+                .requirements()
+                .iter()
+                .map(|req| proto::Requirement {
+                    id: req.id(),
+                    name: req.name().to_owned(),
+                    due: seconds(req.due()),
+                    complete: req.is_complete(),
+                    at_risk: info.at_risk_requirements().contains(&req.id()),
+                })
+                .collect(),
+            locked_time_ranges: info
+                .locked_time_ranges()
+                .iter()
+                .map(|range| proto::TimeRange {
+                    id: range.id(),
+                    start: range.start().map(seconds),
+                    end: range.end().map(seconds),
+                    kind: match range.kind() {
+                        RangeKind::Lock => proto::RangeKind::Lock,
+                        RangeKind::Meeting => proto::RangeKind::Meeting,
+                    }
+                    .into(),
+                })
+                .collect(),
+            deactivated_until: info.deactivated_until().map(seconds),
+            focus_label: info.focus_label().map(str::to_owned),
+            intent: info.intent().map(str::to_owned),
+            next_lock: info.next_lock().map(seconds),
+            pressure: match info.pressure() {
+                manager::Pressure::Relaxed => proto::Pressure::Relaxed,
+                manager::Pressure::Approaching => proto::Pressure::Approaching,
+                manager::Pressure::Locked => proto::Pressure::Locked,
+            }
+            .into(),
+            plan: info.plan().to_vec(),
+            focus_score: info.focus_score(),
+            deactivation_quota_remaining: info
+                .deactivation_quota_remaining()
+                .map(Duration::as_seconds),
+        }
+    }
+}
+
+/// Serves the requests of the socket protocol as typed gRPC methods. Tokens are sent in the
+/// `authorization` metadata, e.g. `Bearer <token>`.
+struct GrpcService<H> {
+    handle: H,
+    info_updates: watch::Receiver<(CurrentInfo, u64)>,
+}
+
+impl<H, F> GrpcService<H>
+where
+    H: Fn(HeaderMap, Request) -> F + Send + Sync + 'static,
+    F: Future<Output = Response> + Send,
+{
+    /// Handles the request, turning error responses into gRPC errors.
+    async fn dispatch<T>(
+        &self,
+        request: tonic::Request<T>,
+        to_request: impl FnOnce(T) -> Request,
+    ) -> Result<Response, Status> {
+        let headers = request.metadata().clone().into_headers();
+        match (self.handle)(headers, to_request(request.into_inner())).await {
+            Response::Error { msg, code } => {
+                let mut status = Status::failed_precondition(msg);
+                // refusals carry their code as metadata, e.g. `refusal-code: not_unlockable`
+                let code = code
+                    .and_then(|code| serde_json::to_value(code).ok())
+                    .and_then(|code| code.as_str()?.parse().ok());
+                if let Some(code) = code {
+                    status.metadata_mut().insert("refusal-code", code);
+                }
+                Err(status)
+            }
+            Response::InternalError { msg } => Err(Status::internal(msg)),
+            Response::QuotaExceeded { msg, .. } => Err(Status::resource_exhausted(msg)),
+            response => Ok(response),
+        }
+    }
+    async fn call_empty<T>(
+        &self,
+        request: tonic::Request<T>,
+        to_request: impl FnOnce(T) -> Request,
+    ) -> GrpcResult<Empty> {
+        self.dispatch(request, to_request).await?;
+        Ok(tonic::Response::new(Empty {}))
+    }
+}
+
+#[tonic::async_trait]
+impl<H, F> Diagonator for GrpcService<H>
+where
+    H: Fn(HeaderMap, Request) -> F + Send + Sync + 'static,
+    F: Future<Output = Response> + Send,
+{
+    async fn get_info(&self, request: tonic::Request<Empty>) -> GrpcResult<Info> {
+        match self.dispatch(request, |_| Request::GetInfo).await? {
+            Response::Info { info } => Ok(tonic::Response::new(Info::from(&*info))),
+            _ => Err(unexpected_response()),
+        }
+    }
+    type WatchInfoStream = BoxStream<'static, Result<InfoUpdate, Status>>;
+    async fn watch_info(
+        &self,
+        request: tonic::Request<Empty>,
+    ) -> GrpcResult<Self::WatchInfoStream> {
+        // tonic only applies deadlines until the response starts, so the stream ends itself
+        let deadline = timeout(request.metadata()).map(|timeout| Instant::now() + timeout);
+        // the client needs to be allowed to get the info in order to watch it
+        self.dispatch(request, |_| Request::GetInfo).await?;
+        let mut updates = self.info_updates.clone();
+        updates.mark_changed();
+        let previous: Option<CurrentInfo> = None;
+        let stream = futures_util::stream::unfold(
+            (Some(updates), previous),
+            move |(updates, previous)| async move {
+                let mut updates = updates?;
+                let changed = match deadline {
+                    Some(deadline) => timeout_at(deadline, updates.changed()).await,
+                    None => Ok(updates.changed().await),
+                };
+                let Ok(changed) = changed else {
+                    let status = Status::deadline_exceeded("The deadline has passed.");
+                    return Some((Err(status), (None, previous)));
+                };
+                changed.ok()?;
+                let (info, cache_version) = updates.borrow_and_update().clone();
+                let update = InfoUpdate {
+                    info: Some(Info::from(&info)),
+                    cache_version,
+                    changes: changes_since(&info, previous.as_ref()).bits(),
+                };
+                Some((Ok(update), (Some(updates), Some(info))))
+            },
+        );
+        Ok(tonic::Response::new(Box::pin(stream)))
+    }
+    async fn get_timer(&self, request: tonic::Request<Empty>) -> GrpcResult<Timer> {
+        let Response::Timer { info } = self.dispatch(request, |_| Request::GetTimer).await? else {
+            return Err(unexpected_response());
+        };
+        let (state, since, until) = match info.timer() {
+            BreakTimer::Unlocked { since, until } => {
+                (proto::TimerState::Unlocked, Some(*since), *until)
+            }
+            BreakTimer::Locked { until } => (proto::TimerState::Locked, None, Some(*until)),
+            BreakTimer::Unlockable => (proto::TimerState::Unlockable, None, None),
+        };
+        Ok(tonic::Response::new(Timer {
+            state: state.into(),
+            since: since.map(seconds),
+            until: until.map(seconds),
+            completed_work_periods: info.completed_work_periods(),
+        }))
+    }
+    async fn unlock_timer(&self, request: tonic::Request<UnlockTimerRequest>) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::UnlockTimer { intent: req.intent })
+            .await
+    }
+    async fn lock_timer(&self, request: tonic::Request<Empty>) -> GrpcResult<Empty> {
+        self.call_empty(request, |_| Request::LockTimer).await
+    }
+    async fn complete_requirement(
+        &self,
+        request: tonic::Request<CompleteRequirementRequest>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::CompleteRequirement {
+            id: req.id,
+            answers: answers(req.answers),
+        })
+        .await
+    }
+    async fn complete_requirement_by_name(
+        &self,
+        request: tonic::Request<CompleteRequirementByNameRequest>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::CompleteRequirementByName {
+            name: req.name,
+            answers: answers(req.answers),
+        })
+        .await
+    }
+    async fn increment_requirement(
+        &self,
+        request: tonic::Request<IncrementRequirementRequest>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::IncrementRequirement {
+            name: req.name,
+        })
+        .await
+    }
+    async fn add_requirement(
+        &self,
+        request: tonic::Request<AddRequirementRequest>,
+    ) -> GrpcResult<Empty> {
+        let due = serde_json::from_value(serde_json::Value::String(request.get_ref().due.clone()))
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        self.call_empty(request, |req| Request::AddRequirement {
+            name: req.name,
+            due,
+        })
+        .await
+    }
+    async fn deactivate(
+        &self,
+        request: tonic::Request<DeactivateRequest>,
+    ) -> GrpcResult<DeactivateReply> {
+        let response = self
+            .dispatch(request, |req| Request::Deactivate {
+                duration: Duration::from_seconds(req.duration_seconds),
+            })
+            .await?;
+        let pending_id = match response {
+            Response::Pending { id } => Some(id),
+            _ => None,
+        };
+        Ok(tonic::Response::new(DeactivateReply { pending_id }))
+    }
+    async fn approve_deactivation(
+        &self,
+        request: tonic::Request<DeactivationId>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::ApproveDeactivation { id: req.id })
+            .await
+    }
+    async fn reject_deactivation(
+        &self,
+        request: tonic::Request<DeactivationId>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::RejectDeactivation { id: req.id })
+            .await
+    }
+    async fn start_focus(&self, request: tonic::Request<StartFocusRequest>) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::StartFocus { label: req.label })
+            .await
+    }
+    async fn report_activity(&self, request: tonic::Request<Empty>) -> GrpcResult<Empty> {
+        self.call_empty(request, |_| Request::ReportActivity).await
+    }
+    async fn begin_activity(
+        &self,
+        request: tonic::Request<BeginActivityRequest>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::BeginActivity { name: req.name })
+            .await
+    }
+    async fn end_activity(&self, request: tonic::Request<Empty>) -> GrpcResult<Empty> {
+        self.call_empty(request, |_| Request::EndActivity).await
+    }
+    async fn submit_plan(&self, request: tonic::Request<SubmitPlanRequest>) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::SubmitPlan {
+            intentions: Some(req.intentions),
+        })
+        .await
+    }
+    async fn report_location(
+        &self,
+        request: tonic::Request<ReportLocationRequest>,
+    ) -> GrpcResult<Empty> {
+        self.call_empty(request, |req| Request::ReportLocation {
+            ssid: req.ssid,
+            location: req.location,
+        })
+        .await
+    }
+    async fn ping(&self, request: tonic::Request<Empty>) -> GrpcResult<Empty> {
+        self.call_empty(request, |_| Request::Ping).await
+    }
+    async fn call(&self, request: tonic::Request<JsonRequest>) -> GrpcResult<JsonReply> {
+        let parsed: Request = serde_json::from_str(&request.get_ref().request)
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let response = self.dispatch(request, |_| parsed).await?;
+        Ok(tonic::Response::new(JsonReply {
+            response: serde_json::to_string(&response).unwrap(),
+        }))
+    }
+}
+
+/// Serves the gRPC interface on `bind_on`, handling each request in the same way as requests
+/// sent to the socket protocol. Connections are checked with HTTP/2 pings every `keep_alive`, so
+/// that streams to clients that went away are closed.
+pub async fn run_grpc_server<H, F>(
+    bind_on: String,
+    handle: H,
+    info_updates: watch::Receiver<(CurrentInfo, u64)>,
+    keep_alive: std::time::Duration,
+) where
+    H: Fn(HeaderMap, Request) -> F + Send + Sync + 'static,
+    F: Future<Output = Response> + Send,
+{
+    let addr: SocketAddr = match bind_on.parse() {
+        Ok(addr) => addr,
+        Err(err) => {
+            eprintln!("Invalid gRPC address {}: {}", bind_on, err);
+            return;
+        }
+    };
+    eprintln!("Serving gRPC on {}", addr);
+    let result = tonic::transport::Server::builder()
+        .http2_keepalive_interval(Some(keep_alive))
+        .http2_keepalive_timeout(Some(keep_alive))
+        .add_service(DiagonatorServer::new(GrpcService {
+            handle,
+            info_updates,
+        }))
+        .serve(addr)
+        .await;
+    if let Err(err) = result {
+        eprintln!("Received error '{}' from the gRPC server", err);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::config::{HookConfig, RequirementConfig, WebhookConfig};
+use crate::events::Event;
+use serde_json::Value;
+
+/// Spawns the command of every hook registered for the event, passing the event to it as JSON
+/// in the `DIAGONATOR_EVENT` environment variable. Hooks run in the background and their
+/// failures are only logged, so a broken hook can never interfere with enforcement.
+pub fn run_hooks(hooks: &[HookConfig], event: &Event) {
+    for hook in hooks.iter().filter(|hook| hook.event == event.name()) {
+        run_hook(&hook.command, event);
+    }
+}
+
+/// Runs the `on_due`, `on_complete`, or `on_missed` command of the configured requirement that
+/// the event is about, in the same way as the hooks registered for the event.
+pub fn run_requirement_hooks(requirements: &[RequirementConfig], event: &Event) {
+    let name = match event {
+        Event::RequirementDue { name, .. }
+        | Event::RequirementCompleted { name, .. }
+        | Event::RequirementMissed { name, .. } => name,
+        _ => return,
+    };
+    let command = |req: &RequirementConfig| match event {
+        Event::RequirementDue { .. } => req.on_due.clone(),
+        Event::RequirementCompleted { .. } => req.on_complete.clone(),
+        _ => req.on_missed.clone(),
+    };
+    // a requirement with the same name may be configured several times, e.g. by a profile
+    if let Some(command) = requirements
+        .iter()
+        .filter(|req| req.name == *name)
+        .find_map(command)
+    {
+        run_hook(&command, event);
+    }
+}
+
+fn run_hook(command: &[String], event: &Event) {
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    let result = tokio::process::Command::new(program)
+        .args(args)
+        .env("DIAGONATOR_EVENT", serde_json::to_string(event).unwrap())
+        .spawn();
+    if let Err(err) = result {
+        eprintln!(
+            "Received error '{}' when running hook {:?} for event {}",
+            err,
+            command,
+            event.name()
+        );
+    }
+}
+
+/// Sends the event to the URL of every webhook registered for the event. The request body is the
+/// webhook's template filled in with the event's fields, or the event as JSON if the webhook has
+/// no template. Like hooks, webhooks are sent in the background and their failures are only
+/// logged.
+pub fn send_webhooks(webhooks: &[WebhookConfig], event: &Event) {
+    for webhook in webhooks
+        .iter()
+        .filter(|webhook| webhook.event == event.name())
+    {
+        let body = match &webhook.template {
+            Some(template) => render_template(template, event),
+            None => serde_json::to_string(event).unwrap(),
+        };
+        let url = webhook.url.clone();
+        let name = event.name();
+        tokio::spawn(async move {
+            let result = reqwest::Client::new()
+                .post(&url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status);
+            if let Err(err) = result {
+                eprintln!(
+                    "Received error '{}' when sending webhook to {} for event {}",
+                    err, url, name
+                );
+            }
+        });
+    }
+}
+
+/// Fills in a handlebars-style template, replacing each `{{field}}` with the value of that field
+/// of the event (`{{type}}` is the name of the event). Strings are escaped so that they can be
+/// placed inside JSON strings, other values are inserted as JSON, and missing fields are left
+/// empty.
+fn render_template(template: &str, event: &Event) -> String {
+    let fields = serde_json::to_value(event).unwrap();
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        match fields.get(rest[start + 2..end].trim()) {
+            Some(Value::String(text)) => {
+                let quoted = Value::String(text.clone()).to_string();
+                output.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            Some(Value::Null) | None => {}
+            Some(value) => output.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
@@ -0,0 +1,78 @@
+use crate::config::IdleDetectionConfig;
+use crate::server::{Request, Response};
+use tokio::process::Command;
+
+const CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+
+async fn run_ioreg(args: &[&str]) -> Option<String> {
+    match Command::new("ioreg").args(args).output().await {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            eprintln!("ioreg {:?} failed with {}", args, output.status);
+            None
+        }
+        Err(err) => {
+            eprintln!("Received error '{}' when running ioreg {:?}", err, args);
+            None
+        }
+    }
+}
+
+/// Returns how many seconds have passed since the last keyboard or mouse input, from the
+/// `HIDIdleTime` (in nanoseconds) of the `IOHIDSystem`.
+async fn idle_seconds() -> Option<u64> {
+    let output = run_ioreg(&["-c", "IOHIDSystem", "-d", "4"]).await?;
+    let (_, rest) = output.split_once("\"HIDIdleTime\" = ")?;
+    let nanoseconds: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    nanoseconds
+        .parse::<u64>()
+        .ok()
+        .map(|nanoseconds| nanoseconds / 1_000_000_000)
+}
+
+/// Returns whether the screen is locked, from the console session's `CGSSessionScreenIsLocked`
+/// flag, which is only present while it is locked.
+async fn is_screen_locked() -> bool {
+    run_ioreg(&["-n", "Root", "-d", "1"])
+        .await
+        .is_some_and(|output| output.contains("\"CGSSessionScreenIsLocked\"=Yes"))
+}
+
+/// Watches for keyboard and mouse activity and for the screen being locked on macOS. Returning
+/// to the computer after being idle or after unlocking the screen is reported like a
+/// `ReportActivity` request, and locking the screen during a work period can start the break like
+/// a `LockTimer` request. While the screen is locked, nothing counts as activity, so assertions
+/// such as `caffeinate -u` that reset the idle time don't start work periods.
+pub async fn run_idle_detection(
+    config: IdleDetectionConfig,
+    handle_request: impl Fn(Request) -> Response,
+) {
+    let threshold = config.idle_threshold_seconds.unwrap_or(60);
+    let lock_timer = config.lock_timer_on_screen_lock.unwrap_or(false);
+    let mut was_idle = true;
+    let mut was_locked = false;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let locked = is_screen_locked().await;
+        if locked && !was_locked && lock_timer {
+            // the timer is only locked if a work period is going on
+            if let Response::Success = handle_request(Request::LockTimer) {
+                eprintln!("Locking the break timer because the screen was locked");
+            }
+        }
+        was_locked = locked;
+        let Some(idle) = idle_seconds().await else {
+            continue;
+        };
+        let is_idle = locked || idle >= threshold;
+        if was_idle && !is_idle {
+            if let Response::Error { msg, .. } = handle_request(Request::ReportActivity) {
+                eprintln!("Unable to report activity: {}", msg);
+            }
+        }
+        was_idle = is_idle;
+    }
+}
@@ -0,0 +1,120 @@
+use crate::rest::build_request;
+use crate::server::{Request, Response};
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::{json, Map, Value};
+use std::future::Future;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// The server-defined error code for requests that the server refused, e.g. because the
+/// session is not unlockable.
+const REQUEST_FAILED: i64 = -32000;
+/// The server-defined error code for deactivations that would exceed the weekly quota.
+const QUOTA_EXCEEDED: i64 = -32001;
+
+fn error_reply(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
+
+/// Converts a method name such as `complete_requirement` to the request type
+/// `CompleteRequirement`. Request types such as `CompleteRequirement` are accepted as is.
+fn request_type(method: &str) -> String {
+    method
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Handles a single call, returning the reply, or `None` if the call is a notification.
+async fn handle_call<H, F>(call: Value, handle: &H) -> Option<Value>
+where
+    H: Fn(Request) -> F,
+    F: Future<Output = Response>,
+{
+    let Value::Object(mut call) = call else {
+        return Some(error_reply(Value::Null, INVALID_REQUEST, "Invalid Request"));
+    };
+    // calls without an id are notifications, which are never replied to
+    let id = call.remove("id");
+    let reply_id = id.clone().unwrap_or(Value::Null);
+    if call.get("jsonrpc") != Some(&json!("2.0")) {
+        return Some(error_reply(reply_id, INVALID_REQUEST, "Invalid Request"));
+    }
+    let Some(Value::String(method)) = call.remove("method") else {
+        return Some(error_reply(reply_id, INVALID_REQUEST, "Invalid Request"));
+    };
+    let params = match call.remove("params") {
+        None => Map::new(),
+        Some(Value::Object(params)) => params,
+        Some(Value::Array(params)) if params.is_empty() => Map::new(),
+        Some(_) => {
+            let msg = "Params must be an object with the fields of the request.";
+            return id.map(|id| error_reply(id, INVALID_PARAMS, msg));
+        }
+    };
+    let reply = match build_request(&request_type(&method), params) {
+        Ok(request) => match handle(request).await {
+            Response::Error { msg, code } => {
+                let mut reply = error_reply(reply_id, REQUEST_FAILED, &msg);
+                if let Some(code) = code {
+                    reply["error"]["data"] = json!({ "code": code });
+                }
+                reply
+            }
+            Response::InternalError { msg } => error_reply(reply_id, INTERNAL_ERROR, &msg),
+            Response::QuotaExceeded { msg, .. } => error_reply(reply_id, QUOTA_EXCEEDED, &msg),
+            response => json!({ "jsonrpc": "2.0", "result": response, "id": reply_id }),
+        },
+        Err(msg) if msg.starts_with("unknown variant") => {
+            error_reply(reply_id, METHOD_NOT_FOUND, "Method not found")
+        }
+        Err(msg) => error_reply(reply_id, INVALID_PARAMS, &msg),
+    };
+    id.map(|_| reply)
+}
+
+/// Handles a JSON-RPC 2.0 call or batch of calls. The methods are the request types of the
+/// socket protocol in snake case, e.g. `complete_requirement`, and the params are the fields of
+/// the request. The result of a call is the response of the socket protocol, while error
+/// responses are turned into JSON-RPC errors.
+pub async fn handle_json_rpc<H, F>(body: Bytes, handle: H) -> axum::response::Response
+where
+    H: Fn(Request) -> F,
+    F: Future<Output = Response>,
+{
+    let reply = match serde_json::from_slice(&body) {
+        Err(_) => Some(error_reply(Value::Null, PARSE_ERROR, "Parse error")),
+        Ok(Value::Array(calls)) if calls.is_empty() => {
+            Some(error_reply(Value::Null, INVALID_REQUEST, "Invalid Request"))
+        }
+        Ok(Value::Array(calls)) => {
+            let mut replies = Vec::new();
+            for call in calls {
+                replies.extend(handle_call(call, &handle).await);
+            }
+            // a batch of notifications gets no reply at all
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        Ok(call) => handle_call(call, &handle).await,
+    };
+    match reply {
+        Some(reply) => Json(reply).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
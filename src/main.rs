@@ -1,15 +1,179 @@
+mod audio;
+mod chat;
+mod clients;
 mod config;
+mod connections;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod display;
+mod email;
+mod encoding;
+mod event_log;
+mod events;
+mod git_hook;
+#[cfg(feature = "graphql")]
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hooks;
+mod idle;
+mod jsonrpc;
 mod manager;
+mod matrix;
+#[cfg(feature = "mdns")]
+mod mdns;
+mod metrics;
+mod refusal;
+mod rest;
 mod server;
+mod service;
 mod simulator;
+mod state_file;
+mod stats;
+mod status_page;
+mod sun;
+#[cfg(feature = "telegram")]
+mod telegram;
 mod time;
+mod tls;
+mod transport;
+mod update;
+mod version;
 
-use config::load_config;
+use config::{ephemeral_config, load_config};
 use server::launch_server;
+use std::path::PathBuf;
+
+enum Command {
+    Serve,
+    InstallService,
+    #[cfg(windows)]
+    RunService,
+    InstallGitHook {
+        repo: PathBuf,
+    },
+}
+
+const USAGE: &str = "Usage:
+  diagonator-server [--config <path>]
+  diagonator-server --ephemeral [--set <key>=<value>]... [--requirement <name>@<HH:MM>]...
+  diagonator-server install-service [--config <path>]
+  diagonator-server hook install-git <repo> --requirement <name> [--complete] [--url <url>] [--token <token>]";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
 
 #[tokio::main]
 async fn main() {
-    match load_config() {
+    let mut config_path = None;
+    let mut ephemeral = false;
+    let mut settings = Vec::new();
+    let mut requirements = Vec::new();
+    let mut requirement = None;
+    let mut complete = false;
+    let mut url = "http://localhost:3000".to_owned();
+    let mut token = None;
+    let mut args = std::env::args().skip(1).peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some("install-service") => {
+            args.next();
+            Command::InstallService
+        }
+        #[cfg(windows)]
+        Some("run-service") => {
+            args.next();
+            Command::RunService
+        }
+        Some("hook") => {
+            args.next();
+            match (args.next().as_deref(), args.next()) {
+                (Some("install-git"), Some(repo)) => Command::InstallGitHook {
+                    repo: PathBuf::from(repo),
+                },
+                _ => usage(),
+            }
+        }
+        _ => Command::Serve,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match (&command, arg.as_str()) {
+            (Command::Serve | Command::InstallService, "--config") => {
+                config_path = Some(PathBuf::from(value()))
+            }
+            #[cfg(windows)]
+            (Command::RunService, "--config") => config_path = Some(PathBuf::from(value())),
+            (Command::Serve, "--ephemeral") => ephemeral = true,
+            (Command::Serve, "--set") => match value().split_once('=') {
+                Some((key, setting)) => settings.push((key.to_owned(), setting.to_owned())),
+                None => usage(),
+            },
+            (Command::Serve, "--requirement") => match value().rsplit_once('@') {
+                Some((name, due)) => requirements.push((name.to_owned(), due.to_owned())),
+                None => usage(),
+            },
+            (Command::InstallGitHook { .. }, "--requirement") => requirement = Some(value()),
+            (Command::InstallGitHook { .. }, "--complete") => complete = true,
+            (Command::InstallGitHook { .. }, "--url") => url = value(),
+            (Command::InstallGitHook { .. }, "--token") => token = Some(value()),
+            _ => usage(),
+        }
+    }
+    match command {
+        Command::Serve => {}
+        Command::InstallService => {
+            match service::install_service(config_path.as_deref()) {
+                Ok(instructions) => eprintln!("{}", instructions),
+                Err(err) => {
+                    eprintln!("Encountered error when installing service: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        #[cfg(windows)]
+        Command::RunService => {
+            let result = load_config(config_path)
+                .map_err(|err| format!("Encountered error when loading config: {}", err))
+                .and_then(service::run_service);
+            if let Err(err) = result {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::InstallGitHook { repo } => {
+            let Some(requirement) = requirement else {
+                usage();
+            };
+            match git_hook::install_git_hook(&repo, &requirement, complete, &url, token.as_deref())
+            {
+                Ok(path) => eprintln!("Installed git hook at {}", path.display()),
+                Err(err) => {
+                    eprintln!("Encountered error when installing git hook: {}", err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    }
+    if !ephemeral && (!settings.is_empty() || !requirements.is_empty()) {
+        eprintln!("--set and --requirement can only be used with --ephemeral");
+        usage();
+    }
+    if ephemeral && config_path.is_some() {
+        eprintln!("--ephemeral doesn't use a configuration file");
+        usage();
+    }
+    let config = if ephemeral {
+        eprintln!("Running ephemerally, so nothing will be saved");
+        ephemeral_config(&settings, &requirements)
+    } else {
+        load_config(config_path)
+    };
+    match config {
         Ok(config) => {
             launch_server(config).await;
         }
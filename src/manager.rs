@@ -1,82 +1,582 @@
-use crate::config::{LockedTimeRangeConfig, RequirementConfig};
+use crate::config::{
+    AppListConfig, BreakCreditConfig, CrunchModeConfig, DailyReviewConfig, DayOverrideConfig,
+    FocusScoreConfig, GroupMode, Location, LockedTimeRangeConfig, ProfileConfig, ProfileRuleConfig,
+    ProfileScheduleConfig, RangeKind, RequirementConfig, RequirementGroupConfig, RestDebtConfig,
+    StateRecovery, StopwatchConfig, TimerPresetConfig, WeeklyTemplateConfig,
+};
+use crate::display::DisplayInfo;
+use crate::events::Event;
+use crate::refusal::RefusalCode;
 use crate::server::Response;
 use crate::simulator::{Simulator, StateChange, StateChangeKind};
-use crate::time::{Duration, HourMinute, LocalDate, Timestamp};
-use serde::{Deserialize, Serialize};
+use crate::stats::{DayStats, ReviewAnswer, Stats, WorkPeriod};
+use crate::time::{
+    parse_date, start_of_week, Duration, HourMinute, LocalDate, TimeOfDay, Timestamp,
+};
+use chrono::{Datelike, Weekday};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use tokio::sync::watch;
 
+/// Where a requirement or locked time range comes from.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-struct Requirement {
+#[serde(tag = "type")]
+pub enum Source {
+    Config,
+    WeeklyTemplate,
+    DailyReview,
+    CrunchMode,
+    DayOverride,
+    Profile { name: String },
+    Command { client: String },
+    Recovery,
+    Planning,
+    Hypothetical,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
     id: u64,
     name: String,
     due: Timestamp,
     complete: bool,
+    completed_at: Option<Timestamp>,
+    prompts: Vec<String>,
+    order: i64,
+    pinned: bool,
+    lock_from_start: bool,
+    activity_goal: Option<ActivityGoal>,
+    count_goal: Option<CountGoal>,
+    source: Source,
+    /// When the requirement stops locking the screen if it is still incomplete
+    #[serde(default)]
+    expires: Option<Timestamp>,
+    /// How long completing the requirement is expected to take
+    #[serde(default)]
+    estimate: Option<Duration>,
+    /// The name of the requirement group that the requirement belongs to
+    #[serde(default)]
+    group: Option<String>,
+    /// Ways to complete the requirement, any one of which completes it
+    #[serde(default)]
+    alternatives: Vec<Alternative>,
+    /// The name of the alternative that the requirement was completed by
+    #[serde(default)]
+    completed_by: Option<String>,
+}
+
+/// One of several ways to complete a requirement, which has its own ID to complete it by.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Alternative {
+    id: u64,
+    name: String,
+    activity_goal: Option<ActivityGoal>,
+    count_goal: Option<CountGoal>,
+}
+
+/// How far a requirement has come, which is announced with an event whenever it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RequirementStatus {
+    Pending,
+    Due,
+    Complete,
+    Missed,
+}
+
+impl Requirement {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn due(&self) -> Timestamp {
+        self.due
+    }
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+    /// Whether the requirement was missed because it expired before it was completed.
+    fn is_expired(&self, current_time: Timestamp) -> bool {
+        !self.complete && self.expires.is_some_and(|expires| current_time >= expires)
+    }
+    /// Requirements that were satisfied by another requirement of their group never become due
+    /// or missed.
+    fn status(&self, current_time: Timestamp, satisfied: bool) -> RequirementStatus {
+        if self.complete {
+            RequirementStatus::Complete
+        } else if satisfied {
+            RequirementStatus::Pending
+        } else if self.is_expired(current_time) {
+            RequirementStatus::Missed
+        } else if current_time >= self.due {
+            RequirementStatus::Due
+        } else {
+            RequirementStatus::Pending
+        }
+    }
+    fn new(id: u64, name: String, due: Timestamp, source: Source) -> Self {
+        Self {
+            id,
+            name,
+            due,
+            complete: false,
+            completed_at: None,
+            prompts: Vec::new(),
+            order: 0,
+            pinned: false,
+            lock_from_start: false,
+            activity_goal: None,
+            count_goal: None,
+            source,
+            expires: None,
+            estimate: None,
+            group: None,
+            alternatives: Vec::new(),
+            completed_by: None,
+        }
+    }
+}
+
+/// A group of requirements, which clients can show with its requirements nested under it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RequirementGroup {
+    name: String,
+    mode: GroupMode,
+    /// The IDs of today's requirements in the group
+    requirements: Vec<u64>,
+    satisfied: bool,
+}
+
+/// Time that must be spent in an activity to complete a requirement.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ActivityGoal {
+    activity: String,
+    duration: Duration,
+    progress: Duration,
+}
+
+fn activity_goal(activity: &Option<String>, minutes: Option<i64>) -> Option<ActivityGoal> {
+    activity
+        .as_ref()
+        .zip(minutes)
+        .map(|(activity, minutes)| ActivityGoal {
+            activity: activity.clone(),
+            duration: Duration::from_minutes(minutes),
+            progress: Duration::ZERO,
+        })
+}
+
+/// Number of times a requirement must be incremented (e.g. by a git hook) to complete it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CountGoal {
+    target: u64,
+    count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Activity {
+    name: String,
+    since: Timestamp,
+}
+
+/// Applications and URLs, such as those that enforcement clients should block or keep available
+/// while locked.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppList {
+    applications: Vec<String>,
+    urls: Vec<String>,
+}
+
+impl AppList {
+    pub fn from_config(config: Option<&AppListConfig>) -> Self {
+        let mut exceptions = Self::default();
+        if let Some(config) = config {
+            exceptions.extend(
+                config.applications.iter().flatten(),
+                config.urls.iter().flatten(),
+            );
+        }
+        exceptions
+    }
+    fn is_empty(&self) -> bool {
+        self.applications.is_empty() && self.urls.is_empty()
+    }
+    fn extend<'a>(
+        &mut self,
+        applications: impl IntoIterator<Item = &'a String>,
+        urls: impl IntoIterator<Item = &'a String>,
+    ) {
+        for application in applications {
+            if !self.applications.contains(application) {
+                self.applications.push(application.clone());
+            }
+        }
+        for url in urls {
+            if !self.urls.contains(url) {
+                self.urls.push(url.clone());
+            }
+        }
+    }
+    fn merge(&mut self, other: &AppList) {
+        self.extend(&other.applications, &other.urls);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-struct TimeRange {
+pub struct TimeRange {
     id: u64,
     start: Option<Timestamp>,
     end: Option<Timestamp>,
+    source: Source,
+    #[serde(default)]
+    kind: RangeKind,
+    #[serde(default, skip_serializing_if = "AppList::is_empty")]
+    exceptions: AppList,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merged_ids: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    merged_sources: Vec<Source>,
+}
+
+#[cfg(feature = "grpc")]
+impl TimeRange {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    pub fn start(&self) -> Option<Timestamp> {
+        self.start
+    }
+    pub fn end(&self) -> Option<Timestamp> {
+        self.end
+    }
+    pub fn kind(&self) -> RangeKind {
+        self.kind
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PendingDeactivation {
+    id: u64,
+    duration: Duration,
+    expires: Timestamp,
+}
+
+impl PendingDeactivation {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
-enum BreakTimer {
-    Unlocked { until: Timestamp },
-    Locked { until: Timestamp },
+pub enum BreakTimer {
+    Unlocked {
+        since: Timestamp,
+        until: Option<Timestamp>,
+    },
+    Locked {
+        until: Timestamp,
+    },
     Unlockable,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimerInfo {
+    timer: BreakTimer,
+    completed_work_periods: u64,
+}
+
+#[cfg(feature = "grpc")]
+impl TimerInfo {
+    pub fn timer(&self) -> &BreakTimer {
+        &self.timer
+    }
+    pub fn completed_work_periods(&self) -> u64 {
+        self.completed_work_periods
+    }
+}
+
+#[derive(Clone)]
 struct BreakTimerManager {
     timer: BreakTimer,
     work_period_duration: Duration,
     break_duration: Duration,
+    /// The length of the long break and the number of completed work periods it follows
+    long_break: Option<(Duration, u64)>,
+    min_work_period_duration: Option<Duration>,
+    stopwatch: Option<StopwatchConfig>,
+    focus_label: Option<String>,
+    intent: Option<String>,
+    finished_work_periods: Vec<WorkPeriod>,
+    break_just_ended: bool,
+    awaiting_activity: bool,
+    completed_work_periods: u64,
+    /// The meetings of the day, during which the timer is paused
+    meetings: Vec<(Option<Timestamp>, Option<Timestamp>)>,
+    refreshed_at: Option<Timestamp>,
+    rest_debt_config: Option<RestDebtConfig>,
+    /// Rest that was missed because breaks were cut short or spent deactivated
+    rest_debt: Duration,
+    /// How much the current break was lengthened to pay back rest debt
+    break_extension: Duration,
+    break_credit_config: Option<BreakCreditConfig>,
+    /// Break time that was banked by ending breaks early, which can be spent on longer breaks
+    break_credit: Duration,
+    /// When the current deactivation ends, since breaks spent deactivated aren't rest
+    deactivated_until: Option<Timestamp>,
 }
 
 impl BreakTimerManager {
-    fn new(work_period_duration: Duration, break_duration: Duration) -> Self {
+    fn new(
+        work_period_duration: Duration,
+        break_duration: Duration,
+        min_work_period_duration: Option<Duration>,
+        stopwatch: Option<StopwatchConfig>,
+    ) -> Self {
         Self {
             timer: BreakTimer::Unlockable,
             work_period_duration,
             break_duration,
+            long_break: None,
+            min_work_period_duration,
+            stopwatch,
+            focus_label: None,
+            intent: None,
+            finished_work_periods: Vec::new(),
+            break_just_ended: false,
+            awaiting_activity: false,
+            completed_work_periods: 0,
+            meetings: Vec::new(),
+            refreshed_at: None,
+            rest_debt_config: None,
+            rest_debt: Duration::ZERO,
+            break_extension: Duration::ZERO,
+            break_credit_config: None,
+            break_credit: Duration::ZERO,
+            deactivated_until: None,
         }
     }
-    fn unlock(&mut self, current_time: Timestamp) -> Result<(), String> {
+    fn unlock(&mut self, current_time: Timestamp, intent: Option<String>) -> Result<(), String> {
         self.refresh(current_time);
         match self.timer {
             BreakTimer::Unlockable => {
+                // in stopwatch mode, the work period lasts until the timer is locked
+                let until = match &self.stopwatch {
+                    Some(stopwatch) => stopwatch
+                        .max_work_period_minutes
+                        .map(|minutes| current_time + Duration::from_minutes(minutes)),
+                    None => Some(current_time + self.work_period_duration),
+                };
                 self.timer = BreakTimer::Unlocked {
-                    until: current_time + self.work_period_duration,
+                    since: current_time,
+                    until,
                 };
+                self.focus_label = None;
+                self.intent = intent;
                 Ok(())
             }
             BreakTimer::Locked { until: _ } => Err("Break timer is locked.".to_owned()),
-            BreakTimer::Unlocked { until: _ } => Err("Break timer is already unlocked.".to_owned()),
+            BreakTimer::Unlocked { .. } => Err("Break timer is already unlocked.".to_owned()),
         }
     }
     fn lock(&mut self, current_time: Timestamp) -> Result<(), String> {
         self.refresh(current_time);
         match self.timer {
-            BreakTimer::Unlocked { until: _ } => {
+            BreakTimer::Unlocked { since, .. } => {
+                let completed = self.finish_work_period(since, current_time);
+                let extension = self.repay_rest_debt();
                 self.timer = BreakTimer::Locked {
-                    until: current_time + self.break_duration,
+                    until: current_time
+                        + self.break_duration_after(current_time - since, completed)
+                        + extension,
                 };
                 Ok(())
             }
             _ => Err("Break timer is not unlocked.".to_owned()),
         }
     }
+    fn start_focus(&mut self, current_time: Timestamp, label: String) -> Result<(), String> {
+        self.refresh(current_time);
+        match self.timer {
+            BreakTimer::Unlocked { .. } => {
+                self.focus_label = Some(label);
+                Ok(())
+            }
+            _ => Err("Break timer is not unlocked.".to_owned()),
+        }
+    }
+    /// Returns the length of the break after a work period. In stopwatch mode, it is proportional
+    /// to the length of the work period. Every `periods_per_long_break`th completed work period
+    /// is followed by a break of at least the long break's length, while work periods that are
+    /// shorter than `min_work_period_duration` only earn their share of the break.
+    fn break_duration_after(&self, work_period: Duration, completed: bool) -> Duration {
+        let duration = self.regular_break_duration_after(work_period);
+        if let (false, Some(min)) = (completed, self.min_work_period_duration) {
+            return Duration::from_seconds(
+                duration.as_seconds() * work_period.as_seconds() / min.as_seconds().max(1),
+            );
+        }
+        match self.long_break {
+            Some((long_break, periods))
+                if completed && self.completed_work_periods.is_multiple_of(periods) =>
+            {
+                duration.max(long_break)
+            }
+            _ => duration,
+        }
+    }
+    fn regular_break_duration_after(&self, work_period: Duration) -> Duration {
+        let Some(stopwatch) = &self.stopwatch else {
+            return self.break_duration;
+        };
+        let mut duration = Duration::from_seconds(
+            work_period.as_seconds() / stopwatch.work_minutes_per_break_minute,
+        );
+        if let Some(min) = stopwatch.min_break_minutes.map(Duration::from_minutes) {
+            duration = duration.max(min);
+        }
+        if let Some(max) = stopwatch.max_break_minutes.map(Duration::from_minutes) {
+            duration = duration.min(max);
+        }
+        duration
+    }
+    /// Adds rest that was missed to the rest debt, up to its maximum.
+    fn accrue_rest_debt(&mut self, missed: Duration) {
+        let Some(config) = &self.rest_debt_config else {
+            return;
+        };
+        let mut rest_debt = self.rest_debt;
+        rest_debt += missed;
+        self.rest_debt = rest_debt.min(Duration::from_minutes(config.max_minutes));
+    }
+    /// Pays back rest debt by lengthening the break that is starting, returning how much longer
+    /// it gets.
+    fn repay_rest_debt(&mut self) -> Duration {
+        let max_extension = self
+            .rest_debt_config
+            .as_ref()
+            .and_then(|config| config.max_extension_minutes)
+            .map_or(self.rest_debt, Duration::from_minutes);
+        let extension = self.rest_debt.min(max_extension);
+        self.rest_debt =
+            Duration::from_seconds(self.rest_debt.as_seconds() - extension.as_seconds());
+        self.break_extension = extension;
+        extension
+    }
+    /// Counts the part of the break since the last refresh that was spent deactivated as missed
+    /// rest.
+    fn track_deactivated_rest(&mut self, current_time: Timestamp) {
+        if let (Some(refreshed_at), Some(deactivated_until), BreakTimer::Locked { until }) =
+            (self.refreshed_at, self.deactivated_until, &self.timer)
+        {
+            let end = current_time.min(deactivated_until).min(*until);
+            if refreshed_at < end {
+                self.accrue_rest_debt(end - refreshed_at);
+            }
+        }
+    }
+    /// Replaces the timer, counting the rest of a break that is cut short as missed.
+    fn set_timer(&mut self, current_time: Timestamp, timer: BreakTimer) {
+        self.refresh(current_time);
+        if let BreakTimer::Locked { until } = self.timer {
+            let end = match timer {
+                BreakTimer::Locked { until } => until.max(current_time),
+                _ => current_time,
+            };
+            if end < until {
+                self.accrue_rest_debt(until - end);
+            }
+        }
+        if !matches!(timer, BreakTimer::Locked { .. }) {
+            self.break_extension = Duration::ZERO;
+        }
+        self.timer = timer;
+    }
+    /// Records a finished work period, returning whether it counts as completed.
+    fn finish_work_period(&mut self, start: Timestamp, end: Timestamp) -> bool {
+        // work periods that are locked early to get extra breaks don't count as completed
+        let too_short = self
+            .min_work_period_duration
+            .is_some_and(|min| end - start < min);
+        if too_short {
+            eprintln!(
+                "Work period was locked after {} minutes, which is shorter than the minimum, so it is not counted as completed and its break is shortened",
+                (end - start).as_minutes()
+            );
+        } else {
+            self.completed_work_periods += 1;
+        }
+        self.finished_work_periods.push(WorkPeriod {
+            start,
+            end,
+            label: self.focus_label.take(),
+            intent: self.intent.clone(),
+            too_short,
+        });
+        !too_short
+    }
+    /// Returns the meeting that is going on at the given time, if any.
+    fn current_meeting(&self, time: Timestamp) -> Option<(Option<Timestamp>, Option<Timestamp>)> {
+        self.meetings.iter().copied().find(|(start, end)| {
+            start.is_none_or(|start| start <= time) && end.is_none_or(|end| time < end)
+        })
+    }
+    /// Moves the timer's deadlines back by the time spent in meetings since the last refresh,
+    /// so that meetings neither count as work time nor use up breaks.
+    fn pause_for_meetings(&mut self, current_time: Timestamp) {
+        let Some(refreshed_at) = self.refreshed_at.replace(current_time) else {
+            return;
+        };
+        let mut paused = Duration::ZERO;
+        for (start, end) in &self.meetings {
+            let start = start.map_or(refreshed_at, |start| start.max(refreshed_at));
+            let end = end.map_or(current_time, |end| end.min(current_time));
+            if start < end {
+                paused += end - start;
+            }
+        }
+        if paused == Duration::ZERO {
+            return;
+        }
+        match &mut self.timer {
+            BreakTimer::Unlocked { since, until } => {
+                *since = *since + paused;
+                *until = until.map(|until| until + paused);
+            }
+            BreakTimer::Locked { until } => *until = *until + paused,
+            BreakTimer::Unlockable => {}
+        }
+    }
     fn refresh(&mut self, current_time: Timestamp) {
-        if let BreakTimer::Unlocked { until } = self.timer {
+        self.track_deactivated_rest(current_time);
+        self.pause_for_meetings(current_time);
+        if let BreakTimer::Unlocked {
+            since,
+            until: Some(until),
+        } = self.timer
+        {
             if current_time >= until {
+                let completed = self.finish_work_period(since, until);
+                let extension = self.repay_rest_debt();
                 self.timer = BreakTimer::Locked {
-                    until: until + self.break_duration,
+                    until: until + self.break_duration_after(until - since, completed) + extension,
                 };
             }
         }
         if let BreakTimer::Locked { until } = self.timer {
             if current_time >= until {
                 self.timer = BreakTimer::Unlockable;
+                // the intent is kept during the break, so that it can be reviewed
+                self.intent = None;
+                self.break_extension = Duration::ZERO;
+                self.break_just_ended = true;
             }
         }
     }
@@ -89,107 +589,816 @@ pub enum CurrentState {
     Unlockable,
 }
 
+/// How close the screen is to locking, so that clients can warn as deadlines approach.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pressure {
+    /// No lock is coming up soon.
+    Relaxed,
+    /// The screen will lock within `pressure_minutes`.
+    Approaching,
+    /// The screen is locked.
+    Locked,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum CurrentStateReason {
     BreakTimer,
     RequirementNotMet { id: u64 },
+    CriticalRequirementNotMet { id: u64 },
     LockedTimeRange { id: u64 },
     NoConstraints,
 }
 
+/// A bitmask of the parts of the info that changed between two versions, so that clients can
+/// skip expensive re-renders when e.g. only the countdown moved.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct ChangeKinds(u32);
+
+impl ChangeKinds {
+    pub const NONE: Self = Self(0);
+    /// `state`, `reason`, `pressure`, or `diagonator_running`
+    pub const STATE: Self = Self(1);
+    /// `until` or `next_lock`
+    pub const UNTIL: Self = Self(2);
+    pub const REQUIREMENTS: Self = Self(4);
+    pub const LOCKED_TIME_RANGES: Self = Self(8);
+    /// `deactivated_until` or `pending_deactivations`
+    pub const DEACTIVATION: Self = Self(16);
+    /// Any other field
+    pub const OTHER: Self = Self(32);
+    pub const ALL: Self = Self(63);
+    #[cfg(feature = "grpc")]
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl std::ops::BitOr for ChangeKinds {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+impl std::ops::BitOrAssign for ChangeKinds {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct CurrentInfo {
     state: CurrentState,
     until: Option<Timestamp>,
     reason: CurrentStateReason,
-    locked_time_ranges: Vec<TimeRange>,
-    requirements: Vec<Requirement>,
+    /// Shared with the earlier versions of the info while the ranges don't change, so that
+    /// cloning the info stays cheap
+    locked_time_ranges: Arc<[TimeRange]>,
+    /// Shared like `locked_time_ranges`
+    requirements: Arc<[Requirement]>,
+    requirement_groups: Vec<RequirementGroup>,
     deactivated_until: Option<Timestamp>,
     diagonator_running: bool,
+    focus_label: Option<String>,
+    intent: Option<String>,
+    crunch_until: Option<Timestamp>,
+    profile: Option<String>,
+    activity: Option<Activity>,
+    pending_deactivations: Vec<PendingDeactivation>,
+    available_update: Option<String>,
+    /// Whether the server is in maintenance mode, e.g. because saving its state failed
+    maintenance: bool,
+    exceptions: AppList,
+    last_modified: Option<Timestamp>,
+    last_modified_by: Option<String>,
+    plan: Vec<String>,
+    next_lock: Option<Timestamp>,
+    pressure: Pressure,
+    /// The incomplete requirements whose estimate doesn't fit into the unlocked time that is
+    /// left before they are due
+    at_risk_requirements: Vec<u64>,
+    /// Today's focus score so far, if a focus score is configured
+    focus_score: Option<u32>,
+    /// The rest that was missed and will lengthen the following breaks, if rest debt is
+    /// configured
+    rest_debt: Option<Duration>,
+    /// How much the current break was lengthened to pay back rest debt
+    break_extension: Option<Duration>,
+    /// The break time that was banked and can be spent on longer breaks, if break credit is
+    /// configured
+    break_credit: Option<Duration>,
+    /// How much of this week's deactivation quota is left, if a weekly quota is configured
+    deactivation_quota_remaining: Option<Duration>,
+}
+
+impl CurrentInfo {
+    pub fn state(&self) -> CurrentState {
+        self.state
+    }
+    pub fn until(&self) -> Option<Timestamp> {
+        self.until
+    }
+    pub fn reason(&self) -> &CurrentStateReason {
+        &self.reason
+    }
+    pub fn requirements(&self) -> &[Requirement] {
+        &self.requirements
+    }
+    pub fn pending_deactivations(&self) -> &[PendingDeactivation] {
+        &self.pending_deactivations
+    }
+    /// Determines which parts of the info changed since the previous version.
+    pub fn changes_since(&self, previous: &CurrentInfo) -> ChangeKinds {
+        let mut changes = ChangeKinds::NONE;
+        if self.state != previous.state
+            || self.reason != previous.reason
+            || self.pressure != previous.pressure
+            || self.diagonator_running != previous.diagonator_running
+        {
+            changes |= ChangeKinds::STATE;
+        }
+        if self.until != previous.until || self.next_lock != previous.next_lock {
+            changes |= ChangeKinds::UNTIL;
+        }
+        if self.requirements != previous.requirements
+            || self.requirement_groups != previous.requirement_groups
+            || self.at_risk_requirements != previous.at_risk_requirements
+        {
+            changes |= ChangeKinds::REQUIREMENTS;
+        }
+        if self.locked_time_ranges != previous.locked_time_ranges {
+            changes |= ChangeKinds::LOCKED_TIME_RANGES;
+        }
+        if self.deactivated_until != previous.deactivated_until
+            || self.pending_deactivations != previous.pending_deactivations
+            || self.deactivation_quota_remaining != previous.deactivation_quota_remaining
+        {
+            changes |= ChangeKinds::DEACTIVATION;
+        }
+        if self.focus_label != previous.focus_label
+            || self.intent != previous.intent
+            || self.crunch_until != previous.crunch_until
+            || self.profile != previous.profile
+            || self.activity != previous.activity
+            || self.available_update != previous.available_update
+            || self.maintenance != previous.maintenance
+            || self.exceptions != previous.exceptions
+            || self.last_modified != previous.last_modified
+            || self.last_modified_by != previous.last_modified_by
+            || self.plan != previous.plan
+            || self.focus_score != previous.focus_score
+            || self.rest_debt != previous.rest_debt
+            || self.break_extension != previous.break_extension
+            || self.break_credit != previous.break_credit
+        {
+            changes |= ChangeKinds::OTHER;
+        }
+        changes
+    }
+}
+
+/// Deserializes a field of an `InfoDelta` that is absent if it didn't change, and `null` if it
+/// changed to nothing.
+fn changed_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+fn changed<T: Clone + PartialEq>(new: &T, old: &T) -> Option<T> {
+    (new != old).then(|| new.clone())
+}
+
+/// Finds the items of a list that were added or changed, and the IDs of those that were removed.
+fn list_delta<T: Clone + PartialEq>(new: &[T], old: &[T], id: fn(&T) -> u64) -> (Vec<T>, Vec<u64>) {
+    let changed = new
+        .iter()
+        .filter(|item| !old.contains(item))
+        .cloned()
+        .collect();
+    let removed = old
+        .iter()
+        .map(id)
+        .filter(|old_id| !new.iter().any(|item| id(item) == *old_id))
+        .collect();
+    (changed, removed)
+}
+
+/// The fields of the info that changed since an earlier version, which lets clients that poll
+/// frequently avoid fetching the full lists every time. Fields that didn't change are left out,
+/// and requirements and locked time ranges are only included if they were added or changed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InfoDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<CurrentState>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    until: Option<Option<Timestamp>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<CurrentStateReason>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    locked_time_ranges: Vec<TimeRange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed_locked_time_ranges: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    requirements: Vec<Requirement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed_requirements: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    requirement_groups: Option<Vec<RequirementGroup>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    deactivated_until: Option<Option<Timestamp>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    diagonator_running: Option<bool>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    focus_label: Option<Option<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    intent: Option<Option<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    crunch_until: Option<Option<Timestamp>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    profile: Option<Option<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    activity: Option<Option<Activity>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending_deactivations: Option<Vec<PendingDeactivation>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    available_update: Option<Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    maintenance: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exceptions: Option<AppList>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    last_modified: Option<Option<Timestamp>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    last_modified_by: Option<Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plan: Option<Vec<String>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    next_lock: Option<Option<Timestamp>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pressure: Option<Pressure>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    at_risk_requirements: Option<Vec<u64>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    focus_score: Option<Option<u32>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    rest_debt: Option<Option<Duration>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    break_extension: Option<Option<Duration>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    break_credit: Option<Option<Duration>>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "changed_option"
+    )]
+    deactivation_quota_remaining: Option<Option<Duration>>,
+}
+
+impl CurrentInfo {
+    /// Determines the fields that changed since the previous version.
+    pub fn delta_since(&self, previous: &CurrentInfo) -> InfoDelta {
+        let (locked_time_ranges, removed_locked_time_ranges) = list_delta(
+            &self.locked_time_ranges,
+            &previous.locked_time_ranges,
+            |range| range.id,
+        );
+        let (requirements, removed_requirements) =
+            list_delta(&self.requirements, &previous.requirements, |req| req.id);
+        InfoDelta {
+            state: changed(&self.state, &previous.state),
+            until: changed(&self.until, &previous.until),
+            reason: changed(&self.reason, &previous.reason),
+            locked_time_ranges,
+            removed_locked_time_ranges,
+            requirements,
+            removed_requirements,
+            requirement_groups: changed(&self.requirement_groups, &previous.requirement_groups),
+            deactivated_until: changed(&self.deactivated_until, &previous.deactivated_until),
+            diagonator_running: changed(&self.diagonator_running, &previous.diagonator_running),
+            focus_label: changed(&self.focus_label, &previous.focus_label),
+            intent: changed(&self.intent, &previous.intent),
+            crunch_until: changed(&self.crunch_until, &previous.crunch_until),
+            profile: changed(&self.profile, &previous.profile),
+            activity: changed(&self.activity, &previous.activity),
+            pending_deactivations: changed(
+                &self.pending_deactivations,
+                &previous.pending_deactivations,
+            ),
+            available_update: changed(&self.available_update, &previous.available_update),
+            maintenance: changed(&self.maintenance, &previous.maintenance),
+            exceptions: changed(&self.exceptions, &previous.exceptions),
+            last_modified: changed(&self.last_modified, &previous.last_modified),
+            last_modified_by: changed(&self.last_modified_by, &previous.last_modified_by),
+            plan: changed(&self.plan, &previous.plan),
+            next_lock: changed(&self.next_lock, &previous.next_lock),
+            pressure: changed(&self.pressure, &previous.pressure),
+            at_risk_requirements: changed(
+                &self.at_risk_requirements,
+                &previous.at_risk_requirements,
+            ),
+            focus_score: changed(&self.focus_score, &previous.focus_score),
+            rest_debt: changed(&self.rest_debt, &previous.rest_debt),
+            break_extension: changed(&self.break_extension, &previous.break_extension),
+            break_credit: changed(&self.break_credit, &previous.break_credit),
+            deactivation_quota_remaining: changed(
+                &self.deactivation_quota_remaining,
+                &previous.deactivation_quota_remaining,
+            ),
+        }
+    }
+}
+
+/// The parts of the info that the gRPC interface converts field by field, some of which kiosk
+/// displays also show.
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+impl CurrentInfo {
+    pub fn locked_time_ranges(&self) -> &[TimeRange] {
+        &self.locked_time_ranges
+    }
+    pub fn deactivated_until(&self) -> Option<Timestamp> {
+        self.deactivated_until
+    }
+    pub fn focus_label(&self) -> Option<&str> {
+        self.focus_label.as_deref()
+    }
+    pub fn intent(&self) -> Option<&str> {
+        self.intent.as_deref()
+    }
+    pub fn next_lock(&self) -> Option<Timestamp> {
+        self.next_lock
+    }
+    pub fn pressure(&self) -> Pressure {
+        self.pressure
+    }
+    pub fn at_risk_requirements(&self) -> &[u64] {
+        &self.at_risk_requirements
+    }
+    pub fn plan(&self) -> &[String] {
+        &self.plan
+    }
+    pub fn focus_score(&self) -> Option<u32> {
+        self.focus_score
+    }
+    pub fn deactivation_quota_remaining(&self) -> Option<Duration> {
+        self.deactivation_quota_remaining
+    }
+}
+/// How strongly enforcement clients should block the screen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Nothing is blocked, because the server is unlocked or deactivated.
+    None,
+    /// The break is over, so the user can dismiss the block by unlocking the timer.
+    Soft,
+    /// The screen stays blocked until the state changes.
+    Hard,
+}
+
+/// The blocking policy that enforcement clients should apply right now.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    severity: Severity,
+    until: Option<Timestamp>,
+    reason: CurrentStateReason,
+    /// An empty blocklist means that everything except the allowed applications is blocked.
+    blocked: AppList,
+    allowed: AppList,
+}
+
+/// A list in the constraints that keeps the copy of itself that was last shared with the info
+/// until it is borrowed mutably, so that unchanged lists are shared between info versions
+/// without comparing them.
+#[derive(Clone)]
+struct SharedList<T> {
+    items: Vec<T>,
+    shared: Option<Arc<[T]>>,
+}
+
+impl<T: Clone> SharedList<T> {
+    fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            shared: None,
+        }
+    }
+    /// Whether the list may have changed since it was last shared.
+    fn changed(&self) -> bool {
+        self.shared.is_none()
+    }
+    /// Returns the copy of the list that was shared last, or shares a new copy made by `copy` if
+    /// the list may have changed since then.
+    fn share(&mut self, copy: impl FnOnce(&[T]) -> Vec<T>) -> Arc<[T]> {
+        let items = &self.items;
+        self.shared
+            .get_or_insert_with(|| copy(items).into())
+            .clone()
+    }
+}
+
+impl<T> Deref for SharedList<T> {
+    type Target = Vec<T>;
+    fn deref(&self) -> &Vec<T> {
+        &self.items
+    }
+}
+
+impl<T> DerefMut for SharedList<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        self.shared = None;
+        &mut self.items
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SharedList<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut SharedList<T> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.deref_mut().iter_mut()
+    }
 }
+
+impl<T: Serialize> Serialize for SharedList<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.items.serialize(serializer)
+    }
+}
+
+#[derive(Clone)]
 struct Constraints {
     break_timer: BreakTimerManager,
-    requirements: Vec<Requirement>,
-    locked_time_ranges: Vec<TimeRange>,
+    requirements: SharedList<Requirement>,
+    requirement_groups: Vec<RequirementGroupConfig>,
+    locked_time_ranges: SharedList<TimeRange>,
     deactivated_until: Option<Timestamp>,
+    crunch_until: Option<Timestamp>,
+    profile: Option<String>,
+    activity: Option<Activity>,
+    /// How far the time spent in the current activity was counted
+    activity_tracked_until: Timestamp,
+    pending_deactivations: Vec<PendingDeactivation>,
+    available_update: Option<String>,
+    maintenance: bool,
+    /// When the state was last changed by a client, and the label of that client
+    last_modification: Option<(Timestamp, String)>,
+    /// The intentions submitted with today's plan
+    plan: Vec<String>,
+    /// Kept between refreshes, so that it only sorts the state changes again when they change
+    simulator: Simulator,
 }
 
 impl Constraints {
-    fn get_current_info(&mut self, current_time: Timestamp) -> CurrentInfo {
-        self.break_timer.refresh(current_time);
-        if let Some(du) = self.deactivated_until {
-            if current_time >= du {
-                self.deactivated_until = None;
-            }
-        }
-        let mut simulator = Simulator::new();
-        // now we push the state changes into the simulator in the following order:
-        // 1. requirements
-        // 2. locked time ranges
-        // 3. break timer
-        // this ensures that if multiple state changes occur at the same time,
-        // requirements and locked time ranges will get first and second priority,
-        // respectively, when determining the reason
-        for requirement in &self.requirements {
-            if !requirement.complete {
-                simulator.push(StateChange {
-                    kind: StateChangeKind::RequirementLocked(requirement.id),
-                    time: requirement.due,
+    /// Whether the requirement no longer needs to be completed, because it is complete or
+    /// another requirement of its `any` group is.
+    fn is_satisfied(&self, req: &Requirement) -> bool {
+        let Some(name) = &req.group else {
+            return req.complete;
+        };
+        req.complete || self.is_group_satisfied(name)
+    }
+    /// Whether the group is an `any` group with a completed requirement.
+    fn is_group_satisfied(&self, name: &str) -> bool {
+        self.requirement_groups
+            .iter()
+            .any(|group| group.name == name && group.mode == Some(GroupMode::Any))
+            && self
+                .requirements
+                .iter()
+                .any(|other| other.complete && other.group.as_deref() == Some(name))
+    }
+    /// Lists the groups that today's requirements belong to.
+    fn requirement_groups(&self) -> Vec<RequirementGroup> {
+        self.requirement_groups
+            .iter()
+            .filter_map(|group| {
+                let members: Vec<&Requirement> = self
+                    .requirements
+                    .iter()
+                    .filter(|req| req.group.as_ref() == Some(&group.name))
+                    .collect();
+                let mode = group.mode.unwrap_or_default();
+                let satisfied = match mode {
+                    GroupMode::All => members.iter().all(|req| req.complete),
+                    GroupMode::Any => members.iter().any(|req| req.complete),
+                };
+                (!members.is_empty()).then(|| RequirementGroup {
+                    name: group.name.clone(),
+                    mode,
+                    requirements: members.iter().map(|req| req.id).collect(),
+                    satisfied,
                 })
+            })
+            .collect()
+    }
+    /// Lists the state changes of the requirements and locked time ranges in order of priority:
+    /// requirements come before locked time ranges, so that they get priority when determining
+    /// the reason if several state changes occur at the same time. The break timer's state
+    /// changes come after both.
+    fn constraint_changes(&self) -> Vec<StateChange> {
+        // the groups are checked once rather than for each of their requirements
+        let satisfied_groups: HashSet<&str> = self
+            .requirement_groups
+            .iter()
+            .map(|group| group.name.as_str())
+            .filter(|name| self.is_group_satisfied(name))
+            .collect();
+        let mut changes = Vec::new();
+        for requirement in &self.requirements {
+            let satisfied = requirement.complete
+                || requirement
+                    .group
+                    .as_deref()
+                    .is_some_and(|name| satisfied_groups.contains(name));
+            // expired requirements unlock before the time of the simulation, so they don't need
+            // to be left out, which would make the changes depend on the time
+            if !satisfied {
+                // critical requirements lock from the start of the day until they are completed
+                changes.push(if requirement.lock_from_start {
+                    StateChange {
+                        kind: StateChangeKind::CriticalRequirementLocked(requirement.id),
+                        time: Timestamp::ZERO,
+                    }
+                } else {
+                    StateChange {
+                        kind: StateChangeKind::RequirementLocked(requirement.id),
+                        time: requirement.due,
+                    }
+                });
+                if let Some(expires) = requirement.expires {
+                    changes.push(StateChange {
+                        kind: if requirement.lock_from_start {
+                            StateChangeKind::CriticalRequirementExpired(requirement.id)
+                        } else {
+                            StateChangeKind::RequirementExpired(requirement.id)
+                        },
+                        time: expires,
+                    })
+                }
             }
         }
         for ltr in &self.locked_time_ranges {
-            simulator.push(StateChange {
+            if ltr.kind == RangeKind::Meeting {
+                continue;
+            }
+            changes.push(StateChange {
                 kind: StateChangeKind::RangeLocked(ltr.id),
                 time: ltr.start.unwrap_or(Timestamp::ZERO),
             });
             if let Some(ltr_end) = ltr.end {
-                simulator.push(StateChange {
+                changes.push(StateChange {
                     kind: StateChangeKind::RangeUnlocked(ltr.id),
                     time: ltr_end,
                 })
             }
         }
+        changes
+    }
+    fn get_current_info(&mut self, current_time: Timestamp) -> CurrentInfo {
+        let meetings: Vec<TimeRange> = self
+            .locked_time_ranges
+            .iter()
+            .filter(|range| range.kind == RangeKind::Meeting)
+            .cloned()
+            .collect();
+        self.break_timer.meetings = merge_overlapping_ranges(meetings)
+            .into_iter()
+            .map(|range| (range.start, range.end))
+            .collect();
+        self.break_timer.deactivated_until = self.deactivated_until;
+        self.break_timer.refresh(current_time);
+        if let Some(du) = self.deactivated_until {
+            if current_time >= du {
+                self.deactivated_until = None;
+            }
+        }
+        self.pending_deactivations
+            .retain(|pending| current_time < pending.expires);
+        // the simulator keeps the state changes of the requirements and locked time ranges until
+        // either list is changed, since only then do the changes differ
+        if self.requirements.changed() || self.locked_time_ranges.changed() {
+            let changes = self.constraint_changes();
+            self.simulator.set_changes(changes);
+        }
+        // during a meeting, the break timer doesn't lock the screen and its deadlines are
+        // delayed until the meeting ends, or for the rest of the day if it doesn't end
+        let resume = match self.break_timer.current_meeting(current_time) {
+            Some((_, end)) => end,
+            None => Some(Timestamp::ZERO),
+        };
+        let delayed =
+            |time: Timestamp| resume.map(|resume| time + (resume.max(current_time) - current_time));
+        let mut break_timer_changes = Vec::new();
         match &self.break_timer.timer {
-            BreakTimer::Unlocked { until } => simulator.push(StateChange {
-                kind: StateChangeKind::BreakTimerLocked,
-                time: *until,
-            }),
+            BreakTimer::Unlocked { until, .. } => {
+                if let Some(until) = until.and_then(delayed) {
+                    break_timer_changes.push(StateChange {
+                        kind: StateChangeKind::BreakTimerLocked,
+                        time: until,
+                    })
+                }
+            }
             BreakTimer::Locked { until } => {
-                simulator.push(StateChange {
-                    kind: StateChangeKind::BreakTimerLocked,
-                    time: Timestamp::ZERO,
-                });
-                simulator.push(StateChange {
-                    kind: StateChangeKind::BreakTimerUnlockable,
-                    time: *until,
-                });
+                if let (Some(resume), Some(until)) = (resume, delayed(*until)) {
+                    break_timer_changes.push(StateChange {
+                        kind: StateChangeKind::BreakTimerLocked,
+                        time: resume,
+                    });
+                    break_timer_changes.push(StateChange {
+                        kind: StateChangeKind::BreakTimerUnlockable,
+                        time: until,
+                    });
+                }
+            }
+            BreakTimer::Unlockable => {
+                if let Some(resume) = resume {
+                    break_timer_changes.push(StateChange {
+                        kind: StateChangeKind::BreakTimerUnlockable,
+                        time: resume,
+                    })
+                }
             }
-            BreakTimer::Unlockable => simulator.push(StateChange {
-                kind: StateChangeKind::BreakTimerUnlockable,
-                time: Timestamp::ZERO,
-            }),
         }
-        let result = simulator.run(current_time);
+        let result = self.simulator.run(current_time, &break_timer_changes);
         let diagonator_running = !(matches!(result.target_state, CurrentState::Unlocked)
             || self.deactivated_until.is_some());
         CurrentInfo {
             state: result.target_state,
             until: result.until,
             reason: result.reason,
-            locked_time_ranges: self.locked_time_ranges.clone(),
-            requirements: self.requirements.clone(),
+            locked_time_ranges: self.locked_time_ranges.share(<[TimeRange]>::to_vec),
+            requirements: self.requirements.share(info_requirements),
+            requirement_groups: self.requirement_groups(),
             deactivated_until: self.deactivated_until,
             diagonator_running,
+            focus_label: self.break_timer.focus_label.clone(),
+            intent: self.break_timer.intent.clone(),
+            crunch_until: self.crunch_until,
+            profile: self.profile.clone(),
+            activity: self.activity.clone(),
+            pending_deactivations: self.pending_deactivations.clone(),
+            available_update: self.available_update.clone(),
+            maintenance: self.maintenance,
+            exceptions: AppList::default(),
+            last_modified: self.last_modification.as_ref().map(|(at, _)| *at),
+            last_modified_by: self.last_modification.as_ref().map(|(_, by)| by.clone()),
+            plan: self.plan.clone(),
+            next_lock: None,
+            pressure: Pressure::Relaxed,
+            at_risk_requirements: Vec::new(),
+            focus_score: None,
+            rest_debt: self
+                .break_timer
+                .rest_debt_config
+                .as_ref()
+                .map(|_| self.break_timer.rest_debt),
+            break_extension: Some(self.break_timer.break_extension)
+                .filter(|extension| *extension > Duration::ZERO),
+            break_credit: self
+                .break_timer
+                .break_credit_config
+                .as_ref()
+                .map(|_| self.break_timer.break_credit),
+            deactivation_quota_remaining: None,
         }
     }
-    fn complete_requirement(&mut self, id: u64) -> Result<(), String> {
+    fn complete_requirement(
+        &mut self,
+        current_time: Timestamp,
+        id: u64,
+        answers: Option<Vec<String>>,
+    ) -> Result<Vec<ReviewAnswer>, String> {
         for req in &mut self.requirements {
-            if req.id == id {
+            let alternative = req.alternatives.iter().find(|alt| alt.id == id).cloned();
+            if req.id == id || alternative.is_some() {
+                let (activity_goal, count_goal) = match &alternative {
+                    Some(alt) => (&alt.activity_goal, &alt.count_goal),
+                    None => (&req.activity_goal, &req.count_goal),
+                };
+                if alternative.is_none() && !req.alternatives.is_empty() {
+                    return Err(format!(
+                        "Requirement {} is completed by completing one of its alternatives.",
+                        id
+                    ));
+                }
+                if let Some(goal) = activity_goal {
+                    return Err(format!(
+                        "Requirement {} is completed by spending time on {}.",
+                        id, goal.activity
+                    ));
+                }
+                if count_goal.is_some() {
+                    return Err(format!(
+                        "Requirement {} is completed by incrementing it.",
+                        id
+                    ));
+                }
+                if req.source == Source::Recovery {
+                    return Err(format!(
+                        "Requirement {} is resolved by an admin with ResolveRecovery.",
+                        id
+                    ));
+                }
+                if req.source == Source::Planning {
+                    return Err(format!(
+                        "Requirement {} is completed by submitting a plan with SubmitPlan.",
+                        id
+                    ));
+                }
+                if req.is_expired(current_time) {
+                    return Err(format!("Requirement {} has expired.", id));
+                }
                 if !req.complete {
+                    let answers = answers.unwrap_or_default();
+                    if answers.len() != req.prompts.len() {
+                        return Err(format!(
+                            "Requirement {} expects {} answers but {} were given.",
+                            id,
+                            req.prompts.len(),
+                            answers.len()
+                        ));
+                    }
                     req.complete = true;
-                    return Ok(());
+                    req.completed_at = Some(current_time);
+                    req.completed_by = alternative.map(|alt| alt.name);
+                    return Ok(req
+                        .prompts
+                        .iter()
+                        .cloned()
+                        .zip(answers)
+                        .map(|(prompt, answer)| ReviewAnswer { prompt, answer })
+                        .collect());
                 } else {
                     return Err(format!("Requirement {} has already been completed.", id));
                 }
@@ -199,38 +1408,349 @@ impl Constraints {
     }
 }
 
+/// Returns the requirements with pinned requirements first, then sorted by their order,
+/// keeping the configured order between requirements with the same order.
+fn sorted_requirements(requirements: &[Requirement]) -> Vec<Requirement> {
+    let mut requirements = requirements.to_vec();
+    requirements.sort_by_key(|req| (!req.pinned, req.order));
+    requirements
+}
+
+/// Sorts the requirements for the info that is sent to clients, where the progress of activity
+/// goals is rounded down to whole minutes, so that tracking an activity only changes the info
+/// once a minute.
+fn info_requirements(requirements: &[Requirement]) -> Vec<Requirement> {
+    let mut requirements = sorted_requirements(requirements);
+    for req in &mut requirements {
+        let alternative_goals = req
+            .alternatives
+            .iter_mut()
+            .filter_map(|alternative| alternative.activity_goal.as_mut());
+        for goal in req.activity_goal.iter_mut().chain(alternative_goals) {
+            goal.progress = Duration::from_minutes(goal.progress.as_minutes());
+        }
+    }
+    requirements
+}
+
+/// A change to today's constraints whose effect can be simulated without applying it.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum HypotheticalChange {
+    CompleteRequirement {
+        id: u64,
+    },
+    AddRequirement {
+        name: String,
+        due: HourMinute,
+    },
+    AddLockedTimeRange {
+        start: Option<HourMinute>,
+        end: Option<HourMinute>,
+    },
+}
+
+/// Merges overlapping and adjacent time ranges. A merged range keeps the ID and source of its
+/// earliest range and lists the IDs and sources of all the ranges it was merged from.
+/// Merges overlapping locked time ranges. Meetings don't lock the screen, so they are only
+/// merged with each other.
+fn merge_time_ranges(ranges: &[TimeRange]) -> Vec<TimeRange> {
+    let (meetings, locks): (Vec<_>, Vec<_>) = ranges
+        .iter()
+        .cloned()
+        .partition(|range| range.kind == RangeKind::Meeting);
+    let mut merged = merge_overlapping_ranges(locks);
+    merged.extend(merge_overlapping_ranges(meetings));
+    merged.sort_by_key(|range| range.start);
+    merged
+}
+
+fn merge_overlapping_ranges(mut ranges: Vec<TimeRange>) -> Vec<TimeRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<TimeRange> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last)
+                if last
+                    .end
+                    .is_none_or(|end| range.start.is_none_or(|start| start <= end)) =>
+            {
+                if last.merged_ids.is_empty() {
+                    last.merged_ids.push(last.id);
+                    last.merged_sources.push(last.source.clone());
+                }
+                last.merged_ids.push(range.id);
+                last.merged_sources.push(range.source);
+                last.exceptions.merge(&range.exceptions);
+                last.end = last.end.zip(range.end).map(|(end, other)| end.max(other));
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DayPreview {
+    date: String,
+    requirements: Vec<Requirement>,
+    locked_time_ranges: Vec<TimeRange>,
+    crunch_mode: bool,
+    profile: Option<String>,
+}
+
+/// The part of the manager's state that is saved to the state file, so that restarting the
+/// server doesn't reset the day.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SavedState {
+    version: u32,
+    date: String,
+    last_id: u64,
+    timer: BreakTimer,
+    completed_work_periods: u64,
+    focus_label: Option<String>,
+    intent: Option<String>,
+    requirements: Vec<Requirement>,
+    locked_time_ranges: Vec<TimeRange>,
+    deactivated_until: Option<Timestamp>,
+    crunch_until: Option<Timestamp>,
+    profile: Option<String>,
+    activity: Option<Activity>,
+    pending_deactivations: Vec<PendingDeactivation>,
+    stats: Vec<DayStats>,
+    #[serde(default)]
+    last_modification: Option<(Timestamp, String)>,
+    #[serde(default)]
+    plan: Vec<String>,
+    #[serde(default)]
+    rest_debt: Duration,
+    #[serde(default)]
+    break_extension: Duration,
+    #[serde(default)]
+    break_credit: Duration,
+    #[serde(default)]
+    work_day_started: bool,
+}
+
+impl SavedState {
+    /// Incremented whenever the saved state changes in an incompatible way.
+    const VERSION: u32 = 1;
+}
+
 pub struct DiagonatorManager {
     manager: DiagonatorManagerInner,
     cached_info: CurrentInfo,
     cache_time: Timestamp,
     cache_version: u64,
+    /// The parts of the info that changed in each of the latest versions
+    recent_changes: VecDeque<(u64, ChangeKinds)>,
+    /// The info of the versions before the current one, oldest first, which deltas are
+    /// computed against
+    recent_infos: VecDeque<(u64, CurrentInfo)>,
+    /// The latest info and its cache version, which are sent to every subscriber whenever the
+    /// cache version is incremented
+    info_updates: watch::Sender<(CurrentInfo, u64)>,
+    /// When the current state began, or when the server started if it hasn't changed since
+    state_since: Timestamp,
 }
 
 impl DiagonatorManager {
     pub const NO_CACHE: u64 = 0;
+    /// The number of versions whose changes are remembered. Callers that are further behind
+    /// are told that everything changed.
+    const MAX_RECENT_CHANGES: usize = 64;
+    /// The number of earlier versions whose info is remembered for deltas. Callers that are
+    /// further behind are sent the full info.
+    const MAX_RECENT_INFOS: usize = 16;
     pub fn new(config: DiagonatorManagerConfig, current_time: Timestamp) -> Self {
         let mut manager = DiagonatorManagerInner::new(config);
         let cached_info = manager.refresh(current_time);
+        let (info_updates, _) = watch::channel((cached_info.clone(), Self::NO_CACHE + 1));
         Self {
             manager,
             cached_info,
             cache_time: current_time,
             cache_version: Self::NO_CACHE + 1,
+            recent_changes: VecDeque::new(),
+            recent_infos: VecDeque::new(),
+            info_updates,
+            state_since: current_time,
+        }
+    }
+    /// Subscribes to the info, so that connection handlers can push it to their clients as soon
+    /// as it changes. A subscriber that falls behind only sees the latest info.
+    pub fn subscribe(&self) -> watch::Receiver<(CurrentInfo, u64)> {
+        self.info_updates.subscribe()
+    }
+    pub fn save_state(&self) -> SavedState {
+        let inner = &self.manager;
+        let constraints = &inner.constraints;
+        SavedState {
+            version: SavedState::VERSION,
+            date: inner.current_date.format("%Y-%m-%d").to_string(),
+            last_id: inner.id_generator.last_id,
+            timer: constraints.break_timer.timer.clone(),
+            completed_work_periods: constraints.break_timer.completed_work_periods,
+            focus_label: constraints.break_timer.focus_label.clone(),
+            intent: constraints.break_timer.intent.clone(),
+            requirements: constraints.requirements.to_vec(),
+            locked_time_ranges: constraints.locked_time_ranges.to_vec(),
+            deactivated_until: constraints.deactivated_until,
+            crunch_until: constraints.crunch_until,
+            profile: constraints.profile.clone(),
+            activity: constraints.activity.clone(),
+            pending_deactivations: constraints.pending_deactivations.clone(),
+            stats: inner.stats.days().to_vec(),
+            last_modification: constraints.last_modification.clone(),
+            plan: constraints.plan.clone(),
+            rest_debt: constraints.break_timer.rest_debt,
+            break_extension: constraints.break_timer.break_extension,
+            break_credit: constraints.break_timer.break_credit,
+            work_day_started: inner.work_day_started,
+        }
+    }
+    /// Replaces the state with a saved state. The state is left unchanged if the saved state
+    /// can't be restored.
+    pub fn restore_state(
+        &mut self,
+        current_time: Timestamp,
+        saved: SavedState,
+    ) -> Result<(), String> {
+        if saved.version != SavedState::VERSION {
+            return Err(format!(
+                "the state was saved in version {} of the format, but version {} is expected",
+                saved.version,
+                SavedState::VERSION
+            ));
+        }
+        let Some(date) = parse_date(&saved.date) else {
+            return Err(format!("the saved date '{}' is invalid", saved.date));
+        };
+        let inner = &mut self.manager;
+        inner.current_date = date;
+        inner.id_generator.last_id = saved.last_id;
+        inner.stats = Stats::from_days(saved.stats);
+        inner.work_day_started = saved.work_day_started;
+        inner.events.clear();
+        let constraints = &mut inner.constraints;
+        constraints.break_timer.timer = saved.timer;
+        constraints.break_timer.completed_work_periods = saved.completed_work_periods;
+        constraints.break_timer.focus_label = saved.focus_label;
+        constraints.break_timer.intent = saved.intent;
+        constraints.break_timer.rest_debt = saved.rest_debt;
+        constraints.break_timer.break_extension = saved.break_extension;
+        constraints.break_timer.break_credit = saved.break_credit;
+        *constraints.requirements = saved.requirements;
+        *constraints.locked_time_ranges = saved.locked_time_ranges;
+        constraints.deactivated_until = saved.deactivated_until;
+        constraints.crunch_until = saved.crunch_until;
+        constraints.profile = saved.profile;
+        // the time while the server wasn't running isn't counted towards the activity
+        constraints.activity = saved.activity;
+        constraints.activity_tracked_until = current_time;
+        constraints.pending_deactivations = saved.pending_deactivations;
+        constraints.last_modification = saved.last_modification;
+        constraints.plan = saved.plan;
+        inner.update_timer_durations();
+        // the requirements' progress before the restart was already announced
+        inner.skip_requirement_announcements(current_time);
+        self.refresh_cache(current_time);
+        Ok(())
+    }
+    /// Applies the configured behavior after the saved state couldn't be restored.
+    pub fn recover(&mut self, current_time: Timestamp, recovery: StateRecovery) {
+        match recovery {
+            StateRecovery::FailOpen => {}
+            StateRecovery::FailClosed => self.manager.fail_closed(),
+            StateRecovery::AskAdmin => {
+                let id = self.manager.id_generator.next_id();
+                let mut requirement = Requirement::new(
+                    id,
+                    "Wait for an admin to resolve the state recovery".to_owned(),
+                    current_time,
+                    Source::Recovery,
+                );
+                requirement.lock_from_start = true;
+                self.manager.constraints.requirements.push(requirement);
+            }
+        }
+        self.refresh_cache(current_time);
+    }
+    /// Submits today's plan, which completes the planning gate. A plan needs at least one
+    /// intention, unless a requirement was already added for today.
+    pub fn submit_plan(&mut self, current_time: Timestamp, intentions: Vec<String>) -> Response {
+        self.refresh_cache(current_time);
+        let intentions: Vec<String> = intentions
+            .iter()
+            .map(|intention| intention.trim())
+            .filter(|intention| !intention.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let constraints = &mut self.manager.constraints;
+        let added_requirement = constraints
+            .requirements
+            .iter()
+            .any(|req| matches!(req.source, Source::Command { .. }));
+        if intentions.is_empty() && !added_requirement {
+            return Response::Error {
+                msg: "A plan needs at least one intention or a requirement added for today."
+                    .to_owned(),
+                code: None,
+            };
+        }
+        constraints.plan = intentions;
+        for req in &mut constraints.requirements {
+            if req.source == Source::Planning && !req.complete {
+                req.complete = true;
+                req.completed_at = Some(current_time);
+            }
+        }
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    pub fn resolve_recovery(&mut self, current_time: Timestamp, fail_open: bool) -> Response {
+        self.refresh_cache(current_time);
+        let requirements = &mut self.manager.constraints.requirements;
+        let count = requirements.len();
+        requirements.retain(|req| req.source != Source::Recovery);
+        if requirements.len() == count {
+            return Response::Error {
+                msg: "No state recovery is pending.".to_owned(),
+                code: None,
+            };
         }
+        if !fail_open {
+            self.manager.fail_closed();
+        }
+        self.refresh_cache(current_time);
+        Response::Success
     }
-    pub fn unlock_timer(&mut self, current_time: Timestamp) -> Response {
+    pub fn unlock_timer(&mut self, current_time: Timestamp, intent: Option<String>) -> Response {
         let info = self.refresh_cache(current_time);
+        let intent = intent.filter(|intent| !intent.trim().is_empty());
+        if self.manager.config.require_intent && intent.is_none() {
+            return Response::Error {
+                msg: "An intent is required to unlock the break timer.".to_owned(),
+                code: Some(RefusalCode::IntentRequired),
+            };
+        }
         if matches!(info.state, CurrentState::Unlockable) {
-            match self.manager.constraints.break_timer.unlock(current_time) {
+            match self
+                .manager
+                .constraints
+                .break_timer
+                .unlock(current_time, intent)
+            {
                 Ok(()) => {
                     self.refresh_cache(current_time);
                     Response::Success
                 }
-                Err(msg) => Response::Error { msg },
+                Err(msg) => Response::Error { msg, code: None },
             }
         } else {
             Response::Error {
                 msg: "Session is not unlockable.".to_owned(),
+                code: Some(RefusalCode::NotUnlockable),
             }
         }
     }
@@ -242,47 +1762,399 @@ impl DiagonatorManager {
                 self.refresh_cache(current_time);
                 Response::Success
             }
-            Err(msg) => Response::Error { msg },
+            Err(msg) => Response::Error { msg, code: None },
         }
     }
-    pub fn get_info(&self) -> CurrentInfo {
-        self.cached_info.clone()
+    /// Ends the current break early and banks the rest of it as break credit. The break is only
+    /// shortened by as much as the bank has room for, and the part of it that pays back rest debt
+    /// can't be banked.
+    pub fn end_break(&mut self, current_time: Timestamp) -> Response {
+        self.refresh_cache(current_time);
+        let break_timer = &mut self.manager.constraints.break_timer;
+        let Some(config) = &break_timer.break_credit_config else {
+            return Response::Error {
+                msg: "Break credit is not configured.".to_owned(),
+                code: None,
+            };
+        };
+        let BreakTimer::Locked { until } = break_timer.timer else {
+            return Response::Error {
+                msg: "There is no break to end.".to_owned(),
+                code: None,
+            };
+        };
+        let unpaid = (until - current_time).as_seconds() - break_timer.break_extension.as_seconds();
+        if unpaid <= 0 {
+            return Response::Error {
+                msg: "The rest of this break pays back rest debt, so it can't be ended early."
+                    .to_owned(),
+                code: None,
+            };
+        }
+        let room = config.max_minutes * 60 - break_timer.break_credit.as_seconds();
+        if room <= 0 {
+            return Response::Error {
+                msg: "The break credit bank is full, so the break can't be ended early.".to_owned(),
+                code: Some(RefusalCode::BreakCreditFull),
+            };
+        }
+        let banked = unpaid.min(room);
+        break_timer.break_credit += Duration::from_seconds(banked);
+        break_timer.timer = BreakTimer::Locked {
+            until: Timestamp::from_seconds(until.as_seconds() - banked),
+        };
+        self.refresh_cache(current_time);
+        Response::Success
     }
+    /// Spends banked break credit to lengthen the current break, or to start a break if the
+    /// timer is unlockable.
+    pub fn spend_break_credit(&mut self, current_time: Timestamp, duration: Duration) -> Response {
+        self.refresh_cache(current_time);
+        let break_timer = &mut self.manager.constraints.break_timer;
+        if break_timer.break_credit_config.is_none() {
+            return Response::Error {
+                msg: "Break credit is not configured.".to_owned(),
+                code: None,
+            };
+        }
+        if duration <= Duration::ZERO {
+            return Response::Error {
+                msg: "The duration must be positive.".to_owned(),
+                code: None,
+            };
+        }
+        if duration > break_timer.break_credit {
+            return Response::Error {
+                msg: format!(
+                    "Only {} minutes of break credit are left.",
+                    break_timer.break_credit.as_minutes()
+                ),
+                code: None,
+            };
+        }
+        let until = match break_timer.timer {
+            BreakTimer::Locked { until } => until + duration,
+            BreakTimer::Unlockable => current_time + duration,
+            BreakTimer::Unlocked { .. } => {
+                return Response::Error {
+                    msg: "Break credit can only be spent during a break or while the timer is unlockable.".to_owned(),
+                    code: None,
+                };
+            }
+        };
+        break_timer.break_credit =
+            Duration::from_seconds(break_timer.break_credit.as_seconds() - duration.as_seconds());
+        break_timer.timer = BreakTimer::Locked { until };
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    /// Returns the info if it changed since the given version, along with its version and the
+    /// parts that changed since the given version.
     pub fn get_info_if_changed(
         &mut self,
         cache_version: u64,
         current_time: Timestamp,
-    ) -> Option<(CurrentInfo, u64)> {
+    ) -> Option<(CurrentInfo, u64, ChangeKinds)> {
         if current_time != self.cache_time {
             self.refresh_cache(current_time);
         }
         if cache_version != self.cache_version {
-            Some((self.cached_info.clone(), self.cache_version))
+            Some((
+                self.cached_info.clone(),
+                self.cache_version,
+                self.changes_since(cache_version),
+            ))
         } else {
             None
         }
     }
-    pub fn get_info_once(&mut self, current_time: Timestamp) -> Response {
-        Response::Info {
-            info: self.refresh_cache(current_time),
+    /// The next time at which the info is known to change without a request, such as the end of
+    /// a work period, break, deactivation, or locked time range, or when a requirement becomes due
+    /// or expires.
+    pub fn next_deadline(&self) -> Option<Timestamp> {
+        let info = &self.cached_info;
+        let requirements = info
+            .requirements
+            .iter()
+            .filter(|req| !req.complete)
+            .flat_map(|req| [Some(req.due), req.expires]);
+        let pending_deactivations = info
+            .pending_deactivations
+            .iter()
+            .map(|pending| Some(pending.expires));
+        [
+            info.until,
+            info.next_lock,
+            info.deactivated_until,
+            info.crunch_until,
+        ]
+        .into_iter()
+        .chain(requirements)
+        .chain(pending_deactivations)
+        .flatten()
+        .filter(|time| *time > self.cache_time)
+        .min()
+    }
+    fn changes_since(&self, cache_version: u64) -> ChangeKinds {
+        let remembered = self
+            .recent_changes
+            .front()
+            .is_some_and(|(oldest, _)| *oldest <= cache_version + 1);
+        if cache_version == Self::NO_CACHE || !remembered {
+            return ChangeKinds::ALL;
+        }
+        self.recent_changes
+            .iter()
+            .filter(|(version, _)| *version > cache_version)
+            .fold(ChangeKinds::NONE, |changes, (_, kinds)| changes | *kinds)
+    }
+    /// Returns the cached info if it is already up to date at the given time, so that it can be
+    /// read without exclusive access to the manager.
+    pub fn cached_info(&self, current_time: Timestamp) -> Option<&CurrentInfo> {
+        (current_time == self.cache_time).then_some(&self.cached_info)
+    }
+    /// Returns what kiosk clients show for the current info.
+    pub fn get_display_info(&mut self, current_time: Timestamp) -> Response {
+        let info = self.refresh_cache(current_time);
+        Response::DisplayInfo {
+            info: DisplayInfo::new(&info, self.state_since, current_time),
+        }
+    }
+    /// Returns the fields that changed since the given version, or the full info if that
+    /// version is no longer remembered.
+    pub fn get_info_delta(&mut self, current_time: Timestamp, cache_version: u64) -> Response {
+        self.refresh_cache(current_time);
+        let previous = if cache_version == self.cache_version {
+            Some(&self.cached_info)
+        } else {
+            self.recent_infos
+                .iter()
+                .find(|(version, _)| *version == cache_version)
+                .map(|(_, info)| info)
+        };
+        match previous {
+            Some(previous) => Response::InfoDelta {
+                delta: Box::new(self.cached_info.delta_since(previous)),
+                cache_version: self.cache_version,
+                changes: self.changes_since(cache_version),
+            },
+            None => Response::InfoUpdate {
+                info: Box::new(self.cached_info.clone()),
+                cache_version: self.cache_version,
+                changes: ChangeKinds::ALL,
+            },
+        }
+    }
+    pub fn get_current_info(&mut self, current_time: Timestamp) -> CurrentInfo {
+        self.refresh_cache(current_time)
+    }
+    pub fn get_policy(&mut self, current_time: Timestamp) -> Response {
+        let info = self.refresh_cache(current_time);
+        let severity = match info.state {
+            _ if !info.diagonator_running => Severity::None,
+            CurrentState::Unlocked => Severity::None,
+            CurrentState::Unlockable => Severity::Soft,
+            CurrentState::Locked => Severity::Hard,
+        };
+        Response::Policy {
+            policy: Policy {
+                severity,
+                until: info.until,
+                reason: info.reason,
+                blocked: self.manager.config.blocklist.clone(),
+                allowed: info.exceptions,
+            },
         }
     }
     pub fn complete_requirement(
         &mut self,
         current_time: Timestamp,
         requirement_id: u64,
+        answers: Option<Vec<String>>,
     ) -> Response {
         self.refresh_cache(current_time);
         match self
             .manager
             .constraints
-            .complete_requirement(requirement_id)
+            .complete_requirement(current_time, requirement_id, answers)
         {
-            Ok(()) => {
+            Ok(review_answers) => {
+                self.manager.stats.record_review_answers(review_answers);
                 self.refresh_cache(current_time);
                 Response::Success
             }
-            Err(msg) => Response::Error { msg },
+            Err(msg) => Response::Error { msg, code: None },
+        }
+    }
+    pub fn complete_requirement_by_name(
+        &mut self,
+        current_time: Timestamp,
+        name: String,
+        answers: Option<Vec<String>>,
+    ) -> Response {
+        self.refresh_cache(current_time);
+        match self.manager.find_incomplete_requirement(&name) {
+            Some(id) => self.complete_requirement(current_time, id, answers),
+            None => Response::Error {
+                msg: format!("There is no incomplete requirement named '{}'.", name),
+                code: None,
+            },
+        }
+    }
+    pub fn increment_requirement(&mut self, current_time: Timestamp, name: String) -> Response {
+        self.refresh_cache(current_time);
+        let Some(req) = self
+            .manager
+            .constraints
+            .requirements
+            .iter_mut()
+            .find(|req| {
+                !req.complete
+                    && !req.is_expired(current_time)
+                    && (req.name == name && req.count_goal.is_some()
+                        || req
+                            .alternatives
+                            .iter()
+                            .any(|alt| alt.name == name && alt.count_goal.is_some()))
+            })
+        else {
+            return Response::Error {
+                msg: format!(
+                    "There is no incomplete requirement named '{}' with a count.",
+                    name
+                ),
+                code: None,
+            };
+        };
+        let (goal, alternative) = match req
+            .alternatives
+            .iter_mut()
+            .find(|alt| alt.name == name && alt.count_goal.is_some())
+        {
+            Some(alt) => (&mut alt.count_goal, Some(alt.name.clone())),
+            None => (&mut req.count_goal, None),
+        };
+        if let Some(goal) = goal {
+            goal.count += 1;
+            if goal.count >= goal.target {
+                req.complete = true;
+                req.completed_at = Some(current_time);
+                req.completed_by = alternative;
+            }
+        }
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    /// Shows the requirements and locked time ranges that the configuration creates on the
+    /// given date, without changing today's constraints.
+    pub fn preview_day(&mut self, current_time: Timestamp, date: LocalDate) -> Response {
+        self.refresh_cache(current_time);
+        let inner = &self.manager;
+        let mut id_generator = IdGenerator::new();
+        let mut builder = ConstraintBuilder {
+            date: &date,
+            location: inner.config.location.as_ref(),
+            id_generator: &mut id_generator,
+        };
+        let (mut requirements, mut locked_time_ranges) = builder.day(&inner.config);
+        // the profile schedule decides the profile of future days
+        let profile = inner
+            .config
+            .scheduled_profile(&date)
+            .unwrap_or_else(|| inner.constraints.profile.clone());
+        if let Some(profile) = inner.config.profile(profile.as_deref()) {
+            let (profile_requirements, profile_ranges) = builder.profile(profile);
+            requirements.extend(profile_requirements);
+            locked_time_ranges.extend(profile_ranges);
+        }
+        let start_of_day = Timestamp::from_date_hm(&date, &HourMinute::START_OF_DAY);
+        let crunch_mode = inner
+            .constraints
+            .crunch_until
+            .is_some_and(|crunch_until| start_of_day < crunch_until);
+        if let (true, Some(crunch)) = (crunch_mode, &inner.config.crunch_mode) {
+            let (crunch_requirements, crunch_ranges) = builder.crunch_mode(crunch);
+            requirements.extend(crunch_requirements);
+            locked_time_ranges.extend(crunch_ranges);
+        }
+        Response::DayPreview {
+            preview: DayPreview {
+                date: date.format("%Y-%m-%d").to_string(),
+                requirements: sorted_requirements(&requirements),
+                locked_time_ranges,
+                crunch_mode,
+                profile,
+            },
+        }
+    }
+    /// Returns the information that clients would receive if the changes were applied,
+    /// without applying them.
+    pub fn simulate_change(
+        &mut self,
+        current_time: Timestamp,
+        changes: Vec<HypotheticalChange>,
+    ) -> Response {
+        self.refresh_cache(current_time);
+        let inner = &self.manager;
+        let mut constraints = inner.constraints.clone();
+        let mut id_generator = inner.id_generator.clone();
+        let resolve =
+            |hm: Option<HourMinute>| hm.map(|hm| Timestamp::from_date_hm(&inner.current_date, &hm));
+        for change in changes {
+            match change {
+                HypotheticalChange::CompleteRequirement { id } => {
+                    match constraints.requirements.iter_mut().find(|req| req.id == id) {
+                        Some(req) => {
+                            req.complete = true;
+                            req.completed_at = Some(current_time);
+                        }
+                        None => {
+                            return Response::Error {
+                                msg: format!("Requirement {} not found.", id),
+                                code: None,
+                            }
+                        }
+                    }
+                }
+                HypotheticalChange::AddRequirement { name, due } => {
+                    constraints.requirements.push(Requirement::new(
+                        id_generator.next_id(),
+                        name,
+                        Timestamp::from_date_hm(&inner.current_date, &due),
+                        Source::Hypothetical,
+                    ))
+                }
+                HypotheticalChange::AddLockedTimeRange { start, end } => {
+                    constraints.locked_time_ranges.push(TimeRange {
+                        id: id_generator.next_id(),
+                        start: resolve(start),
+                        end: resolve(end),
+                        source: Source::Hypothetical,
+                        kind: RangeKind::Lock,
+                        exceptions: AppList::default(),
+                        merged_ids: Vec::new(),
+                        merged_sources: Vec::new(),
+                    })
+                }
+            }
+        }
+        let mut info = constraints.get_current_info(current_time);
+        // the real refresh would lock an unlocked break timer as soon as diagonator runs
+        if info.diagonator_running && constraints.break_timer.lock(current_time).is_ok() {
+            info = constraints.get_current_info(current_time);
+        }
+        Response::Info {
+            info: Box::new(info),
+        }
+    }
+    pub fn list_requirements(&mut self, current_time: Timestamp, all: bool) -> Response {
+        let info = self.refresh_cache(current_time);
+        Response::Requirements {
+            requirements: if all {
+                sorted_requirements(&self.manager.constraints.requirements)
+            } else {
+                info.requirements.to_vec()
+            },
         }
     }
     pub fn add_requirement(
@@ -290,28 +2162,407 @@ impl DiagonatorManager {
         current_time: Timestamp,
         name: String,
         due: HourMinute,
+        client: String,
     ) -> Response {
         self.refresh_cache(current_time);
-        self.manager.constraints.requirements.push(Requirement {
-            id: self.manager.id_generator.next_id(),
+        let id = self.manager.id_generator.next_id();
+        let source = Source::Command { client };
+        self.manager.constraints.requirements.push(Requirement::new(
+            id,
+            name.clone(),
+            Timestamp::from_date_hm(&self.manager.current_date, &due),
+            source.clone(),
+        ));
+        self.manager
+            .events
+            .push(Event::RequirementAdded { id, name, source });
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    pub fn start_focus(&mut self, current_time: Timestamp, label: String) -> Response {
+        self.refresh_cache(current_time);
+        match self
+            .manager
+            .constraints
+            .break_timer
+            .start_focus(current_time, label)
+        {
+            Ok(()) => {
+                self.refresh_cache(current_time);
+                Response::Success
+            }
+            Err(msg) => Response::Error { msg, code: None },
+        }
+    }
+    pub fn get_stats(&mut self, current_time: Timestamp) -> Response {
+        self.refresh_cache(current_time);
+        let inner = &self.manager;
+        let completed_today = inner
+            .constraints
+            .requirements
+            .iter()
+            .filter(|req| req.complete)
+            .count();
+        let compare = |days_ago| {
+            inner.stats.compare(
+                &inner.current_date,
+                completed_today,
+                &(inner.current_date - chrono::Duration::days(days_ago)),
+            )
+        };
+        Response::Stats {
+            days: inner.stats.days().to_vec(),
+            vs_yesterday: compare(1),
+            vs_last_week: compare(7),
+            this_week: inner
+                .stats
+                .week(&inner.current_week_start(), completed_today),
+        }
+    }
+    pub fn get_day_stats(&mut self, current_time: Timestamp, date: &LocalDate) -> Option<DayStats> {
+        self.refresh_cache(current_time);
+        self.manager.stats.day(date).cloned()
+    }
+    pub fn start_crunch_mode(&mut self, current_time: Timestamp, days: u32) -> Response {
+        if self.manager.config.crunch_mode.is_none() {
+            return Response::Error {
+                msg: "Crunch mode is not configured.".to_owned(),
+                code: None,
+            };
+        }
+        if days == 0 {
+            return Response::Error {
+                msg: "Crunch mode must last at least 1 day.".to_owned(),
+                code: None,
+            };
+        }
+        self.refresh_cache(current_time);
+        let Some(end_date) = self
+            .manager
+            .current_date
+            .checked_add_signed(chrono::Duration::days(days.into()))
+        else {
+            return Response::Error {
+                msg: format!("Crunch mode can't last {} days.", days),
+                code: None,
+            };
+        };
+        let already_active = self.manager.constraints.crunch_until.is_some();
+        self.manager.constraints.crunch_until = Some(Timestamp::from_date_hm(
+            &end_date,
+            &HourMinute::START_OF_DAY,
+        ));
+        if !already_active {
+            self.manager.apply_crunch_mode();
+        }
+        eprintln!("Crunch mode is active for {} days", days);
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    pub fn set_preset(&mut self, current_time: Timestamp, name: String) -> Response {
+        let Some((work_period_duration, break_duration)) = self
+            .manager
+            .config
+            .presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .map(|preset| {
+                (
+                    Duration::from_minutes(preset.work_period_minutes),
+                    Duration::from_minutes(preset.break_minutes),
+                )
+            })
+        else {
+            return Response::Error {
+                msg: format!("Unknown timer preset '{}'.", name),
+                code: None,
+            };
+        };
+        self.refresh_cache(current_time);
+        if self.manager.constraints.crunch_until.is_some() {
+            return Response::Error {
+                msg: "The timer preset cannot be changed in crunch mode.".to_owned(),
+                code: Some(RefusalCode::PresetInCrunchMode),
+            };
+        }
+        let config = &mut self.manager.config;
+        config.work_period_duration = work_period_duration;
+        config.break_duration = break_duration;
+        self.manager.update_timer_durations();
+        eprintln!("Using timer preset '{}'", name);
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    /// Switches to the profile of the first rule that matches the reported Wi-Fi network or
+    /// location, or to the base configuration if no rule matches.
+    pub fn report_location(
+        &mut self,
+        current_time: Timestamp,
+        ssid: Option<String>,
+        location: Option<String>,
+    ) -> Response {
+        self.refresh_cache(current_time);
+        let profile = self
+            .manager
+            .config
+            .profile_rules
+            .iter()
+            .find(|rule| rule.matches(ssid.as_deref(), location.as_deref()))
+            .map(|rule| rule.profile.clone());
+        if profile != self.manager.constraints.profile {
+            self.manager.switch_profile(profile);
+            self.refresh_cache(current_time);
+        }
+        Response::Success
+    }
+    pub fn begin_activity(&mut self, current_time: Timestamp, name: String) -> Response {
+        self.refresh_cache(current_time);
+        let constraints = &mut self.manager.constraints;
+        constraints.activity = Some(Activity {
             name,
-            due: Timestamp::from_date_hm(&self.manager.current_date, &due),
-            complete: false,
+            since: current_time,
         });
+        constraints.activity_tracked_until = current_time;
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    pub fn end_activity(&mut self, current_time: Timestamp) -> Response {
+        self.refresh_cache(current_time);
+        if self.manager.constraints.activity.take().is_none() {
+            return Response::Error {
+                msg: "No activity is in progress.".to_owned(),
+                code: None,
+            };
+        }
         self.refresh_cache(current_time);
         Response::Success
     }
     pub fn deactivate(&mut self, current_time: Timestamp, duration: Duration) -> Response {
+        if self.manager.constraints.crunch_until.is_some() {
+            return Response::Error {
+                msg: "Deactivation is not allowed in crunch mode.".to_owned(),
+                code: Some(RefusalCode::DeactivationInCrunchMode),
+            };
+        }
+        if let Some(max) = self.manager.config.max_deactivation {
+            if duration > max {
+                return Response::Error {
+                    msg: format!(
+                        "Deactivation may last at most {} minutes.",
+                        max.as_minutes()
+                    ),
+                    code: Some(RefusalCode::DeactivationTooLong),
+                };
+            }
+        }
+        // the break is rest until the deactivation starts
+        self.refresh_cache(current_time);
+        if let Some(refusal) = self.manager.check_deactivation_quota(duration) {
+            return refusal;
+        }
+        if let Some(timeout) = self.manager.config.deactivation_approval_timeout {
+            let id = self.manager.id_generator.next_id();
+            self.manager
+                .constraints
+                .pending_deactivations
+                .push(PendingDeactivation {
+                    id,
+                    duration,
+                    expires: current_time + timeout,
+                });
+            self.manager
+                .events
+                .push(Event::DeactivationRequested { id, duration });
+            self.refresh_cache(current_time);
+            return Response::Pending { id };
+        }
+        self.manager.constraints.deactivated_until = Some(current_time + duration);
+        self.refresh_cache(current_time);
+        self.manager.stats.record_deactivation(duration);
+        Response::Success
+    }
+    pub fn approve_deactivation(&mut self, current_time: Timestamp, id: u64) -> Response {
+        self.refresh_cache(current_time);
+        let Some(duration) = self
+            .manager
+            .constraints
+            .pending_deactivations
+            .iter()
+            .find(|pending| pending.id == id)
+            .map(|pending| pending.duration)
+        else {
+            return Response::Error {
+                msg: format!("Pending deactivation {} not found.", id),
+                code: None,
+            };
+        };
+        // refusals leave the deactivation pending, so that it can be approved later
+        if self.manager.constraints.crunch_until.is_some() {
+            return Response::Error {
+                msg: "Deactivation is not allowed in crunch mode.".to_owned(),
+                code: Some(RefusalCode::DeactivationInCrunchMode),
+            };
+        }
+        if let Some(refusal) = self.manager.check_deactivation_quota(duration) {
+            return refusal;
+        }
+        self.manager
+            .constraints
+            .pending_deactivations
+            .retain(|pending| pending.id != id);
         self.manager.constraints.deactivated_until = Some(current_time + duration);
         self.refresh_cache(current_time);
+        self.manager.stats.record_deactivation(duration);
+        Response::Success
+    }
+    pub fn reject_deactivation(&mut self, current_time: Timestamp, id: u64) -> Response {
+        self.refresh_cache(current_time);
+        match self.take_pending_deactivation(id) {
+            Ok(_) => {
+                self.refresh_cache(current_time);
+                Response::Success
+            }
+            Err(msg) => Response::Error { msg, code: None },
+        }
+    }
+    fn take_pending_deactivation(&mut self, id: u64) -> Result<PendingDeactivation, String> {
+        let pending = &mut self.manager.constraints.pending_deactivations;
+        match pending.iter().position(|pending| pending.id == id) {
+            Some(index) => Ok(pending.remove(index)),
+            None => Err(format!("Pending deactivation {} not found.", id)),
+        }
+    }
+    pub fn report_activity(&mut self, current_time: Timestamp) -> Response {
+        self.refresh_cache(current_time);
+        let break_timer = &mut self.manager.constraints.break_timer;
+        if std::mem::take(&mut break_timer.awaiting_activity) {
+            if let Err(msg) = break_timer.unlock(current_time, None) {
+                return Response::Error { msg, code: None };
+            }
+            self.refresh_cache(current_time);
+        }
         Response::Success
     }
+    pub fn get_timer(&mut self, current_time: Timestamp) -> Response {
+        self.refresh_cache(current_time);
+        let break_timer = &self.manager.constraints.break_timer;
+        Response::Timer {
+            info: TimerInfo {
+                timer: break_timer.timer.clone(),
+                completed_work_periods: break_timer.completed_work_periods,
+            },
+        }
+    }
+    pub fn set_timer_state(&mut self, current_time: Timestamp, timer: BreakTimer) -> Response {
+        if let BreakTimer::Unlocked {
+            since,
+            until: Some(until),
+        } = timer
+        {
+            if since > until {
+                return Response::Error {
+                    msg: "Work period cannot end before it starts.".to_owned(),
+                    code: None,
+                };
+            }
+        }
+        self.refresh_cache(current_time);
+        eprintln!("Break timer state set to {:?}", timer);
+        self.manager
+            .constraints
+            .break_timer
+            .set_timer(current_time, timer);
+        self.refresh_cache(current_time);
+        Response::Success
+    }
+    /// Returns the full internal state for debugging.
+    pub fn dump_state(&self) -> serde_json::Value {
+        let inner = &self.manager;
+        let constraints = &inner.constraints;
+        let break_timer = &constraints.break_timer;
+        json!({
+            "current_date": inner.current_date.to_string(),
+            "cache_version": self.cache_version,
+            "cache_time": self.cache_time,
+            "cached_info": self.cached_info,
+            "timer": break_timer.timer,
+            "work_period_duration": break_timer.work_period_duration,
+            "break_duration": break_timer.break_duration,
+            "completed_work_periods": break_timer.completed_work_periods,
+            "focus_label": break_timer.focus_label,
+            "intent": break_timer.intent,
+            "awaiting_activity": break_timer.awaiting_activity,
+            "requirements": constraints.requirements,
+            "locked_time_ranges": constraints.locked_time_ranges,
+            "deactivated_until": constraints.deactivated_until,
+            "crunch_until": constraints.crunch_until,
+            "pending_deactivations": constraints.pending_deactivations,
+            "pending_events": inner.events,
+        })
+    }
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.manager.events)
+    }
+    /// Returns the work period and break durations that `SetPreset` last switched to, or the
+    /// configured ones, before crunch mode and profiles override them.
+    pub fn base_timer_durations(&self) -> (Duration, Duration) {
+        let config = &self.manager.config;
+        (config.work_period_duration, config.break_duration)
+    }
+    /// Returns the message that the user configured for refusals with the given code.
+    pub fn refusal_message(&self, code: RefusalCode) -> Option<&str> {
+        self.manager
+            .config
+            .refusal_messages
+            .get(&code)
+            .map(String::as_str)
+    }
+    /// Replaces the configuration while the server is running. Today's requirements and locked
+    /// time ranges are recreated from the new configuration.
+    pub fn set_config(&mut self, current_time: Timestamp, config: DiagonatorManagerConfig) {
+        self.refresh_cache(current_time);
+        self.manager.config = config;
+        self.manager.rebuild_constraints();
+        self.refresh_cache(current_time);
+    }
+    /// Records that a client changed the state, so that clients can show who did it and when.
+    pub fn record_modification(&mut self, current_time: Timestamp, client: String) {
+        self.manager.constraints.last_modification = Some((current_time, client));
+        self.refresh_cache(current_time);
+    }
+    pub fn set_available_update(&mut self, current_time: Timestamp, version: Option<String>) {
+        self.manager.constraints.available_update = version;
+        self.refresh_cache(current_time);
+    }
+    pub fn set_maintenance(&mut self, current_time: Timestamp, enabled: bool) {
+        self.manager.constraints.maintenance = enabled;
+        self.refresh_cache(current_time);
+    }
     fn refresh_cache(&mut self, current_time: Timestamp) -> CurrentInfo {
         self.cache_time = current_time;
         let new_info = self.manager.refresh(current_time);
+        if new_info.state != self.cached_info.state {
+            // the state changed when it was due to, unless it changed early
+            self.state_since = match self.cached_info.until {
+                Some(until) if until <= current_time => until,
+                _ => current_time,
+            };
+        }
         if new_info != self.cached_info {
-            self.cached_info = new_info.clone();
+            let changes = new_info.changes_since(&self.cached_info);
+            let previous_info = std::mem::replace(&mut self.cached_info, new_info.clone());
+            if self.recent_infos.len() == Self::MAX_RECENT_INFOS {
+                self.recent_infos.pop_front();
+            }
+            self.recent_infos
+                .push_back((self.cache_version, previous_info));
             self.cache_version += 1;
+            if self.recent_changes.len() == Self::MAX_RECENT_CHANGES {
+                self.recent_changes.pop_front();
+            }
+            self.recent_changes.push_back((self.cache_version, changes));
+            self.info_updates
+                .send_replace((new_info.clone(), self.cache_version));
         }
         new_info
     }
@@ -322,53 +2573,313 @@ struct DiagonatorManagerInner {
     constraints: Constraints,
     current_date: LocalDate,
     id_generator: IdGenerator,
+    stats: Stats,
+    events: Vec<Event>,
+    announced_break_end: Option<Timestamp>,
+    announced_day_end: Option<LocalDate>,
+    /// The last announced status of each of today's requirements
+    requirement_statuses: HashMap<u64, RequirementStatus>,
+    /// Whether today's work day was started, either by `auto_unlock_at` or by hand
+    work_day_started: bool,
 }
 
 impl DiagonatorManagerInner {
     pub fn new(config: DiagonatorManagerConfig) -> Self {
-        let break_timer =
-            BreakTimerManager::new(config.work_period_duration, config.break_duration);
+        let break_timer = BreakTimerManager::new(
+            config.work_period_duration,
+            config.break_duration,
+            config.min_work_period_duration,
+            config.stopwatch.clone(),
+        );
         Self {
             config,
             constraints: Constraints {
                 break_timer,
-                requirements: Vec::new(),
-                locked_time_ranges: Vec::new(),
+                requirements: SharedList::new(),
+                requirement_groups: Vec::new(),
+                locked_time_ranges: SharedList::new(),
                 deactivated_until: None,
+                crunch_until: None,
+                profile: None,
+                activity: None,
+                activity_tracked_until: Timestamp::default(),
+                pending_deactivations: Vec::new(),
+                available_update: None,
+                maintenance: false,
+                last_modification: None,
+                plan: Vec::new(),
+                simulator: Simulator::new(),
             },
             current_date: Timestamp::ZERO.get_date(),
             id_generator: IdGenerator::new(),
+            stats: Stats::new(),
+            events: Vec::new(),
+            announced_break_end: None,
+            announced_day_end: None,
+            requirement_statuses: HashMap::new(),
+            work_day_started: false,
+        }
+    }
+    /// Locks until all of today's incomplete requirements are completed again, since their
+    /// progress was lost.
+    fn fail_closed(&mut self) {
+        for req in &mut self.constraints.requirements {
+            if !req.complete {
+                req.lock_from_start = true;
+            }
+        }
+    }
+    /// Whether the work day should start by itself, because it is past `auto_unlock_at` and the
+    /// work day hasn't started yet. It starts once nothing else keeps the server locked.
+    fn auto_unlock_due(&self, current_time: Timestamp) -> bool {
+        self.config.auto_unlock_at.as_ref().is_some_and(|at| {
+            !self.work_day_started
+                && current_time >= Timestamp::from_date_hm(&self.current_date, at)
+        })
+    }
+    /// The first day of the current week, according to `week_starts_on`.
+    fn current_week_start(&self) -> LocalDate {
+        start_of_week(&self.current_date, self.config.week_start)
+    }
+    /// How much of this week's deactivation quota is left, if a weekly quota is configured.
+    fn remaining_deactivation_quota(&self) -> Option<Duration> {
+        let quota = self.config.weekly_deactivation_quota?;
+        let used = self.stats.deactivated_since(&self.current_week_start());
+        Some(Duration::from_seconds(
+            (quota.as_seconds() - used.as_seconds()).max(0),
+        ))
+    }
+    /// The refusal of a deactivation that would exceed the weekly deactivation quota, if it would.
+    fn check_deactivation_quota(&self, duration: Duration) -> Option<Response> {
+        let remaining = self.remaining_deactivation_quota()?;
+        if duration <= remaining {
+            return None;
         }
+        let next_week_start = self.current_week_start() + chrono::Duration::days(7);
+        Some(Response::QuotaExceeded {
+            msg: format!(
+                "Only {} minutes of this week's deactivation quota are left.",
+                remaining.as_minutes()
+            ),
+            remaining,
+            resets_at: Timestamp::from_date_hm(&next_week_start, &HourMinute::START_OF_DAY),
+        })
     }
     fn new_day(&mut self) {
-        self.constraints.requirements = self
+        // a night's sleep makes up for the rest that was missed
+        self.constraints.break_timer.rest_debt = Duration::ZERO;
+        // break credit is only banked for the day
+        self.constraints.break_timer.break_credit = Duration::ZERO;
+        self.work_day_started = false;
+        if let Some(score) = self.focus_score(None) {
+            self.stats.record_focus_score(score);
+        }
+        for req in &self.constraints.requirements {
+            // requirements that were satisfied by another requirement of their group weren't
+            // missed
+            if req.complete || !self.constraints.is_satisfied(req) {
+                self.stats
+                    .record_requirement(&req.name, req.complete, req.completed_by.as_deref());
+            }
+        }
+        self.stats.new_day(&self.current_date);
+        self.constraints.break_timer.completed_work_periods = 0;
+        self.constraints.plan.clear();
+        if let Some(profile) = self.config.scheduled_profile(&self.current_date) {
+            if profile != self.constraints.profile {
+                match &profile {
+                    Some(name) => eprintln!("Switching to profile '{}' for today", name),
+                    None => eprintln!("Switching to the base configuration for today"),
+                }
+                self.constraints.profile = profile.clone();
+                self.events.push(Event::ProfileChanged { profile });
+            }
+        }
+        if let Some(crunch_until) = self.constraints.crunch_until {
+            if Timestamp::from_date_hm(&self.current_date, &HourMinute::START_OF_DAY)
+                >= crunch_until
+            {
+                eprintln!("Crunch mode has ended");
+                self.constraints.crunch_until = None;
+            }
+        }
+        self.build_constraints();
+    }
+    /// Creates today's requirements and locked time ranges from the configuration.
+    fn build_constraints(&mut self) {
+        let mut builder = ConstraintBuilder {
+            date: &self.current_date,
+            location: self.config.location.as_ref(),
+            id_generator: &mut self.id_generator,
+        };
+        let (requirements, locked_time_ranges) = builder.day(&self.config);
+        *self.constraints.requirements = requirements;
+        self.constraints.requirement_groups = self.config.requirement_groups.clone();
+        *self.constraints.locked_time_ranges = locked_time_ranges;
+        self.apply_profile();
+        if self.constraints.crunch_until.is_some() {
+            self.apply_crunch_mode();
+        }
+        self.apply_planning_gate();
+        self.update_timer_durations();
+    }
+    /// Adds a requirement that locks the screen until the day is planned if the active profile or
+    /// the base configuration enables the planning gate, and removes it otherwise unless the
+    /// day was already planned.
+    fn apply_planning_gate(&mut self) {
+        let enabled = self
             .config
-            .requirements
+            .profile(self.constraints.profile.as_deref())
+            .and_then(|profile| profile.planning_gate)
+            .unwrap_or(self.config.planning_gate);
+        let requirements = &mut self.constraints.requirements;
+        if !enabled {
+            requirements.retain(|req| req.source != Source::Planning || req.complete);
+        } else if !requirements
             .iter()
-            .map(|req| Requirement {
-                id: self.id_generator.next_id(),
-                name: req.name.clone(),
-                due: Timestamp::from_date_hm(&self.current_date, &req.due),
-                complete: false,
-            })
-            .collect();
-        self.constraints.locked_time_ranges = self
+            .any(|req| req.source == Source::Planning)
+        {
+            let mut requirement = Requirement::new(
+                self.id_generator.next_id(),
+                "Plan the day".to_owned(),
+                Timestamp::from_date_hm(&self.current_date, &HourMinute::START_OF_DAY),
+                Source::Planning,
+            );
+            requirement.lock_from_start = true;
+            requirements.push(requirement);
+        }
+    }
+    /// Recreates today's requirements and locked time ranges after the configuration changed.
+    /// Requirements added by clients or for a state recovery are kept, and requirements that
+    /// still exist keep their ID and progress.
+    fn rebuild_constraints(&mut self) {
+        let old_requirements = std::mem::take(&mut *self.constraints.requirements);
+        // rebuilding doesn't end a deactivation like starting crunch mode does
+        let deactivated_until = self.constraints.deactivated_until;
+        self.build_constraints();
+        self.constraints.deactivated_until = deactivated_until;
+        for req in &mut self.constraints.requirements {
+            if let Some(old) = old_requirements
+                .iter()
+                .find(|old| old.name == req.name && old.source == req.source)
+            {
+                *req = Requirement {
+                    due: req.due,
+                    prompts: std::mem::take(&mut req.prompts),
+                    order: req.order,
+                    pinned: req.pinned,
+                    lock_from_start: req.lock_from_start,
+                    group: req.group.take(),
+                    ..old.clone()
+                };
+            }
+        }
+        self.constraints.requirements.extend(
+            old_requirements
+                .into_iter()
+                .filter(|req| matches!(req.source, Source::Command { .. } | Source::Recovery)),
+        );
+    }
+    /// Overlays the active profile's configuration onto today's constraints.
+    fn apply_profile(&mut self) {
+        let Some(profile) = self.config.profile(self.constraints.profile.as_deref()) else {
+            return;
+        };
+        let mut builder = ConstraintBuilder {
+            date: &self.current_date,
+            location: self.config.location.as_ref(),
+            id_generator: &mut self.id_generator,
+        };
+        let (requirements, locked_time_ranges) = builder.profile(profile);
+        self.constraints.requirements.extend(requirements);
+        self.constraints
+            .locked_time_ranges
+            .extend(locked_time_ranges);
+    }
+    /// Replaces the active profile's requirements and locked time ranges with those of another
+    /// profile, or removes them if `profile` is `None`.
+    fn switch_profile(&mut self, profile: Option<String>) {
+        let from_profile = |source: &Source| matches!(source, Source::Profile { .. });
+        self.constraints
+            .requirements
+            .retain(|req| !from_profile(&req.source));
+        self.constraints
+            .locked_time_ranges
+            .retain(|ltr| !from_profile(&ltr.source));
+        match &profile {
+            Some(name) => eprintln!("Switching to profile '{}'", name),
+            None => eprintln!("Switching to the base configuration"),
+        }
+        self.constraints.profile = profile.clone();
+        self.apply_profile();
+        self.apply_planning_gate();
+        self.update_timer_durations();
+        self.events.push(Event::ProfileChanged { profile });
+    }
+    /// Uses the work period and break durations of crunch mode, the active profile, or the base
+    /// configuration, in that order of precedence.
+    fn update_timer_durations(&mut self) {
+        let crunch = self
             .config
+            .crunch_mode
+            .as_ref()
+            .filter(|_| self.constraints.crunch_until.is_some());
+        let profile = self.config.profile(self.constraints.profile.as_deref());
+        let work_period_minutes = crunch
+            .and_then(|crunch| crunch.work_period_minutes)
+            .or(profile.and_then(|profile| profile.work_period_minutes));
+        let break_minutes = crunch
+            .and_then(|crunch| crunch.break_minutes)
+            .or(profile.and_then(|profile| profile.break_minutes));
+        let work_period_duration =
+            work_period_minutes.map_or(self.config.work_period_duration, Duration::from_minutes);
+        let break_duration =
+            break_minutes.map_or(self.config.break_duration, Duration::from_minutes);
+        let break_timer = &mut self.constraints.break_timer;
+        break_timer.work_period_duration = work_period_duration;
+        break_timer.break_duration = break_duration;
+        break_timer.long_break = self.config.long_break;
+        break_timer.rest_debt_config = self.config.rest_debt.clone();
+        break_timer.break_credit_config = self.config.break_credit.clone();
+    }
+    /// Overlays the crunch mode configuration onto today's constraints.
+    fn apply_crunch_mode(&mut self) {
+        let Some(crunch) = &self.config.crunch_mode else {
+            return;
+        };
+        let mut builder = ConstraintBuilder {
+            date: &self.current_date,
+            location: self.config.location.as_ref(),
+            id_generator: &mut self.id_generator,
+        };
+        let (requirements, locked_time_ranges) = builder.crunch_mode(crunch);
+        self.constraints.requirements.extend(requirements);
+        self.constraints
             .locked_time_ranges
-            .iter()
-            .map(|ltr| TimeRange {
-                id: self.id_generator.next_id(),
-                start: Timestamp::from_date_hm_opt(&self.current_date, &ltr.start),
-                end: Timestamp::from_date_hm_opt(&self.current_date, &ltr.end),
-            })
-            .collect();
+            .extend(locked_time_ranges);
+        self.update_timer_durations();
+        self.constraints.deactivated_until = None;
     }
     fn refresh(&mut self, current_time: Timestamp) -> CurrentInfo {
+        self.track_activity(current_time);
         let current_date = current_time.get_date();
         if current_date != self.current_date {
+            // starting the server isn't announced as the start of a new day
+            let starting_up = self.current_date == Timestamp::ZERO.get_date();
+            self.announce_missed_requirements();
             self.current_date = current_date;
             self.new_day();
+            if starting_up {
+                // neither are the requirements that were already due when the server started
+                self.skip_requirement_announcements(current_time);
+            } else {
+                self.events.push(Event::DayStarted {
+                    date: current_date.format("%Y-%m-%d").to_string(),
+                    requirements: self.constraints.requirements.to_vec(),
+                });
+            }
         }
+        self.announce_day_end(current_time);
         let mut current_info = self.constraints.get_current_info(current_time);
 
         if current_info.diagonator_running {
@@ -377,17 +2888,593 @@ impl DiagonatorManagerInner {
                 current_info = self.constraints.get_current_info(current_time);
             }
         }
+        if std::mem::take(&mut self.constraints.break_timer.break_just_ended)
+            && self.config.auto_unlock_after_break
+            && matches!(current_info.state, CurrentState::Unlockable)
+        {
+            if self.config.auto_unlock_requires_activity {
+                // the work period starts when the next ReportActivity request arrives
+                self.constraints.break_timer.awaiting_activity = true;
+            } else if let Ok(()) = self.constraints.break_timer.unlock(current_time, None) {
+                current_info = self.constraints.get_current_info(current_time);
+            }
+        }
+        if self.auto_unlock_due(current_time) {
+            if !matches!(self.constraints.break_timer.timer, BreakTimer::Unlockable) {
+                // the work day was already started by hand
+                self.work_day_started = true;
+            } else if matches!(current_info.state, CurrentState::Unlockable) {
+                self.work_day_started = true;
+                if self.config.auto_unlock_requires_activity {
+                    self.constraints.break_timer.awaiting_activity = true;
+                } else if let Ok(()) = self.constraints.break_timer.unlock(current_time, None) {
+                    current_info = self.constraints.get_current_info(current_time);
+                }
+            }
+        }
+        if !matches!(current_info.state, CurrentState::Unlockable) {
+            self.constraints.break_timer.awaiting_activity = false;
+        }
+        for period in self.constraints.break_timer.finished_work_periods.drain(..) {
+            self.events.push(Event::WorkPeriodCompleted {
+                start: period.start,
+                end: period.end,
+                label: period.label.clone(),
+                intent: period.intent.clone(),
+                too_short: period.too_short,
+            });
+            self.stats.record_work_period(period);
+        }
+        self.announce_break_end(current_time);
+        self.announce_requirements(current_time);
+        current_info.exceptions = self.current_exceptions(&current_info);
+        current_info.next_lock = next_lock(&current_info);
+        current_info.pressure = match current_info.next_lock {
+            _ if current_info.diagonator_running
+                && matches!(current_info.state, CurrentState::Locked) =>
+            {
+                Pressure::Locked
+            }
+            Some(next_lock) if next_lock <= current_time + self.config.pressure_duration => {
+                Pressure::Approaching
+            }
+            _ => Pressure::Relaxed,
+        };
+        current_info.at_risk_requirements = self.at_risk_requirements(current_time, &current_info);
+        current_info.focus_score = self.focus_score(Some(current_time));
+        current_info.deactivation_quota_remaining = self.remaining_deactivation_quota();
+        if self.config.merge_locked_time_ranges {
+            current_info.locked_time_ranges =
+                merge_time_ranges(&current_info.locked_time_ranges).into();
+        }
+        if let Some(hide_after) = self.config.hide_completed_requirements_after {
+            current_info.requirements = current_info
+                .requirements
+                .iter()
+                .filter(|req| {
+                    req.completed_at
+                        .is_none_or(|completed_at| current_time < completed_at + hide_after)
+                })
+                .cloned()
+                .collect();
+        }
+        current_info
+    }
+    /// Combines the configured exceptions with those of the locked time range that is
+    /// currently locking the screen, if any.
+    fn current_exceptions(&self, current_info: &CurrentInfo) -> AppList {
+        let mut exceptions = self.config.exceptions.clone();
+        if let (CurrentState::Locked, CurrentStateReason::LockedTimeRange { id }) =
+            (current_info.state, &current_info.reason)
+        {
+            if let Some(range) = self
+                .constraints
+                .locked_time_ranges
+                .iter()
+                .find(|range| range.id == *id)
+            {
+                exceptions.merge(&range.exceptions);
+            }
+        }
+        exceptions
+    }
+    /// Finds the requirements with an estimate that is longer than the time that will still be
+    /// unlocked before they are due. The unlocked time excludes the current lock and locked time
+    /// ranges, and only counts the share of it that the break timer leaves for work.
+    fn at_risk_requirements(
+        &self,
+        current_time: Timestamp,
+        current_info: &CurrentInfo,
+    ) -> Vec<u64> {
+        let locked =
+            current_info.diagonator_running && matches!(current_info.state, CurrentState::Locked);
+        let work = self.config.work_period_duration.as_seconds();
+        let break_seconds = self.config.break_duration.as_seconds();
+        // each long break adds its extra length to one of its cycles of work periods
+        let long_break_share = self.config.long_break.map_or(0, |(long_break, periods)| {
+            (long_break.as_seconds() - break_seconds).max(0) / periods as i64
+        });
+        let cycle = work + break_seconds + long_break_share;
+        let locked_time_ranges = merge_overlapping_ranges(
+            current_info
+                .locked_time_ranges
+                .iter()
+                .filter(|range| range.kind == RangeKind::Lock)
+                .cloned()
+                .collect(),
+        );
         current_info
+            .requirements
+            .iter()
+            .filter(|req| {
+                let Some(estimate) = req.estimate else {
+                    return false;
+                };
+                // critical requirements are completed while the screen is locked anyway
+                if self.constraints.is_satisfied(req)
+                    || req.lock_from_start
+                    || req.is_expired(current_time)
+                    || req.due <= current_time
+                {
+                    return false;
+                }
+                let start = match (locked, current_info.until) {
+                    (true, Some(until)) => until.max(current_time),
+                    (true, None) => return true,
+                    (false, _) => current_time,
+                };
+                let mut unlocked = (req.due - start).as_seconds().max(0);
+                for range in &locked_time_ranges {
+                    let range_start = range.start.unwrap_or(Timestamp::ZERO).max(start);
+                    let range_end = range.end.map_or(req.due, |end| end.min(req.due));
+                    if range_start < range_end {
+                        unlocked -= (range_end - range_start).as_seconds();
+                    }
+                }
+                if cycle > 0 {
+                    unlocked = unlocked.max(0) * work / cycle;
+                }
+                unlocked < estimate.as_seconds()
+            })
+            .map(|req| req.id)
+            .collect()
+    }
+    /// Finds the ID of an incomplete requirement with the name, or of an alternative with the
+    /// name of an incomplete requirement.
+    /// Scores today so far, counting the requirements that are complete or already due, or the
+    /// whole day once it is over if `current_time` is `None`.
+    fn focus_score(&self, current_time: Option<Timestamp>) -> Option<u32> {
+        let config = self.config.focus_score.as_ref()?;
+        let today = self.stats.today()?;
+        // requirements that were satisfied by another requirement of their group aren't counted
+        let due: Vec<&Requirement> = self
+            .constraints
+            .requirements
+            .iter()
+            .filter(|req| req.complete || !self.constraints.is_satisfied(req))
+            .filter(|req| req.complete || current_time.is_none_or(|time| time >= req.due))
+            .collect();
+        let on_time = due
+            .iter()
+            .filter(|req| req.completed_at.is_some_and(|at| at <= req.due))
+            .count();
+        Some(today.compute_focus_score(config, on_time, due.len()))
+    }
+    fn find_incomplete_requirement(&self, name: &str) -> Option<u64> {
+        self.constraints
+            .requirements
+            .iter()
+            .filter(|req| !req.complete)
+            .find_map(|req| {
+                if req.name == name {
+                    return Some(req.id);
+                }
+                req.alternatives
+                    .iter()
+                    .find(|alt| alt.name == name)
+                    .map(|alt| alt.id)
+            })
+    }
+    /// Adds the time spent in the current activity since it was last tracked to the statistics
+    /// and to the progress of the requirements for that activity.
+    fn track_activity(&mut self, current_time: Timestamp) {
+        let Some(activity) = &self.constraints.activity else {
+            return;
+        };
+        let tracked_until = &mut self.constraints.activity_tracked_until;
+        if current_time <= *tracked_until {
+            return;
+        }
+        let elapsed = current_time - *tracked_until;
+        *tracked_until = current_time;
+        self.stats.record_activity_time(&activity.name, elapsed);
+        for req in &mut self.constraints.requirements {
+            // expired requirements were missed, so they can't be completed anymore
+            if req.complete || req.is_expired(current_time) {
+                continue;
+            }
+            let goals = req.activity_goal.iter_mut().map(|goal| (goal, None)).chain(
+                req.alternatives.iter_mut().filter_map(
+                    |Alternative {
+                         name,
+                         activity_goal,
+                         ..
+                     }| {
+                        activity_goal
+                            .as_mut()
+                            .map(|goal| (goal, Some(name.clone())))
+                    },
+                ),
+            );
+            let mut completed_by = None;
+            for (goal, alternative) in goals {
+                if goal.activity != activity.name {
+                    continue;
+                }
+                goal.progress += elapsed;
+                if goal.progress >= goal.duration && completed_by.is_none() {
+                    completed_by = Some(alternative);
+                }
+            }
+            if let Some(alternative) = completed_by {
+                req.complete = true;
+                req.completed_at = Some(current_time);
+                req.completed_by = alternative;
+            }
+        }
+    }
+    fn announce_break_end(&mut self, current_time: Timestamp) {
+        if let (Some(warn_before), BreakTimer::Locked { until }) = (
+            self.config.warn_before_break_end,
+            &self.constraints.break_timer.timer,
+        ) {
+            if current_time + warn_before >= *until && self.announced_break_end != Some(*until) {
+                self.announced_break_end = Some(*until);
+                self.events.push(Event::BreakEndingSoon { at: *until });
+            }
+        }
+    }
+    /// Announces the requirements that became due, were completed, or expired since they were
+    /// last announced.
+    fn announce_requirements(&mut self, current_time: Timestamp) {
+        for req in &self.constraints.requirements {
+            let status = req.status(current_time, self.constraints.is_satisfied(req));
+            if self.requirement_statuses.insert(req.id, status) == Some(status) {
+                continue;
+            }
+            let (id, name) = (req.id, req.name.clone());
+            match status {
+                RequirementStatus::Pending => {}
+                RequirementStatus::Due => self.events.push(Event::RequirementDue {
+                    id,
+                    name,
+                    due: req.due,
+                }),
+                RequirementStatus::Complete => {
+                    self.events.push(Event::RequirementCompleted { id, name })
+                }
+                RequirementStatus::Missed => {
+                    self.events.push(Event::RequirementMissed { id, name })
+                }
+            }
+        }
+    }
+    /// Announces the requirements that are still incomplete when the day ends, unless they
+    /// already expired.
+    fn announce_missed_requirements(&mut self) {
+        for req in &self.constraints.requirements {
+            if !self.constraints.is_satisfied(req)
+                && self.requirement_statuses.get(&req.id) != Some(&RequirementStatus::Missed)
+            {
+                self.events.push(Event::RequirementMissed {
+                    id: req.id,
+                    name: req.name.clone(),
+                });
+            }
+        }
+        self.requirement_statuses.clear();
+    }
+    /// Takes the current status of every requirement as already announced.
+    fn skip_requirement_announcements(&mut self, current_time: Timestamp) {
+        let constraints = &self.constraints;
+        self.requirement_statuses = constraints
+            .requirements
+            .iter()
+            .map(|req| {
+                (
+                    req.id,
+                    req.status(current_time, constraints.is_satisfied(req)),
+                )
+            })
+            .collect();
+    }
+    /// Gives a final warning shortly before midnight, listing the requirements that will be
+    /// missed unless they are completed before the day ends.
+    fn announce_day_end(&mut self, current_time: Timestamp) {
+        let day_end = Timestamp::from_date_hm(&self.current_date.succ(), &HourMinute::START_OF_DAY);
+        if current_time + self.config.warn_before_day_end >= day_end
+            && self.announced_day_end != Some(self.current_date)
+        {
+            self.announced_day_end = Some(self.current_date);
+            let missed_requirements = self
+                .constraints
+                .requirements
+                .iter()
+                .filter(|req| !self.constraints.is_satisfied(req))
+                .cloned()
+                .collect();
+            self.events.push(Event::DayEnding {
+                at: day_end,
+                missed_requirements,
+            });
+        }
+    }
+}
+
+/// Determines when the screen will lock next. This is an absolute time rather than a countdown,
+/// so that the info doesn't change every second.
+fn next_lock(info: &CurrentInfo) -> Option<Timestamp> {
+    match (info.state, info.deactivated_until) {
+        // the lock resumes when the deactivation ends
+        (CurrentState::Locked, deactivated_until) => deactivated_until,
+        (_, Some(deactivated_until)) => info.until.map(|until| until.max(deactivated_until)),
+        (_, None) => info.until,
+    }
+}
+
+/// Creates today's requirements and locked time ranges from their configuration.
+struct ConstraintBuilder<'a> {
+    date: &'a LocalDate,
+    location: Option<&'a Location>,
+    id_generator: &'a mut IdGenerator,
+}
+
+impl ConstraintBuilder<'_> {
+    /// Creates the requirements and locked time ranges that apply outside of crunch mode,
+    /// taking into account the day overrides for this date.
+    fn day(&mut self, config: &DiagonatorManagerConfig) -> (Vec<Requirement>, Vec<TimeRange>) {
+        let date = self.date.format("%Y-%m-%d").to_string();
+        let overrides: Vec<&DayOverrideConfig> = config
+            .day_overrides
+            .iter()
+            .filter(|day_override| day_override.date == date)
+            .collect();
+        let skipped = |name: &str| {
+            overrides.iter().any(|day_override| {
+                day_override
+                    .skip_requirements
+                    .iter()
+                    .flatten()
+                    .any(|skipped| skipped == name)
+            })
+        };
+        let mut requirements: Vec<Requirement> = config
+            .requirements
+            .iter()
+            .filter(|req| !skipped(&req.name))
+            .map(|req| self.requirement(req, Source::Config))
+            .collect();
+        for day_override in &overrides {
+            requirements.extend(
+                day_override
+                    .requirements
+                    .iter()
+                    .flatten()
+                    .map(|req| self.requirement(req, Source::DayOverride)),
+            );
+        }
+        if let Some(review) = config
+            .daily_review
+            .as_ref()
+            .filter(|review| !skipped(review.name.as_deref().unwrap_or("Daily review")))
+        {
+            requirements.push(Requirement {
+                prompts: review.prompts.clone(),
+                ..Requirement::new(
+                    self.id_generator.next_id(),
+                    review
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| "Daily review".to_owned()),
+                    Timestamp::from_date_hm(self.date, &review.due),
+                    Source::DailyReview,
+                )
+            });
+        }
+        let weekly_ranges = config
+            .weekly_template
+            .as_ref()
+            .map(|template| template.for_weekday(self.date.weekday()))
+            .unwrap_or_default();
+        let override_ranges: Vec<&LockedTimeRangeConfig> = overrides
+            .iter()
+            .filter_map(|day_override| day_override.locked_time_ranges.as_ref())
+            .flatten()
+            .collect();
+        let replaces_ranges = overrides
+            .iter()
+            .any(|day_override| day_override.locked_time_ranges.is_some());
+        let locked_time_ranges = if replaces_ranges {
+            override_ranges
+                .into_iter()
+                .filter_map(|ltr| self.time_range(ltr, Source::DayOverride))
+                .collect()
+        } else {
+            config
+                .locked_time_ranges
+                .iter()
+                .map(|ltr| (ltr, Source::Config))
+                .chain(
+                    weekly_ranges
+                        .iter()
+                        .map(|ltr| (ltr, Source::WeeklyTemplate)),
+                )
+                .filter_map(|(ltr, source)| self.time_range(ltr, source))
+                .collect()
+        };
+        (requirements, locked_time_ranges)
+    }
+    /// Creates the additional requirements and locked time ranges of crunch mode.
+    fn crunch_mode(&mut self, crunch: &CrunchModeConfig) -> (Vec<Requirement>, Vec<TimeRange>) {
+        self.additions(
+            &crunch.requirements,
+            &crunch.locked_time_ranges,
+            Source::CrunchMode,
+        )
+    }
+    /// Creates the additional requirements and locked time ranges of a profile.
+    fn profile(&mut self, profile: &ProfileConfig) -> (Vec<Requirement>, Vec<TimeRange>) {
+        self.additions(
+            &profile.requirements,
+            &profile.locked_time_ranges,
+            Source::Profile {
+                name: profile.name.clone(),
+            },
+        )
+    }
+    fn additions(
+        &mut self,
+        requirements: &Option<Vec<RequirementConfig>>,
+        locked_time_ranges: &Option<Vec<LockedTimeRangeConfig>>,
+        source: Source,
+    ) -> (Vec<Requirement>, Vec<TimeRange>) {
+        let requirements = requirements
+            .iter()
+            .flatten()
+            .map(|req| self.requirement(req, source.clone()))
+            .collect();
+        let locked_time_ranges = locked_time_ranges
+            .iter()
+            .flatten()
+            .filter_map(|ltr| self.time_range(ltr, source.clone()))
+            .collect();
+        (requirements, locked_time_ranges)
+    }
+    fn requirement(&mut self, req: &RequirementConfig, source: Source) -> Requirement {
+        let id = self.id_generator.next_id();
+        let alternatives = req
+            .alternatives
+            .iter()
+            .flatten()
+            .map(|alt| Alternative {
+                id: self.id_generator.next_id(),
+                name: alt.name.clone(),
+                activity_goal: activity_goal(&alt.activity, alt.activity_minutes),
+                count_goal: alt.count.map(|target| CountGoal { target, count: 0 }),
+            })
+            .collect();
+        Requirement {
+            order: req.order.unwrap_or(0),
+            pinned: req.pinned.unwrap_or(false),
+            lock_from_start: req.lock_from_start.unwrap_or(false),
+            activity_goal: activity_goal(&req.activity, req.activity_minutes),
+            count_goal: req.count.map(|target| CountGoal { target, count: 0 }),
+            expires: req
+                .expires
+                .as_ref()
+                .map(|expires| Timestamp::from_date_hm(self.date, expires)),
+            estimate: req.estimate_minutes.map(Duration::from_minutes),
+            group: req.group.clone(),
+            alternatives,
+            ..Requirement::new(
+                id,
+                req.name.clone(),
+                Timestamp::from_date_hm(self.date, &req.due),
+                source,
+            )
+        }
+    }
+    fn time_range(&mut self, ltr: &LockedTimeRangeConfig, source: Source) -> Option<TimeRange> {
+        let resolve = |time: &Option<TimeOfDay>| match time {
+            Some(time) => Timestamp::from_date_time_of_day(self.date, time, self.location)
+                .map(Some)
+                .ok_or(()),
+            None => Ok(None),
+        };
+        match (resolve(&ltr.start), resolve(&ltr.end)) {
+            (Ok(start), Ok(end)) => Some(TimeRange {
+                id: self.id_generator.next_id(),
+                start,
+                end,
+                source,
+                kind: ltr.kind.unwrap_or_default(),
+                exceptions: AppList::from_config(ltr.exceptions.as_ref()),
+                merged_ids: Vec::new(),
+                merged_sources: Vec::new(),
+            }),
+            _ => {
+                eprintln!(
+                    "Skipping locked time range because the sun does not rise or set on {}",
+                    self.date
+                );
+                None
+            }
+        }
     }
 }
 
 pub struct DiagonatorManagerConfig {
     pub requirements: Vec<RequirementConfig>,
+    pub requirement_groups: Vec<RequirementGroupConfig>,
     pub locked_time_ranges: Vec<LockedTimeRangeConfig>,
     pub work_period_duration: Duration,
     pub break_duration: Duration,
+    /// The length of the long break and the number of completed work periods it follows
+    pub long_break: Option<(Duration, u64)>,
+    pub location: Option<Location>,
+    pub require_intent: bool,
+    pub planning_gate: bool,
+    pub daily_review: Option<DailyReviewConfig>,
+    pub focus_score: Option<FocusScoreConfig>,
+    pub warn_before_break_end: Option<Duration>,
+    pub warn_before_day_end: Duration,
+    pub pressure_duration: Duration,
+    pub auto_unlock_after_break: bool,
+    /// When the first work period of the day starts by itself
+    pub auto_unlock_at: Option<HourMinute>,
+    pub auto_unlock_requires_activity: bool,
+    pub weekly_template: Option<WeeklyTemplateConfig>,
+    /// The first day of each week
+    pub week_start: Weekday,
+    pub crunch_mode: Option<CrunchModeConfig>,
+    pub deactivation_approval_timeout: Option<Duration>,
+    pub max_deactivation: Option<Duration>,
+    pub weekly_deactivation_quota: Option<Duration>,
+    pub hide_completed_requirements_after: Option<Duration>,
+    pub merge_locked_time_ranges: bool,
+    pub min_work_period_duration: Option<Duration>,
+    pub stopwatch: Option<StopwatchConfig>,
+    pub rest_debt: Option<RestDebtConfig>,
+    pub break_credit: Option<BreakCreditConfig>,
+    pub presets: Vec<TimerPresetConfig>,
+    pub day_overrides: Vec<DayOverrideConfig>,
+    pub profiles: Vec<ProfileConfig>,
+    pub profile_rules: Vec<ProfileRuleConfig>,
+    pub profile_schedule: Vec<ProfileScheduleConfig>,
+    pub exceptions: AppList,
+    pub blocklist: AppList,
+    /// The messages that replace the default messages of refusals
+    pub refusal_messages: HashMap<RefusalCode, String>,
+}
+
+impl DiagonatorManagerConfig {
+    fn profile(&self, name: Option<&str>) -> Option<&ProfileConfig> {
+        let name = name?;
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+    /// Determines the profile of the first profile schedule rule that matches the date, which is
+    /// `None` for the base configuration if no rule matches. Returns `None` without a profile
+    /// schedule.
+    fn scheduled_profile(&self, date: &LocalDate) -> Option<Option<String>> {
+        if self.profile_schedule.is_empty() {
+            return None;
+        }
+        let rule = self.profile_schedule.iter().find(|rule| rule.matches(date));
+        Some(rule.map(|rule| rule.profile.clone()))
+    }
 }
 
+#[derive(Clone)]
 struct IdGenerator {
     last_id: u64,
 }
@@ -0,0 +1,187 @@
+use crate::chat::{describe_info, describe_pending_deactivation, describe_response, parse_command};
+use crate::clients::Role;
+use crate::config::MatrixConfig;
+use crate::manager::CurrentState;
+use crate::server::{Request, Response};
+use crate::time::Timestamp;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::time::Duration;
+
+const SYNC_TIMEOUT_MS: u64 = 30_000;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+struct MatrixClient {
+    http: reqwest::Client,
+    config: MatrixConfig,
+    // transaction IDs must be unique for the access token, even across restarts
+    txn_prefix: i64,
+    next_txn: AtomicU64,
+}
+
+impl MatrixClient {
+    fn url(&self, segments: &[&str]) -> Result<reqwest::Url, String> {
+        let mut url = reqwest::Url::parse(&self.config.homeserver)
+            .map_err(|err| format!("invalid homeserver URL: {}", err))?;
+        url.path_segments_mut()
+            .map_err(|()| "invalid homeserver URL".to_owned())?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+    async fn sync(&self, since: Option<&str>, timeout_ms: u64) -> Result<Value, String> {
+        let filter = json!({
+            "room": {
+                "rooms": [self.config.room_id],
+                "timeline": {"types": ["m.room.message"]},
+            },
+        });
+        let mut query = vec![
+            ("timeout", timeout_ms.to_string()),
+            ("filter", filter.to_string()),
+        ];
+        if let Some(since) = since {
+            query.push(("since", since.to_owned()));
+        }
+        self.http
+            .get(self.url(&["sync"])?)
+            .bearer_auth(&self.config.access_token)
+            .query(&query)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| err.to_string())?
+            .json()
+            .await
+            .map_err(|err| err.to_string())
+    }
+    async fn send(&self, text: &str) {
+        let txn = format!(
+            "{}-{}",
+            self.txn_prefix,
+            self.next_txn.fetch_add(1, Ordering::SeqCst)
+        );
+        let url = match self.url(&[
+            "rooms",
+            &self.config.room_id,
+            "send",
+            "m.room.message",
+            &txn,
+        ]) {
+            Ok(url) => url,
+            Err(err) => {
+                eprintln!("Unable to send Matrix message: {}", err);
+                return;
+            }
+        };
+        let result = self
+            .http
+            .put(url)
+            .bearer_auth(&self.config.access_token)
+            .json(&json!({"msgtype": "m.notice", "body": text}))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        if let Err(err) = result {
+            eprintln!("Unable to send Matrix message: {}", err);
+        }
+    }
+    fn role_of(&self, user_id: &str) -> Option<Role> {
+        self.config
+            .users
+            .iter()
+            .find(|user| user.user_id == user_id)
+            .map(|user| user.role)
+    }
+    async fn answer_commands(&self, handle: &impl Fn(String, Role, Request) -> Response) {
+        let mut since: Option<String> = None;
+        loop {
+            // the first sync only finds out where the room's timeline ends, so that commands
+            // sent while the server wasn't running are not answered
+            let timeout_ms = if since.is_some() { SYNC_TIMEOUT_MS } else { 0 };
+            let response = match self.sync(since.as_deref(), timeout_ms).await {
+                Ok(response) => response,
+                Err(err) => {
+                    eprintln!("Matrix sync failed: {}", err);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            if since.is_some() {
+                let events = response["rooms"]["join"][&self.config.room_id]["timeline"]["events"]
+                    .as_array()
+                    .cloned()
+                    .unwrap_or_default();
+                for event in events {
+                    self.answer(&event, handle).await;
+                }
+            }
+            if let Some(next_batch) = response["next_batch"].as_str() {
+                since = Some(next_batch.to_owned());
+            }
+        }
+    }
+    async fn answer(&self, event: &Value, handle: &impl Fn(String, Role, Request) -> Response) {
+        let (Some(sender), Some(body)) =
+            (event["sender"].as_str(), event["content"]["body"].as_str())
+        else {
+            return;
+        };
+        let Some(request) = parse_command(body, '!') else {
+            return;
+        };
+        let reply = match (self.role_of(sender), request) {
+            (None, _) => "You are not allowed to use this bot.".to_owned(),
+            (Some(_), Err(msg)) => msg,
+            (Some(role), Ok(request)) => {
+                describe_response(&handle(format!("matrix:{}", sender), role, request))
+            }
+        };
+        self.send(&reply).await;
+    }
+    /// Posts the state whenever it changes, and asks for pending deactivations to be approved.
+    async fn announce_state_changes(&self, handle: &impl Fn(String, Role, Request) -> Response) {
+        let mut last_state: Option<CurrentState> = None;
+        let mut announced_deactivations: HashSet<u64> = HashSet::new();
+        let mut interval = tokio::time::interval(STATE_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Response::Info { info } =
+                handle("matrix".to_owned(), Role::Observer, Request::GetInfo)
+            else {
+                continue;
+            };
+            if last_state.is_some_and(|state| state != info.state()) {
+                self.send(&describe_info(&info)).await;
+            }
+            last_state = Some(info.state());
+            for pending in info.pending_deactivations() {
+                if announced_deactivations.insert(pending.id()) {
+                    self.send(&describe_pending_deactivation(pending, '!'))
+                        .await;
+                }
+            }
+        }
+    }
+}
+
+/// Runs a Matrix bot that posts state changes to a room and answers commands sent to that room
+/// by the configured users, with the permissions of their roles.
+pub async fn run_matrix_bot(
+    config: MatrixConfig,
+    handle: impl Fn(String, Role, Request) -> Response,
+) {
+    let client = MatrixClient {
+        http: reqwest::Client::new(),
+        config,
+        txn_prefix: Timestamp::now().as_seconds(),
+        next_txn: AtomicU64::new(0),
+    };
+    tokio::join!(
+        client.answer_commands(&handle),
+        client.announce_state_changes(&handle)
+    );
+}
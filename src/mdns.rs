@@ -0,0 +1,65 @@
+use crate::config::MdnsConfig;
+use crate::version::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, VERSION};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::SocketAddr;
+
+const SERVICE_TYPE: &str = "_diagonator._tcp.local.";
+
+/// Picks the first endpoint that clients on the network can reach, i.e. a TCP address that isn't
+/// a loopback address, returning its port and whether it uses TLS.
+fn advertised_endpoint(endpoints: &[(String, bool)]) -> Option<(u16, bool)> {
+    endpoints.iter().find_map(|(bind_on, tls)| {
+        let addr: SocketAddr = bind_on.parse().ok()?;
+        (!addr.ip().is_loopback()).then_some((addr.port(), *tls))
+    })
+}
+
+fn advertise(config: &MdnsConfig, port: u16, tls: bool) -> Result<ServiceDaemon, String> {
+    let host = hostname::get()
+        .map_err(|err| err.to_string())?
+        .to_string_lossy()
+        .into_owned();
+    let name = config.name.clone().unwrap_or_else(|| host.clone());
+    let properties = [
+        ("version", VERSION.to_owned()),
+        ("protocol_version", PROTOCOL_VERSION.to_string()),
+        ("min_protocol_version", MIN_PROTOCOL_VERSION.to_string()),
+        ("tls", tls.to_string()),
+    ];
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &name,
+        &format!("{}.local.", host),
+        "",
+        port,
+        &properties[..],
+    )
+    .map_err(|err| err.to_string())?
+    .enable_addr_auto();
+    let daemon = ServiceDaemon::new().map_err(|err| err.to_string())?;
+    daemon.register(service).map_err(|err| err.to_string())?;
+    Ok(daemon)
+}
+
+/// Advertises the server as `_diagonator._tcp` on the local network, so that clients can
+/// discover it without knowing its address. The advertisement lasts as long as the returned
+/// daemon.
+pub fn run_mdns_advertisement(
+    config: &MdnsConfig,
+    endpoints: &[(String, bool)],
+) -> Option<ServiceDaemon> {
+    let Some((port, tls)) = advertised_endpoint(endpoints) else {
+        eprintln!("WARNING: mDNS is configured, but the server doesn't listen on a TCP address that other devices can reach");
+        return None;
+    };
+    match advertise(config, port, tls) {
+        Ok(daemon) => {
+            eprintln!("Advertising {} on port {} over mDNS", SERVICE_TYPE, port);
+            Some(daemon)
+        }
+        Err(err) => {
+            eprintln!("Unable to advertise the server over mDNS: {}", err);
+            None
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsSnapshot {
+    coalesced_info_updates: u64,
+    dropped_events: u64,
+    refused_connections: u64,
+    idle_connections_closed: u64,
+    persistence_failures: u64,
+}
+
+/// Counters describing the health of the server, shared between all tasks.
+pub struct Metrics {
+    coalesced_info_updates: AtomicU64,
+    dropped_events: AtomicU64,
+    refused_connections: AtomicU64,
+    idle_connections_closed: AtomicU64,
+    persistence_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            coalesced_info_updates: AtomicU64::new(0),
+            dropped_events: AtomicU64::new(0),
+            refused_connections: AtomicU64::new(0),
+            idle_connections_closed: AtomicU64::new(0),
+            persistence_failures: AtomicU64::new(0),
+        }
+    }
+    pub fn record_coalesced_info_update(&self) {
+        self.coalesced_info_updates.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_dropped_event(&self) {
+        self.dropped_events.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_refused_connection(&self) {
+        self.refused_connections.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_idle_connection_closed(&self) {
+        self.idle_connections_closed.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_persistence_failure(&self) {
+        self.persistence_failures.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            coalesced_info_updates: self.coalesced_info_updates.load(Ordering::Relaxed),
+            dropped_events: self.dropped_events.load(Ordering::Relaxed),
+            refused_connections: self.refused_connections.load(Ordering::Relaxed),
+            idle_connections_closed: self.idle_connections_closed.load(Ordering::Relaxed),
+            persistence_failures: self.persistence_failures.load(Ordering::Relaxed),
+        }
+    }
+}
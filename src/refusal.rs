@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// Identifies why a request was refused, so that clients can tell refusals apart without parsing
+/// their messages, and so that users can replace the messages with their own through
+/// `refusal_messages`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RefusalCode {
+    PermissionDenied,
+    Maintenance,
+    NotUnlockable,
+    IntentRequired,
+    DeactivationInCrunchMode,
+    DeactivationTooLong,
+    DeactivationQuotaExceeded,
+    PresetInCrunchMode,
+    PresetLoosensRestrictions,
+    BreakCreditFull,
+}
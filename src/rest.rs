@@ -0,0 +1,142 @@
+use crate::encoding::Encoding;
+use crate::server::{Caller, Request, Response};
+use axum::body::Bytes;
+use axum::extract::{Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, patch, post, put};
+use axum::Router;
+use serde_json::{Map, Value};
+use std::future::Future;
+
+/// Builds the request of the given type from the fields of a JSON object, the same way as if the
+/// object had been sent to the socket protocol with a `type` field.
+pub fn build_request(kind: &str, mut fields: Map<String, Value>) -> Result<Request, String> {
+    fields.insert("type".to_owned(), Value::String(kind.to_owned()));
+    serde_json::from_value(Value::Object(fields)).map_err(|err| err.to_string())
+}
+
+/// Collects the fields of a request from the body, the query string, and the path. An empty
+/// body is allowed for requests without fields, and query values are parsed as JSON if possible,
+/// so that e.g. `?all=true` is a boolean.
+fn collect_fields(
+    body: &[u8],
+    encoding: Encoding,
+    query: Vec<(String, String)>,
+    path: Vec<(String, String)>,
+) -> Result<Map<String, Value>, String> {
+    let mut fields = if body.iter().all(u8::is_ascii_whitespace) {
+        Map::new()
+    } else {
+        encoding
+            .decode(body)
+            .map_err(|msg| format!("Invalid request body: {}", msg))?
+    };
+    for (key, value) in query {
+        let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+        fields.insert(key, value);
+    }
+    for (key, value) in path {
+        // IDs are numbers, while other path segments such as names are always strings
+        let value = match value.parse::<u64>() {
+            Ok(id) if key == "id" => Value::from(id),
+            _ => Value::String(value),
+        };
+        fields.insert(key, value);
+    }
+    Ok(fields)
+}
+
+fn respond(encoding: Encoding, response: Response) -> (StatusCode, axum::response::Response) {
+    let status = match response {
+        Response::Error { .. } => StatusCode::BAD_REQUEST,
+        Response::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        Response::QuotaExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::OK,
+    };
+    (status, encoding.respond(&response))
+}
+
+/// Exposes the requests of the socket protocol as REST endpoints, e.g. `GET /info` or
+/// `POST /requirements/3/complete`. The handler authorizes and handles each request in the same
+/// way as requests sent to the socket protocol.
+pub fn rest_api<H, F>(handle: H) -> Router
+where
+    H: Fn(Caller, Request) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Response> + Send,
+{
+    let endpoint = |kind: &'static str| {
+        let handle = handle.clone();
+        move |caller: Caller,
+              headers: HeaderMap,
+              path: Option<Path<Vec<(String, String)>>>,
+              Query(query): Query<Vec<(String, String)>>,
+              body: Bytes| async move {
+            let path = path.map(|Path(path)| path).unwrap_or_default();
+            let encoding = Encoding::of_response(&headers);
+            match collect_fields(&body, Encoding::of_request(&headers), query, path)
+                .and_then(|fields| build_request(kind, fields))
+            {
+                Ok(request) => respond(encoding, handle(caller, request).await),
+                Err(msg) => respond(encoding, Response::Error { msg, code: None }),
+            }
+        }
+    };
+    Router::new()
+        .route("/info", get(endpoint("GetInfo")))
+        .route("/info/delta", get(endpoint("GetInfoDelta")))
+        .route("/info/if-changed", get(endpoint("GetInfoIfChanged")))
+        .route("/policy", get(endpoint("GetPolicy")))
+        .route("/display", get(endpoint("GetDisplayInfo")))
+        .route("/timer", get(endpoint("GetTimer")))
+        .route("/timer", put(endpoint("SetTimerState")))
+        .route("/timer/unlock", post(endpoint("UnlockTimer")))
+        .route("/timer/lock", post(endpoint("LockTimer")))
+        .route("/timer/end-break", post(endpoint("EndBreak")))
+        .route(
+            "/timer/spend-break-credit",
+            post(endpoint("SpendBreakCredit")),
+        )
+        .route("/requirements", get(endpoint("ListRequirements")))
+        .route("/requirements", post(endpoint("AddRequirement")))
+        .route(
+            "/requirements/:id/complete",
+            post(endpoint("CompleteRequirement")),
+        )
+        .route(
+            "/requirements/by-name/:name/complete",
+            post(endpoint("CompleteRequirementByName")),
+        )
+        .route(
+            "/requirements/by-name/:name/increment",
+            post(endpoint("IncrementRequirement")),
+        )
+        .route("/deactivate", post(endpoint("Deactivate")))
+        .route(
+            "/deactivations/:id/approve",
+            post(endpoint("ApproveDeactivation")),
+        )
+        .route(
+            "/deactivations/:id/reject",
+            post(endpoint("RejectDeactivation")),
+        )
+        .route("/focus", post(endpoint("StartFocus")))
+        .route("/plan", post(endpoint("SubmitPlan")))
+        .route("/activity", post(endpoint("ReportActivity")))
+        .route("/activities/begin", post(endpoint("BeginActivity")))
+        .route("/activities/end", post(endpoint("EndActivity")))
+        .route("/stats", get(endpoint("GetStats")))
+        .route("/events", get(endpoint("GetEvents")))
+        .route("/preview/:date", get(endpoint("PreviewDay")))
+        .route("/simulate", post(endpoint("SimulateChange")))
+        .route("/preset", post(endpoint("SetPreset")))
+        .route("/location", post(endpoint("ReportLocation")))
+        .route("/crunch", post(endpoint("StartCrunchMode")))
+        .route("/clients", get(endpoint("GetClients")))
+        .route("/maintenance", post(endpoint("SetMaintenance")))
+        .route("/recovery/resolve", post(endpoint("ResolveRecovery")))
+        .route("/config", patch(endpoint("PatchConfig")))
+        .route("/metrics", get(endpoint("GetMetrics")))
+        .route("/version", get(endpoint("GetVersion")))
+        .route("/hello", get(endpoint("Hello")))
+        .route("/update/check", post(endpoint("CheckUpdate")))
+}
@@ -1,90 +1,1615 @@
-use crate::config::DiagonatorConfig;
-use crate::manager::{CurrentInfo, DiagonatorManager, DiagonatorManagerConfig};
-use crate::time::{Duration, HourMinute, Timestamp};
-use axum::routing::post;
-use axum::Json;
+use crate::audio::run_audio_mute;
+use crate::clients::{ClientInfo, ClientRegistry, Permission, Role};
+use crate::config::{
+    DiagonatorConfig, LoadConfigError, RunningConfig, StateFormat, StateRecovery, TokenConfig,
+};
+use crate::connections::ConnectionLimits;
+use crate::display::{run_display_server, DisplayInfo};
+use crate::email::run_email_digest;
+use crate::encoding::Encoding;
+use crate::event_log::{EventLog, LoggedEvent};
+#[cfg(feature = "graphql")]
+use crate::graphql::handle_graphql;
+use crate::hooks::{run_hooks, run_requirement_hooks, send_webhooks};
+use crate::idle::run_idle_detection;
+use crate::jsonrpc::handle_json_rpc;
+use crate::manager::{
+    AppList, BreakTimer, ChangeKinds, CurrentInfo, DayPreview, DiagonatorManager,
+    DiagonatorManagerConfig, HypotheticalChange, InfoDelta, Policy, Requirement, TimerInfo,
+};
+use crate::matrix::run_matrix_bot;
+use crate::metrics::{Metrics, MetricsSnapshot};
+use crate::refusal::RefusalCode;
+use crate::rest::rest_api;
+use crate::state_file::{restore_state, save_state};
+use crate::stats::{Comparison, DayStats, WeekSummary};
+use crate::status_page::run_status_page;
+use crate::time::{parse_date, Duration, HourMinute, Timestamp};
+use crate::tls::load_tls_acceptor;
+use crate::transport::listen;
+use crate::update::{check_for_update, UpdateStatus};
+use crate::version::{VersionInfo, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, VERSION};
+use axum::body::{Body, Bytes};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use axum::http::{header, Extensions, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Extension;
+use futures_util::future::join_all;
+use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use socketioxide::{extract::SocketRef, SocketIo};
-use std::sync::Mutex;
+use serde_json::json;
+use socketioxide::extract::{SocketRef, TryData};
+use socketioxide::socket::Sid;
+use socketioxide::{SendError, SocketError, SocketIo};
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::{watch, Notify};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
-enum Request {
-    UnlockTimer,
+pub enum Request {
+    UnlockTimer {
+        intent: Option<String>,
+    },
     LockTimer,
+    EndBreak,
+    SpendBreakCredit {
+        duration: Duration,
+    },
     GetInfo,
-    CompleteRequirement { id: u64 },
-    AddRequirement { name: String, due: HourMinute },
-    Deactivate { duration: Duration },
+    CompleteRequirement {
+        id: u64,
+        answers: Option<Vec<String>>,
+    },
+    CompleteRequirementByName {
+        name: String,
+        answers: Option<Vec<String>>,
+    },
+    IncrementRequirement {
+        name: String,
+    },
+    AddRequirement {
+        name: String,
+        due: HourMinute,
+    },
+    Deactivate {
+        duration: Duration,
+    },
+    StartFocus {
+        label: String,
+    },
+    GetStats,
+    ReportActivity,
+    GetTimer,
+    SetTimerState {
+        timer: BreakTimer,
+    },
+    StartCrunchMode {
+        days: u32,
+    },
+    ApproveDeactivation {
+        id: u64,
+    },
+    RejectDeactivation {
+        id: u64,
+    },
+    GetClients,
+    SetMaintenance {
+        enabled: bool,
+    },
+    GetMetrics,
+    CheckUpdate,
+    GetVersion,
+    ListRequirements {
+        all: Option<bool>,
+    },
+    PreviewDay {
+        date: String,
+    },
+    SimulateChange {
+        changes: Vec<HypotheticalChange>,
+    },
+    SetPreset {
+        name: String,
+    },
+    ReportLocation {
+        ssid: Option<String>,
+        location: Option<String>,
+    },
+    BeginActivity {
+        name: String,
+    },
+    EndActivity,
+    GetPolicy,
+    ResolveRecovery {
+        fail_open: bool,
+    },
+    Subscribe,
+    PatchConfig {
+        patch: serde_json::Value,
+        persist: Option<bool>,
+    },
+    Hello {
+        protocol_version: Option<u32>,
+    },
+    SubmitPlan {
+        intentions: Option<Vec<String>>,
+    },
+    Ping,
+    GetEvents {
+        since: Option<Timestamp>,
+    },
+    GetInfoDelta {
+        cache_version: u64,
+    },
+    GetInfoIfChanged {
+        cache_version: u64,
+    },
+    GetDisplayInfo,
+}
+
+impl Request {
+    /// The types of all requests, which are reported to clients in the handshake.
+    const TYPES: &'static [&'static str] = &[
+        "UnlockTimer",
+        "LockTimer",
+        "EndBreak",
+        "SpendBreakCredit",
+        "GetInfo",
+        "CompleteRequirement",
+        "CompleteRequirementByName",
+        "IncrementRequirement",
+        "AddRequirement",
+        "Deactivate",
+        "StartFocus",
+        "GetStats",
+        "ReportActivity",
+        "GetTimer",
+        "SetTimerState",
+        "StartCrunchMode",
+        "ApproveDeactivation",
+        "RejectDeactivation",
+        "GetClients",
+        "SetMaintenance",
+        "GetMetrics",
+        "CheckUpdate",
+        "GetVersion",
+        "ListRequirements",
+        "PreviewDay",
+        "SimulateChange",
+        "SetPreset",
+        "ReportLocation",
+        "BeginActivity",
+        "EndActivity",
+        "GetPolicy",
+        "ResolveRecovery",
+        "Subscribe",
+        "PatchConfig",
+        "Hello",
+        "SubmitPlan",
+        "Ping",
+        "GetEvents",
+        "GetInfoDelta",
+        "GetInfoIfChanged",
+        "GetDisplayInfo",
+    ];
+    fn required_permission(&self) -> Permission {
+        match self {
+            Self::GetInfo
+            | Self::GetStats
+            | Self::GetTimer
+            | Self::GetMetrics
+            | Self::GetVersion
+            | Self::ListRequirements { .. }
+            | Self::PreviewDay { .. }
+            | Self::SimulateChange { .. }
+            | Self::GetPolicy
+            | Self::Subscribe
+            | Self::Hello { .. }
+            | Self::Ping
+            | Self::GetEvents { .. }
+            | Self::GetInfoDelta { .. }
+            | Self::GetInfoIfChanged { .. }
+            | Self::GetDisplayInfo => Permission::Read,
+            Self::SetTimerState { .. }
+            | Self::StartCrunchMode { .. }
+            | Self::GetClients
+            | Self::SetMaintenance { .. }
+            | Self::ResolveRecovery { .. }
+            | Self::PatchConfig { .. } => Permission::Admin,
+            Self::ApproveDeactivation { .. } | Self::RejectDeactivation { .. } => {
+                Permission::Partner
+            }
+            _ => Permission::Write,
+        }
+    }
+    /// Whether the request changes the manager's state, in which case the client that sent it
+    /// is shown as the last one to modify the state. Activity reports happen in the background,
+    /// so they don't count, and update checks only need write permission because they make the
+    /// server contact GitHub.
+    fn modifies_state(&self) -> bool {
+        !matches!(self.required_permission(), Permission::Read)
+            && !matches!(self, Self::ReportActivity | Self::CheckUpdate)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct SocketAuth {
+    token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct WebSocketParams {
+    token: Option<String>,
+    /// How the info is sent, as JSON text messages by default
+    format: Option<Encoding>,
+}
+
+/// The roles of the clients that may connect through a listener, or `None` if all roles may.
+/// Each listener adds its roles to the extensions of the requests that it receives.
+#[derive(Clone, Default)]
+struct ListenerRoles(Option<Arc<[Role]>>);
+
+impl ListenerRoles {
+    fn of(parts: &Extensions) -> Self {
+        parts.get::<Self>().cloned().unwrap_or_default()
+    }
+    fn admit(&self, role: Role) -> Result<(), String> {
+        match &self.0 {
+            Some(roles) if !roles.contains(&role) => {
+                Err("Your role can't connect through this listener.".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The headers of an HTTP request, together with the roles allowed by the listener that received
+/// it.
+#[derive(Clone)]
+pub struct Caller {
+    headers: HeaderMap,
+    roles: ListenerRoles,
+}
+
+#[axum::async_trait]
+impl<S: Sync> FromRequestParts<S> for Caller {
+    type Rejection = Infallible;
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Infallible> {
+        Ok(Self {
+            headers: parts.headers.clone(),
+            roles: ListenerRoles::of(&parts.extensions),
+        })
+    }
+}
+
+/// Requests that don't come through a listener, such as gRPC requests, are allowed for all roles.
+impl From<HeaderMap> for Caller {
+    fn from(headers: HeaderMap) -> Self {
+        Self {
+            headers,
+            roles: ListenerRoles::default(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Response {
     Success,
-    Error { msg: String },
-    Info { info: CurrentInfo },
+    Error {
+        msg: String,
+        /// Set when the request was refused by a rule rather than being invalid
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<RefusalCode>,
+    },
+    Info {
+        info: Box<CurrentInfo>,
+    },
+    Stats {
+        days: Vec<DayStats>,
+        vs_yesterday: Option<Comparison>,
+        vs_last_week: Option<Comparison>,
+        this_week: WeekSummary,
+    },
+    Timer {
+        info: TimerInfo,
+    },
+    Pending {
+        id: u64,
+    },
+    Clients {
+        clients: Vec<ClientInfo>,
+    },
+    Metrics {
+        metrics: MetricsSnapshot,
+    },
+    Update {
+        status: UpdateStatus,
+    },
+    Version {
+        info: VersionInfo,
+    },
+    Hello {
+        protocol_version: u32,
+        min_protocol_version: u32,
+        version: String,
+        commands: Vec<String>,
+    },
+    Pong,
+    Events {
+        events: Vec<LoggedEvent>,
+    },
+    Requirements {
+        requirements: Vec<Requirement>,
+    },
+    DayPreview {
+        preview: DayPreview,
+    },
+    Policy {
+        policy: Policy,
+    },
+    InfoUpdate {
+        info: Box<CurrentInfo>,
+        cache_version: u64,
+        changes: ChangeKinds,
+    },
+    InfoDelta {
+        delta: Box<InfoDelta>,
+        cache_version: u64,
+        changes: ChangeKinds,
+    },
+    NotModified {
+        cache_version: u64,
+    },
+    DisplayInfo {
+        info: DisplayInfo,
+    },
+    /// A deactivation was refused because it would exceed the weekly deactivation quota.
+    QuotaExceeded {
+        msg: String,
+        remaining: Duration,
+        resets_at: Timestamp,
+    },
+    InternalError {
+        msg: String,
+    },
 }
 
-pub async fn launch_server(config: DiagonatorConfig) {
-    let (layer, io) = SocketIo::new_layer();
+/// The info pushed to Socket.IO and WebSocket clients, along with the parts that changed since
+/// the info that was last pushed to the same client.
+#[derive(Serialize)]
+struct InfoPush<'a> {
+    #[serde(flatten)]
+    info: &'a CurrentInfo,
+    changes: ChangeKinds,
+}
+
+/// Determines which parts of the info changed since the info that was last pushed to a client,
+/// if any.
+pub fn changes_since(info: &CurrentInfo, previous: Option<&CurrentInfo>) -> ChangeKinds {
+    previous.map_or(ChangeKinds::ALL, |previous| info.changes_since(previous))
+}
+
+struct ServerState {
+    /// Reading the info only takes a read lock while it is up to date, so that clients reading
+    /// it don't wait for each other
+    manager: RwLock<DiagonatorManager>,
+    admin_token: Option<String>,
+    /// If set, clients without a token are refused, and this token identifies a user
+    auth_token: Option<String>,
+    partner_token: Option<String>,
+    tokens: Vec<TokenConfig>,
+    clients: Mutex<ClientRegistry>,
+    maintenance: AtomicBool,
+    metrics: Metrics,
+    running_config: Mutex<RunningConfig>,
+    /// Held while a patch is applied, so that patches don't overwrite each other's changes to the
+    /// configuration file
+    patching_config: tokio::sync::Mutex<()>,
+    event_log: Option<Mutex<EventLog>>,
+    /// Wakes the loop that pushes info updates and announces events, after something that may
+    /// have changed them
+    changed: Notify,
+    /// How often long-lived connections are checked, so that connections to clients that went
+    /// away are closed
+    keep_alive: std::time::Duration,
+}
+
+impl ServerState {
+    /// Determines the label and role of the client that sent the token through a listener that
+    /// admits the given roles.
+    fn identify(
+        &self,
+        roles: &ListenerRoles,
+        token: Option<&str>,
+    ) -> Result<(String, Role), String> {
+        let (label, role) = self.identify_token(token)?;
+        roles.admit(role)?;
+        Ok((label, role))
+    }
+    /// Determines the label and role of the client that sent the token.
+    /// Clients without a token are anonymous users, unless an `auth_token` is required.
+    fn identify_token(&self, token: Option<&str>) -> Result<(String, Role), String> {
+        let Some(token) = token else {
+            if self.auth_token.is_some() {
+                return Err("This server requires a token.".to_owned());
+            }
+            return Ok(("anonymous".to_owned(), Role::User));
+        };
+        let matches = |expected: &Option<String>| {
+            expected
+                .as_deref()
+                .is_some_and(|expected| tokens_match(expected, token))
+        };
+        if matches(&self.admin_token) {
+            return Ok(("admin".to_owned(), Role::Admin));
+        }
+        if matches(&self.auth_token) {
+            return Ok(("user".to_owned(), Role::User));
+        }
+        if matches(&self.partner_token) {
+            return Ok(("partner".to_owned(), Role::Partner));
+        }
+        self.tokens
+            .iter()
+            .find(|config| tokens_match(&config.token, token))
+            .map(|config| (config.label.clone(), config.role))
+            .ok_or_else(|| "Invalid token.".to_owned())
+    }
+    fn set_maintenance(&self, enabled: bool) {
+        self.update_maintenance(&mut self.manager(), enabled);
+    }
+    /// Like `set_maintenance`, for callers that already hold the manager.
+    fn update_maintenance(&self, manager: &mut DiagonatorManager, enabled: bool) {
+        if self.maintenance.swap(enabled, Ordering::SeqCst) != enabled {
+            eprintln!(
+                "Maintenance mode {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+            manager.set_maintenance(Timestamp::now(), enabled);
+            self.changed.notify_one();
+        }
+    }
+    /// Enters maintenance mode after writing to disk failed, so that changes that could no
+    /// longer be persisted are refused until an admin fixes the problem and disables
+    /// maintenance mode.
+    fn persistence_failed(&self, manager: &mut DiagonatorManager, what: &str, err: impl Display) {
+        eprintln!("Received error '{}' when {}", err, what);
+        self.metrics.record_persistence_failure();
+        self.update_maintenance(manager, true);
+    }
+    // a panic while one of these locks is held must not take down the whole server,
+    // so poisoned locks are recovered instead of propagating the panic
+    fn manager(&self) -> RwLockWriteGuard<'_, DiagonatorManager> {
+        self.manager.write().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn read_manager(&self) -> RwLockReadGuard<'_, DiagonatorManager> {
+        self.manager.read().unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Returns the current info, only refreshing it with exclusive access to the manager if it
+    /// isn't up to date yet.
+    fn current_info(&self) -> CurrentInfo {
+        let current_time = Timestamp::now();
+        if let Some(info) = self.read_manager().cached_info(current_time) {
+            return info.clone();
+        }
+        let info = self.manager().get_current_info(current_time);
+        self.changed.notify_one();
+        info
+    }
+    fn clients(&self) -> MutexGuard<'_, ClientRegistry> {
+        self.clients.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn event_log(&self) -> Option<MutexGuard<'_, EventLog>> {
+        let event_log = self.event_log.as_ref()?;
+        Some(event_log.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+    fn running_config(&self) -> MutexGuard<'_, RunningConfig> {
+        self.running_config
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    async fn check_update(&self) -> Response {
+        match check_for_update().await {
+            Ok(status) => {
+                let available_update =
+                    Some(status.latest_version.clone()).filter(|_| status.update_available);
+                if let Some(version) = &available_update {
+                    eprintln!("diagonator-server {} is available", version);
+                }
+                self.manager()
+                    .set_available_update(Timestamp::now(), available_update);
+                self.changed.notify_one();
+                Response::Update { status }
+            }
+            Err(msg) => Response::Error { msg, code: None },
+        }
+    }
+    fn dump_state(&self) {
+        let dump = json!({
+            "manager": self.read_manager().dump_state(),
+            "clients": self.clients().list(Timestamp::now()),
+            "maintenance": self.maintenance.load(Ordering::SeqCst),
+            "metrics": self.metrics.snapshot(),
+        });
+        eprintln!(
+            "State dump:\n{}",
+            serde_json::to_string_pretty(&dump).unwrap()
+        );
+    }
+    /// Checks that the client that sent the request is allowed to use it,
+    /// returning the label and role of the client.
+    fn authorize(
+        &self,
+        caller: &Caller,
+        request: &Request,
+    ) -> Result<(String, Role), (String, Option<RefusalCode>)> {
+        let token = caller
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let (label, role) = self
+            .identify(&caller.roles, token)
+            .map_err(|msg| (msg, None))?;
+        self.check_permission(&label, role, request)
+            .map_err(|(msg, code)| (msg, Some(code)))?;
+        Ok((label, role))
+    }
+    /// Records the activity of the client and checks that its role allows it to use the request.
+    fn check_permission(
+        &self,
+        label: &str,
+        role: Role,
+        request: &Request,
+    ) -> Result<(), (String, RefusalCode)> {
+        self.clients()
+            .record_activity(label, role, Timestamp::now());
+        if !role.allows(request.required_permission()) {
+            return Err((
+                "You do not have permission to use this command.".to_owned(),
+                RefusalCode::PermissionDenied,
+            ));
+        }
+        let permission = request.required_permission();
+        if matches!(permission, Permission::Write | Permission::Partner)
+            && self.maintenance.load(Ordering::SeqCst)
+        {
+            return Err((
+                "The server is in maintenance mode, so only read-only and admin commands are allowed.".to_owned(),
+                RefusalCode::Maintenance,
+            ));
+        }
+        Ok(())
+    }
+    /// Replaces the message of a refusal with the one that the user configured for its code.
+    fn with_refusal_message(&self, mut response: Response) -> Response {
+        let code = match response {
+            Response::Error {
+                code: Some(code), ..
+            } => code,
+            Response::QuotaExceeded { .. } => RefusalCode::DeactivationQuotaExceeded,
+            _ => return response,
+        };
+        if let Some(message) = self.read_manager().refusal_message(code) {
+            if let Response::Error { msg, .. } | Response::QuotaExceeded { msg, .. } = &mut response
+            {
+                *msg = message.to_owned();
+            }
+        }
+        response
+    }
+    /// Handles a request sent over HTTP, whether through the socket protocol, the REST API,
+    /// or JSON-RPC.
+    async fn handle_request(&self, caller: Caller, request: Request) -> Response {
+        let response = match self.authorize(&caller, &request) {
+            Ok((client, _)) => self.dispatch_async(client, request).await,
+            Err((msg, code)) => Response::Error { msg, code },
+        };
+        self.with_refusal_message(response)
+    }
+    /// Handles a command sent through a chat bot or by the idle detection. Chat users are
+    /// identified by their chat account instead of a token, so the bot passes in their label and
+    /// role.
+    fn handle_chat_request(&self, label: String, role: Role, request: Request) -> Response {
+        if let Request::CheckUpdate | Request::GetEvents { .. } | Request::PatchConfig { .. } =
+            request
+        {
+            return Response::Error {
+                msg: "This command is not available in chat.".to_owned(),
+                code: None,
+            };
+        }
+        let response = match self.check_permission(&label, role, &request) {
+            Ok(()) => panic::catch_unwind(AssertUnwindSafe(|| self.dispatch(label, request)))
+                .unwrap_or_else(|_| internal_error()),
+            Err((msg, code)) => Response::Error {
+                msg,
+                code: Some(code),
+            },
+        };
+        self.with_refusal_message(response)
+    }
+    /// Handles a request sent over D-Bus. The session bus is only reachable by the local user, so
+    /// D-Bus clients are given the configured role instead of being identified by a token.
+    #[cfg(feature = "dbus")]
+    async fn handle_dbus_request(&self, role: Role, request: Request) -> Response {
+        const LABEL: &str = "dbus";
+        let response = match self.check_permission(LABEL, role, &request) {
+            Ok(()) => self.dispatch_async(LABEL.to_owned(), request).await,
+            Err((msg, code)) => Response::Error {
+                msg,
+                code: Some(code),
+            },
+        };
+        self.with_refusal_message(response)
+    }
+    /// Handles a request from a client that is allowed to use it, where the requests that wait
+    /// for the network or for files do so without blocking the runtime or holding the manager.
+    async fn dispatch_async(&self, client: String, request: Request) -> Response {
+        match request {
+            Request::CheckUpdate => self.check_update().await,
+            Request::GetEvents { since } => self.get_events(since).await,
+            Request::PatchConfig { patch, persist } => {
+                self.patch_config(client, patch, persist.unwrap_or(false))
+                    .await
+            }
+            request => panic::catch_unwind(AssertUnwindSafe(|| self.dispatch(client, request)))
+                .unwrap_or_else(|_| internal_error()),
+        }
+    }
+    /// Reads the events from the event log, which may decompress archives, without holding the
+    /// log. If the log is rotated while it is read, the events are read again.
+    async fn get_events(&self, since: Option<Timestamp>) -> Response {
+        loop {
+            let Some(snapshot) = self.event_log().map(|log| log.snapshot(since)) else {
+                return Response::Error {
+                    msg: "The event log is not enabled.".to_owned(),
+                    code: None,
+                };
+            };
+            let Ok((snapshot, events)) = tokio::task::spawn_blocking(move || {
+                let events = snapshot.read();
+                (snapshot, events)
+            })
+            .await
+            else {
+                return internal_error();
+            };
+            if self
+                .event_log()
+                .is_some_and(|log| !snapshot.is_current(&log))
+            {
+                continue;
+            }
+            return match events {
+                Ok(events) => Response::Events { events },
+                Err(msg) => Response::InternalError {
+                    msg: format!("Unable to read the event log: {}", msg),
+                },
+            };
+        }
+    }
+    /// Merges a patch into the configuration and writes it to the configuration file if it is
+    /// persisted, only locking the manager once the file is written.
+    async fn patch_config(
+        &self,
+        client: String,
+        patch: serde_json::Value,
+        persist: bool,
+    ) -> Response {
+        let _patching = self.patching_config.lock().await;
+        let prepared = match self.running_config().prepare_patch(patch, persist) {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                return Response::Error {
+                    msg: err.to_string(),
+                    code: None,
+                }
+            }
+        };
+        let Ok((prepared, persisted)) = tokio::task::spawn_blocking(move || {
+            let persisted = prepared.persist();
+            (prepared, persisted)
+        })
+        .await
+        else {
+            return internal_error();
+        };
+        if let Err(err) = persisted {
+            if let LoadConfigError::WriteError(..) = err {
+                self.persistence_failed(&mut self.manager(), "persisting the configuration", &err);
+            }
+            return Response::Error {
+                msg: err.to_string(),
+                code: None,
+            };
+        }
+        let config = self.running_config().apply(prepared);
+        let mut manager = self.manager();
+        manager.set_config(Timestamp::now(), manager_config(&config));
+        manager.record_modification(Timestamp::now(), client);
+        drop(manager);
+        self.changed.notify_one();
+        Response::Success
+    }
+    /// Handles a request from a client that is allowed to use it.
+    fn dispatch(&self, client: String, request: Request) -> Response {
+        match request {
+            Request::GetClients => {
+                return Response::Clients {
+                    clients: self.clients().list(Timestamp::now()),
+                };
+            }
+            Request::SetMaintenance { enabled } => {
+                self.set_maintenance(enabled);
+                return Response::Success;
+            }
+            Request::GetMetrics => {
+                return Response::Metrics {
+                    metrics: self.metrics.snapshot(),
+                };
+            }
+            Request::GetVersion => {
+                return Response::Version {
+                    info: VersionInfo::current(),
+                };
+            }
+            Request::Hello { protocol_version } => return hello(protocol_version),
+            Request::GetInfo => {
+                return Response::Info {
+                    info: Box::new(self.current_info()),
+                };
+            }
+            Request::Ping => return Response::Pong,
+            Request::Subscribe => {
+                return Response::Error {
+                    msg: "Subscribing is only supported by the socket protocol.".to_owned(),
+                    code: None,
+                };
+            }
+            _ => {}
+        }
+        let modifies_state = request.modifies_state();
+        let mut manager = self.manager();
+        let response = match request {
+            Request::UnlockTimer { intent } => manager.unlock_timer(Timestamp::now(), intent),
+            Request::LockTimer => manager.lock_timer(Timestamp::now()),
+            Request::EndBreak => manager.end_break(Timestamp::now()),
+            Request::SpendBreakCredit { duration } => {
+                manager.spend_break_credit(Timestamp::now(), duration)
+            }
+            Request::CompleteRequirement { id, answers } => {
+                manager.complete_requirement(Timestamp::now(), id, answers)
+            }
+            Request::CompleteRequirementByName { name, answers } => {
+                manager.complete_requirement_by_name(Timestamp::now(), name, answers)
+            }
+            Request::IncrementRequirement { name } => {
+                manager.increment_requirement(Timestamp::now(), name)
+            }
+            Request::AddRequirement { name, due } => {
+                manager.add_requirement(Timestamp::now(), name, due, client.clone())
+            }
+            Request::Deactivate { duration } => manager.deactivate(Timestamp::now(), duration),
+            Request::StartFocus { label } => manager.start_focus(Timestamp::now(), label),
+            Request::GetStats => manager.get_stats(Timestamp::now()),
+            Request::PreviewDay { date } => match parse_date(&date) {
+                Some(date) => manager.preview_day(Timestamp::now(), date),
+                None => Response::Error {
+                    msg: format!("Invalid date '{}', expected YYYY-MM-DD.", date),
+                    code: None,
+                },
+            },
+            Request::SimulateChange { changes } => {
+                manager.simulate_change(Timestamp::now(), changes)
+            }
+            Request::ListRequirements { all } => {
+                manager.list_requirements(Timestamp::now(), all.unwrap_or(false))
+            }
+            Request::ReportActivity => manager.report_activity(Timestamp::now()),
+            Request::GetTimer => manager.get_timer(Timestamp::now()),
+            Request::SetTimerState { timer } => manager.set_timer_state(Timestamp::now(), timer),
+            Request::StartCrunchMode { days } => manager.start_crunch_mode(Timestamp::now(), days),
+            Request::SetPreset { name } => {
+                let (work_period_duration, break_duration) = manager.base_timer_durations();
+                if self.running_config().allows_preset(
+                    &name,
+                    work_period_duration.as_minutes(),
+                    break_duration.as_minutes(),
+                ) {
+                    manager.set_preset(Timestamp::now(), name)
+                } else {
+                    Response::Error {
+                        msg: format!(
+                            "The timer preset '{}' loosens restrictions, which config_loosening_delay_hours doesn't allow.",
+                            name
+                        ),
+                        code: Some(RefusalCode::PresetLoosensRestrictions),
+                    }
+                }
+            }
+            Request::BeginActivity { name } => manager.begin_activity(Timestamp::now(), name),
+            Request::EndActivity => manager.end_activity(Timestamp::now()),
+            Request::GetPolicy => manager.get_policy(Timestamp::now()),
+            Request::SubmitPlan { intentions } => {
+                manager.submit_plan(Timestamp::now(), intentions.unwrap_or_default())
+            }
+            Request::ResolveRecovery { fail_open } => {
+                manager.resolve_recovery(Timestamp::now(), fail_open)
+            }
+            Request::ReportLocation { ssid, location } => {
+                manager.report_location(Timestamp::now(), ssid, location)
+            }
+            Request::ApproveDeactivation { id } => {
+                manager.approve_deactivation(Timestamp::now(), id)
+            }
+            Request::GetInfoDelta { cache_version } => {
+                manager.get_info_delta(Timestamp::now(), cache_version)
+            }
+            Request::GetInfoIfChanged { cache_version } => {
+                match manager.get_info_if_changed(cache_version, Timestamp::now()) {
+                    Some((info, cache_version, changes)) => Response::InfoUpdate {
+                        info: Box::new(info),
+                        cache_version,
+                        changes,
+                    },
+                    None => Response::NotModified { cache_version },
+                }
+            }
+            Request::GetDisplayInfo => manager.get_display_info(Timestamp::now()),
+            Request::RejectDeactivation { id } => manager.reject_deactivation(Timestamp::now(), id),
+            Request::GetClients
+            | Request::SetMaintenance { .. }
+            | Request::GetMetrics
+            | Request::GetVersion
+            | Request::Hello { .. }
+            | Request::GetInfo
+            | Request::Ping
+            | Request::Subscribe => unreachable!("handled without locking the manager"),
+            Request::CheckUpdate | Request::GetEvents { .. } | Request::PatchConfig { .. } => {
+                unreachable!("handled asynchronously")
+            }
+        };
+        if modifies_state
+            && !matches!(
+                response,
+                Response::Error { .. } | Response::QuotaExceeded { .. }
+            )
+        {
+            manager.record_modification(Timestamp::now(), client);
+        }
+        self.changed.notify_one();
+        response
+    }
+}
+
+/// Compares a token with an expected one in constant time, so that how long the comparison takes
+/// doesn't reveal how much of the token was guessed right.
+fn tokens_match(expected: &str, token: &str) -> bool {
+    let difference = expected
+        .bytes()
+        .zip(token.bytes())
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    std::hint::black_box(difference) == 0 && expected.len() == token.len()
+}
+
+/// Answers the handshake with the server's protocol version and the requests it supports.
+/// Clients that are too old for the server are refused, while newer clients are expected to fall
+/// back to the server's protocol version and to the requests it supports.
+fn hello(client_version: Option<u32>) -> Response {
+    if let Some(client_version) = client_version.filter(|version| *version < MIN_PROTOCOL_VERSION) {
+        return Response::Error {
+            msg: format!(
+                "This client uses protocol version {}, but the server only supports versions {} to {}. Please update the client.",
+                client_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+            code: None,
+        };
+    }
+    Response::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        version: VERSION.to_owned(),
+        commands: Request::TYPES.iter().map(|kind| kind.to_string()).collect(),
+    }
+}
+
+const UPDATE_CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(24 * 60 * 60);
+/// How long the info may go without a refresh, for the parts that change gradually rather than
+/// at a known time, such as the at-risk requirements
+const MAX_REFRESH_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(1);
 
-    let manager_config = DiagonatorManagerConfig {
-        requirements: config.requirements.unwrap_or(Vec::new()),
-        locked_time_ranges: config.locked_time_ranges.unwrap_or(Vec::new()),
+/// Creates the manager's configuration from the parts of the configuration that it uses.
+fn manager_config(config: &DiagonatorConfig) -> DiagonatorManagerConfig {
+    DiagonatorManagerConfig {
+        requirements: config.requirements.clone().unwrap_or_default(),
+        requirement_groups: config.requirement_groups.clone().unwrap_or_default(),
+        locked_time_ranges: config.locked_time_ranges.clone().unwrap_or_default(),
         work_period_duration: Duration::from_minutes(config.work_period_minutes),
         break_duration: Duration::from_minutes(config.break_minutes),
-    };
-    let manager = Box::leak(Box::new(Mutex::new(DiagonatorManager::new(
-        manager_config,
-        Timestamp::now(),
-    ))));
-    io.ns("/", |s: SocketRef| {
-        s.emit("info_update", manager.lock().unwrap().get_info())
-            .ok();
+        long_break: config
+            .long_break_minutes
+            .map(Duration::from_minutes)
+            .zip(config.periods_per_long_break),
+        location: config.location.clone(),
+        require_intent: config.require_intent.unwrap_or(false),
+        planning_gate: config.planning_gate.unwrap_or(false),
+        daily_review: config.daily_review.clone(),
+        focus_score: config.focus_score.clone(),
+        warn_before_break_end: config
+            .warn_before_break_end_seconds
+            .map(Duration::from_seconds),
+        warn_before_day_end: Duration::from_minutes(
+            config.warn_before_day_end_minutes.unwrap_or(10),
+        ),
+        pressure_duration: Duration::from_minutes(config.pressure_minutes.unwrap_or(10)),
+        auto_unlock_after_break: config.auto_unlock_after_break.unwrap_or(false),
+        auto_unlock_at: config.auto_unlock_at.clone(),
+        auto_unlock_requires_activity: config.auto_unlock_requires_activity.unwrap_or(false),
+        weekly_template: config.weekly_template.clone(),
+        week_start: config.week_starts_on.unwrap_or_default().weekday(),
+        crunch_mode: config.crunch_mode.clone(),
+        deactivation_approval_timeout: config
+            .deactivation_approval
+            .as_ref()
+            .map(|approval| Duration::from_minutes(approval.timeout_minutes)),
+        max_deactivation: config
+            .limits
+            .as_ref()
+            .and_then(|limits| limits.max_deactivation_minutes)
+            .map(Duration::from_minutes),
+        weekly_deactivation_quota: config
+            .limits
+            .as_ref()
+            .and_then(|limits| limits.max_weekly_deactivation_minutes)
+            .map(Duration::from_minutes),
+        hide_completed_requirements_after: config
+            .hide_completed_requirements_after_minutes
+            .map(Duration::from_minutes),
+        merge_locked_time_ranges: config.merge_locked_time_ranges.unwrap_or(false),
+        min_work_period_duration: config.min_work_period_minutes.map(Duration::from_minutes),
+        stopwatch: config.stopwatch.clone(),
+        rest_debt: config.rest_debt.clone(),
+        break_credit: config.break_credit.clone(),
+        presets: config.presets.clone().unwrap_or_default(),
+        day_overrides: config.day_overrides.clone().unwrap_or_default(),
+        profiles: config.profiles.clone().unwrap_or_default(),
+        profile_rules: config.profile_rules.clone().unwrap_or_default(),
+        profile_schedule: config.profile_schedule.clone().unwrap_or_default(),
+        exceptions: AppList::from_config(config.exceptions.as_ref()),
+        blocklist: AppList::from_config(config.blocklist.as_ref()),
+        refusal_messages: config.refusal_messages.clone().unwrap_or_default(),
+    }
+}
+
+pub async fn launch_server(config: DiagonatorConfig) {
+    panic::set_hook(Box::new(|info| {
+        eprintln!("{}\n{}", info, Backtrace::force_capture());
+    }));
+    let keep_alive = std::time::Duration::from_secs(config.keep_alive_seconds.unwrap_or(30));
+    let (layer, io) = SocketIo::builder()
+        .ping_interval(keep_alive)
+        .ping_timeout(keep_alive)
+        .build_layer();
+    let tls = config.tls.as_ref().map(|tls| {
+        load_tls_acceptor(tls).unwrap_or_else(|msg| {
+            eprintln!("Unable to set up TLS: {}", msg);
+            std::process::exit(1);
+        })
+    });
+    let manager_config = manager_config(&config);
+    let running_config = RunningConfig::new(&config);
+
+    let requirement_hooks: Vec<_> = config
+        .all_requirements()
+        .filter(|req| req.on_due.is_some() || req.on_complete.is_some() || req.on_missed.is_some())
+        .cloned()
+        .collect();
+    let hooks = config.hooks.unwrap_or_default();
+    let webhooks = config.webhooks.unwrap_or_default();
+    let matrix = config.matrix;
+    #[cfg(feature = "telegram")]
+    let telegram = config.telegram;
+    let email_digest = config.email_digest;
+    let status_page = config.status_page;
+    let display = config.display;
+    let audio_mute = config.audio_mute;
+    let idle_detection = config.idle_detection;
+    let grpc_bind_on = config.grpc_bind_on;
+    let dbus = config.dbus;
+    let mdns = config.mdns;
+    let check_for_updates = config.check_for_updates.unwrap_or(false);
+    let state_file = config.state_file;
+    let event_log = config.event_log.map(|config| {
+        EventLog::open(config).unwrap_or_else(|msg| {
+            eprintln!("Unable to open the event log: {}", msg);
+            std::process::exit(1);
+        })
     });
+    let state_format = config.state_format.unwrap_or(StateFormat::Json);
+    let mut manager = DiagonatorManager::new(manager_config, Timestamp::now());
+    if let Some(path) = &state_file {
+        restore_state(
+            &mut manager,
+            path,
+            state_format,
+            config.state_recovery.unwrap_or(StateRecovery::FailClosed),
+        );
+    }
+    let state: &'static ServerState = Box::leak(Box::new(ServerState {
+        manager: RwLock::new(manager),
+        admin_token: config.admin_token,
+        auth_token: config.auth_token,
+        partner_token: config
+            .deactivation_approval
+            .map(|approval| approval.partner_token),
+        tokens: config.tokens.unwrap_or_default(),
+        clients: Mutex::new(ClientRegistry::new()),
+        maintenance: AtomicBool::new(false),
+        metrics: Metrics::new(),
+        running_config: Mutex::new(running_config),
+        patching_config: tokio::sync::Mutex::new(()),
+        event_log: event_log.map(Mutex::new),
+        changed: Notify::new(),
+        keep_alive,
+    }));
+    io.ns(
+        "/",
+        move |s: SocketRef, TryData(auth): TryData<SocketAuth>| {
+            let token = auth.ok().and_then(|auth| auth.token);
+            let roles = ListenerRoles::of(&s.req_parts().extensions);
+            let (label, role) = match state.identify(&roles, token.as_deref()) {
+                Ok(client) => client,
+                Err(_) => {
+                    s.disconnect().ok();
+                    return;
+                }
+            };
+            state.clients().subscribe(&label, role, Timestamp::now());
+            // the new subscriber is sent the info right away
+            state.changed.notify_one();
+            s.on_disconnect(move || {
+                state.clients().unsubscribe(&label, Timestamp::now());
+            });
+        },
+    );
 
     let app = axum::Router::new()
         .route(
             "/",
-            post(|Json(request): Json<Request>| async {
-                let mut manager = manager.lock().unwrap();
-                let response = match request {
-                    Request::UnlockTimer => manager.unlock_timer(Timestamp::now()),
-                    Request::LockTimer => manager.lock_timer(Timestamp::now()),
-                    Request::GetInfo => manager.get_info_once(Timestamp::now()),
-                    Request::CompleteRequirement { id } => {
-                        manager.complete_requirement(Timestamp::now(), id)
+            post(move |caller: Caller, body: Bytes| async move {
+                let encoding = Encoding::of_response(&caller.headers);
+                match Encoding::of_request(&caller.headers).decode(&body) {
+                    Ok(serde_json::Value::Array(requests)) => {
+                        encoding.respond(&handle_batch(state, caller, requests).await)
                     }
-                    Request::AddRequirement { name, due } => {
-                        manager.add_requirement(Timestamp::now(), name, due)
-                    }
-                    Request::Deactivate { duration } => {
-                        manager.deactivate(Timestamp::now(), duration)
-                    }
-                };
-                Json(response)
+                    Ok(request) => match parse_request(request) {
+                        Ok(Request::Subscribe) => subscribe(state, &caller, encoding),
+                        Ok(request) => {
+                            encoding.respond(&state.handle_request(caller, request).await)
+                        }
+                        Err(msg) => encoding.respond(&Response::Error { msg, code: None }),
+                    },
+                    Err(msg) => encoding.respond(&Response::Error {
+                        msg: format!("Invalid request: {}", msg),
+                        code: None,
+                    }),
+                }
             }),
         )
-        .layer(layer);
-
-    eprintln!("Server is listening on {}", &config.bind_on);
-    let listener = tokio::net::TcpListener::bind(config.bind_on).await.unwrap();
+        .nest(
+            "/api",
+            rest_api(move |caller, request| state.handle_request(caller, request)),
+        )
+        .route(
+            "/rpc",
+            post(move |caller: Caller, body: Bytes| {
+                handle_json_rpc(body, move |request| {
+                    state.handle_request(caller.clone(), request)
+                })
+            }),
+        )
+        .route(
+            "/ws",
+            get(
+                move |upgrade: WebSocketUpgrade,
+                      Query(params): Query<WebSocketParams>,
+                      extensions: Extensions| {
+                    accept_websocket(state, params, ListenerRoles::of(&extensions), upgrade)
+                },
+            ),
+        );
+    #[cfg(feature = "graphql")]
+    let app = app.route(
+        "/graphql",
+        post(move |caller: Caller, body: Bytes| {
+            handle_graphql(body, move |request| state.handle_request(caller, request))
+        }),
+    );
+    let app = app.layer(layer);
 
-    let server = async { axum::serve(listener, app).await.unwrap() };
+    let limits = ConnectionLimits::new(
+        config.max_connections,
+        config
+            .idle_timeout_seconds
+            .map(std::time::Duration::from_secs),
+    );
+    // `bind_on` uses TLS whenever it is configured and admits all roles, while the other listeners
+    // choose for themselves
+    let listeners = std::iter::once((config.bind_on, tls.clone(), None)).chain(
+        config.listeners.into_iter().flatten().map(|listener| {
+            let tls = tls.clone().filter(|_| listener.tls.unwrap_or(false));
+            (listener.bind_on, tls, listener.roles)
+        }),
+    );
+    let listeners: Vec<_> = listeners.collect();
+    // the server is advertised for as long as it runs
+    #[cfg(feature = "mdns")]
+    let _advertisement = mdns.and_then(|mdns| {
+        let endpoints: Vec<_> = listeners
+            .iter()
+            .map(|(bind_on, tls, _)| (bind_on.clone(), tls.is_some()))
+            .collect();
+        crate::mdns::run_mdns_advertisement(&mdns, &endpoints)
+    });
+    #[cfg(not(feature = "mdns"))]
+    if mdns.is_some() {
+        eprintln!("WARNING: mDNS is configured, but this build doesn't include the mdns feature");
+    }
+    let server = join_all(listeners.into_iter().map(|(bind_on, tls, roles)| {
+        let app = app
+            .clone()
+            .layer(Extension(ListenerRoles(roles.map(Arc::from))));
+        let limits = limits.clone();
+        async move {
+            eprintln!(
+                "Server is listening on {}{}",
+                bind_on,
+                if tls.is_some() { " with TLS" } else { "" }
+            );
+            if let Err(err) = listen(&bind_on, tls, app, limits, &state.metrics).await {
+                eprintln!("Unable to listen on {}: {}", bind_on, err);
+                std::process::exit(1);
+            }
+        }
+    }));
     let watch_for_changes = async {
         let mut cache_version = DiagonatorManager::NO_CACHE;
+        let mut latest_info = None;
+        // the changes that haven't been delivered to each subscriber yet, which are all changes
+        // for subscribers that haven't been delivered any info
+        let mut undelivered_changes: HashMap<Sid, ChangeKinds> = HashMap::new();
         loop {
-            if let Some((new_info, new_version)) = manager
-                .lock()
-                .unwrap()
-                .get_info_if_changed(cache_version, Timestamp::now())
-            {
-                io.emit("info_update", new_info).unwrap();
+            let (changed_info, events, saved_state) = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut manager = state.manager();
+                let changed_info = manager.get_info_if_changed(cache_version, Timestamp::now());
+                // the state is saved whenever the info changes
+                let saved_state = changed_info
+                    .as_ref()
+                    .filter(|_| state_file.is_some())
+                    .map(|_| manager.save_state());
+                (changed_info, manager.take_events(), saved_state)
+            }))
+            .unwrap_or((None, Vec::new(), None));
+            if let (Some(path), Some(saved_state)) = (&state_file, saved_state) {
+                if let Err(err) = save_state(path, state_format, &saved_state).await {
+                    let what = format!("saving state to {}", path.display());
+                    state.persistence_failed(&mut state.manager(), &what, err);
+                }
+            }
+            if let Some((new_info, new_version, changes)) = changed_info {
+                latest_info = Some(new_info);
                 cache_version = new_version;
+                for undelivered in undelivered_changes.values_mut() {
+                    *undelivered |= changes;
+                }
             }
-            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+            let sockets = io.sockets().unwrap_or_default();
+            undelivered_changes.retain(|sid, _| sockets.iter().any(|socket| socket.id == *sid));
+            if let Some(info) = &latest_info {
+                for socket in &sockets {
+                    let changes = *undelivered_changes
+                        .entry(socket.id)
+                        .or_insert(ChangeKinds::ALL);
+                    if changes == ChangeKinds::NONE {
+                        continue;
+                    }
+                    // if a slow subscriber's buffer is full, it is sent the latest info
+                    // once there is space again, skipping any versions in between
+                    match socket.emit("info_update", &InfoPush { info, changes }) {
+                        Ok(()) => {
+                            undelivered_changes.insert(socket.id, ChangeKinds::NONE);
+                        }
+                        Err(SendError::Socket(SocketError::InternalChannelFull(_))) => {
+                            state.metrics.record_coalesced_info_update();
+                        }
+                        Err(_) => {}
+                    }
+                }
+            }
+            if !events.is_empty() && state.event_log.is_some() {
+                let (current_time, logged) = (Timestamp::now(), events.clone());
+                // appending can rotate and compress the log, which would hold up the runtime
+                let event_log_result = tokio::task::spawn_blocking(move || {
+                    state
+                        .event_log()
+                        .map(|mut event_log| event_log.append(current_time, &logged))
+                })
+                .await;
+                if let Ok(Some(Err(err))) = event_log_result {
+                    state.persistence_failed(&mut state.manager(), "writing to the event log", err);
+                }
+            }
+            for event in events {
+                run_hooks(&hooks, &event);
+                run_requirement_hooks(&requirement_hooks, &event);
+                send_webhooks(&webhooks, &event);
+                for socket in &sockets {
+                    if let Err(SendError::Socket(SocketError::InternalChannelFull(_))) =
+                        socket.emit("event", &event)
+                    {
+                        state.metrics.record_dropped_event();
+                    }
+                }
+            }
+            // transitions are pushed right when they happen, rather than at the next refresh
+            let deadline = state.read_manager().next_deadline().map(|deadline| {
+                std::time::UNIX_EPOCH
+                    + std::time::Duration::from_secs(deadline.as_seconds().max(0) as u64)
+            });
+            let wait = deadline
+                .and_then(|deadline| deadline.duration_since(std::time::SystemTime::now()).ok())
+                .map_or(MAX_REFRESH_INTERVAL, |wait| wait.min(MAX_REFRESH_INTERVAL));
+            tokio::select! {
+                _ = state.changed.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    };
+    let check_updates_periodically = async {
+        if !check_for_updates {
+            return;
+        }
+        let mut interval = tokio::time::interval(UPDATE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Response::Error { msg, .. } = state.check_update().await {
+                eprintln!("{}", msg);
+            }
+        }
+    };
+    let matrix_bot = async {
+        if let Some(matrix) = matrix {
+            run_matrix_bot(matrix, |label, role, request| {
+                state.handle_chat_request(label, role, request)
+            })
+            .await;
+        }
+    };
+    // builds without the telegram feature refuse configurations with a telegram table
+    let telegram_bot = async {
+        #[cfg(feature = "telegram")]
+        if let Some(telegram) = telegram {
+            crate::telegram::run_telegram_bot(telegram, |label, role, request| {
+                state.handle_chat_request(label, role, request)
+            })
+            .await;
+        }
+    };
+    let daily_digest = async {
+        if let Some(email_digest) = email_digest {
+            run_email_digest(email_digest, |date| {
+                state.manager().get_day_stats(Timestamp::now(), date)
+            })
+            .await;
+        }
+    };
+    let status_page = async {
+        if let Some(status_page) = status_page {
+            run_status_page(status_page, || state.current_info()).await;
+        }
+    };
+    let display_server = async {
+        if let Some(display) = display {
+            run_display_server(display, || state.current_info()).await;
+        }
+    };
+    let mute_audio = async {
+        if let Some(audio_mute) = audio_mute {
+            run_audio_mute(audio_mute, || state.current_info()).await;
+        }
+    };
+    let detect_idle = async {
+        if let Some(idle_detection) = idle_detection {
+            run_idle_detection(idle_detection, |request| {
+                state.handle_chat_request("idle-detection".to_owned(), Role::User, request)
+            })
+            .await;
+        }
+    };
+    let grpc_server = async {
+        let Some(bind_on) = grpc_bind_on else {
+            return;
+        };
+        #[cfg(feature = "grpc")]
+        {
+            let updates = state.read_manager().subscribe();
+            crate::grpc::run_grpc_server(
+                bind_on,
+                move |headers, request| state.handle_request(Caller::from(headers), request),
+                updates,
+                state.keep_alive,
+            )
+            .await;
+        }
+        #[cfg(not(feature = "grpc"))]
+        {
+            let _ = bind_on;
+            eprintln!(
+                "WARNING: grpc_bind_on is set, but this build doesn't include the grpc feature"
+            );
         }
     };
-    tokio::join!(server, watch_for_changes);
+    let dbus_service = async {
+        let Some(dbus) = dbus else {
+            return;
+        };
+        #[cfg(feature = "dbus")]
+        {
+            let role = dbus.role.unwrap_or(Role::User);
+            let updates = state.read_manager().subscribe();
+            crate::dbus::run_dbus_service(
+                move |request| Box::pin(state.handle_dbus_request(role, request)),
+                updates,
+            )
+            .await;
+        }
+        #[cfg(not(feature = "dbus"))]
+        {
+            let _ = dbus;
+            eprintln!("WARNING: The D-Bus service is configured, but this build doesn't include the dbus feature");
+        }
+    };
+    tokio::join!(
+        server,
+        grpc_server,
+        dbus_service,
+        watch_for_changes,
+        dump_state_on_signal(state),
+        check_updates_periodically,
+        matrix_bot,
+        telegram_bot,
+        daily_digest,
+        status_page,
+        display_server,
+        mute_audio,
+        detect_idle
+    );
+}
+
+/// Parses a request of the socket protocol. Unknown or malformed requests get an error response
+/// instead of a plain text rejection, so that clients of other versions can handle them.
+fn parse_request(request: serde_json::Value) -> Result<Request, String> {
+    serde_json::from_value(request).map_err(|err| format!("Invalid request: {}", err))
+}
+
+/// Handles a batch of requests one after another and returns their responses in the same order,
+/// so that e.g. a `GetInfo` after a `CompleteRequirement` already sees the completed requirement.
+/// A request that fails doesn't stop the requests after it.
+async fn handle_batch(
+    state: &'static ServerState,
+    caller: Caller,
+    requests: Vec<serde_json::Value>,
+) -> Vec<Response> {
+    let mut responses = Vec::with_capacity(requests.len());
+    for request in requests {
+        responses.push(match parse_request(request) {
+            Ok(Request::Subscribe) => Response::Error {
+                msg: "Subscribe can't be sent in a batch.".to_owned(),
+                code: None,
+            },
+            Ok(request) => state.handle_request(caller.clone(), request).await,
+            Err(msg) => Response::Error { msg, code: None },
+        });
+    }
+    responses
+}
+
+fn internal_error() -> Response {
+    Response::InternalError {
+        msg: "The server encountered an internal error while handling the request.".to_owned(),
+    }
+}
+
+/// Upgrades the connection to a WebSocket that is sent the current info as JSON whenever it
+/// changes. Browsers can't set headers on WebSocket connections, so the token is passed as the
+/// `token` query parameter instead.
+async fn accept_websocket(
+    state: &'static ServerState,
+    params: WebSocketParams,
+    roles: ListenerRoles,
+    upgrade: WebSocketUpgrade,
+) -> axum::response::Response {
+    let (label, role) = match state.identify(&roles, params.token.as_deref()) {
+        Ok(client) => client,
+        Err(msg) => return (StatusCode::UNAUTHORIZED, msg).into_response(),
+    };
+    if let Err((msg, code)) = state.check_permission(&label, role, &Request::GetInfo) {
+        let msg = state
+            .read_manager()
+            .refusal_message(code)
+            .map_or(msg, str::to_owned);
+        return (StatusCode::FORBIDDEN, msg).into_response();
+    }
+    upgrade.on_upgrade(move |socket| async move {
+        state.clients().subscribe(&label, role, Timestamp::now());
+        let encoding = params.format.unwrap_or(Encoding::Json);
+        let updates = state.read_manager().subscribe();
+        push_info_updates(updates, socket, state.keep_alive, encoding).await;
+        state.clients().unsubscribe(&label, Timestamp::now());
+    })
+}
+
+/// Unregisters a subscriber from the client registry once its connection is closed.
+struct SubscriberGuard {
+    state: &'static ServerState,
+    label: String,
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.state
+            .clients()
+            .unsubscribe(&self.label, Timestamp::now());
+    }
 }
+
+/// Keeps the connection of a `Subscribe` request open and streams an `InfoUpdate` response
+/// whenever the info changes, starting with the current info. Each response is a single line of
+/// JSON, and like Socket.IO subscribers, a slow client skips the versions that changed in the
+/// meantime. A `Pong` response is sent whenever nothing was sent for the keep-alive interval, so
+/// that the connection is closed once writing to it fails.
+fn subscribe(
+    state: &'static ServerState,
+    caller: &Caller,
+    encoding: Encoding,
+) -> axum::response::Response {
+    let (label, role) = match state.authorize(caller, &Request::Subscribe) {
+        Ok(client) => client,
+        Err((msg, code)) => {
+            let response = state.with_refusal_message(Response::Error { msg, code });
+            return encoding.respond(&response);
+        }
+    };
+    state.clients().subscribe(&label, role, Timestamp::now());
+    let guard = SubscriberGuard { state, label };
+    let mut updates = state.read_manager().subscribe();
+    updates.mark_changed();
+    let previous: Option<CurrentInfo> = None;
+    let lines = futures_util::stream::unfold(
+        (updates, previous, guard),
+        move |(mut updates, previous, guard)| async move {
+            let Ok(changed) = tokio::time::timeout(state.keep_alive, updates.changed()).await
+            else {
+                let line = encode_line(encoding, &Response::Pong);
+                return Some((Ok(line), (updates, previous, guard)));
+            };
+            changed.ok()?;
+            let (info, cache_version) = updates.borrow_and_update().clone();
+            let response = Response::InfoUpdate {
+                info: Box::new(info.clone()),
+                cache_version,
+                changes: changes_since(&info, previous.as_ref()),
+            };
+            let line = encode_line(encoding, &response);
+            Some((Ok::<_, Infallible>(line), (updates, Some(info), guard)))
+        },
+    );
+    let content_type = match encoding {
+        Encoding::Json => "application/x-ndjson",
+        Encoding::MessagePack => encoding.content_type(),
+    };
+    (
+        [(header::CONTENT_TYPE, content_type)],
+        Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+/// Encodes a response of a `Subscribe` stream. JSON responses are separated by newlines, while
+/// MessagePack values follow each other directly.
+fn encode_line(encoding: Encoding, response: &Response) -> Vec<u8> {
+    let mut line = encoding.encode(response);
+    if encoding == Encoding::Json {
+        line.push(b'\n');
+    }
+    line
+}
+
+/// Encodes a WebSocket message as a text message for JSON or a binary message for MessagePack.
+fn websocket_message<T: Serialize>(encoding: Encoding, value: &T) -> Message {
+    match encoding {
+        Encoding::Json => Message::Text(serde_json::to_string(value).unwrap()),
+        Encoding::MessagePack => Message::Binary(encoding.encode(value)),
+    }
+}
+
+/// Sends the latest info to the WebSocket whenever it changes, until the client disconnects.
+/// Like Socket.IO subscribers, a slow client skips the versions that changed in the meantime.
+/// The client is pinged every `keep_alive`, and the connection is closed if it hasn't sent
+/// anything since the previous ping.
+async fn push_info_updates(
+    mut updates: watch::Receiver<(CurrentInfo, u64)>,
+    socket: WebSocket,
+    keep_alive: std::time::Duration,
+    encoding: Encoding,
+) {
+    let (mut sink, mut stream) = socket.split();
+    updates.mark_changed();
+    let mut previous: Option<CurrentInfo> = None;
+    let mut pings = tokio::time::interval_at(tokio::time::Instant::now() + keep_alive, keep_alive);
+    let mut awaiting_pong = false;
+    loop {
+        tokio::select! {
+            changed = updates.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let (info, _) = updates.borrow_and_update().clone();
+                let changes = changes_since(&info, previous.as_ref());
+                let message = websocket_message(encoding, &InfoPush { info: &info, changes });
+                if sink.send(message).await.is_err() {
+                    break;
+                }
+                previous = Some(info);
+            }
+            _ = pings.tick() => {
+                if awaiting_pong || sink.send(Message::Ping(Vec::new())).await.is_err() {
+                    break;
+                }
+                awaiting_pong = true;
+            }
+            // reading incoming messages answers pings and detects closing, and clients that
+            // can't send ping frames, such as browsers, can send a Ping request instead
+            message = stream.next() => {
+                let Some(Ok(message)) = message else {
+                    break;
+                };
+                awaiting_pong = false;
+                let request = match message {
+                    Message::Text(text) => Encoding::Json.decode(text.as_bytes()),
+                    Message::Binary(bytes) => Encoding::MessagePack.decode(&bytes),
+                    _ => continue,
+                };
+                if let Ok(Request::Ping) = request {
+                    if sink.send(websocket_message(encoding, &Response::Pong)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Dumps the server's state to stderr every time it receives SIGUSR1.
+#[cfg(unix)]
+async fn dump_state_on_signal(state: &ServerState) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut signals = signal(SignalKind::user_defined1()).unwrap();
+    while signals.recv().await.is_some() {
+        state.dump_state();
+    }
+}
+
+#[cfg(not(unix))]
+async fn dump_state_on_signal(_state: &ServerState) {}
@@ -0,0 +1,207 @@
+#[cfg(windows)]
+use crate::config::DiagonatorConfig;
+use std::path::Path;
+#[cfg(target_os = "macos")]
+use std::path::PathBuf;
+#[cfg(windows)]
+use std::sync::{Mutex, PoisonError};
+
+#[cfg(target_os = "macos")]
+const LAUNCHD_LABEL: &str = "com.github.yaxollum.diagonator-server";
+
+/// Installs a launchd agent that starts the server when the user logs in and restarts it if it
+/// exits. Returns instructions for starting it.
+#[cfg(target_os = "macos")]
+pub fn install_service(config_path: Option<&Path>) -> Result<String, String> {
+    let home = dirs::home_dir().ok_or("Unable to determine path to home directory")?;
+    let exe = std::env::current_exe()
+        .map_err(|err| format!("Unable to determine path to executable: {}", err))?;
+    let mut args = vec![exe];
+    if let Some(config_path) = config_path {
+        args.push("--config".into());
+        args.push(config_path.to_owned());
+    }
+    let program_arguments: String = args
+        .iter()
+        .map(|arg| {
+            format!(
+                "        <string>{}</string>\n",
+                escape_xml(&arg.to_string_lossy())
+            )
+        })
+        .collect();
+    let log_path = home.join("Library/Logs/diagonator-server.log");
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+    <key>StandardErrorPath</key>
+    <string>{}</string>
+</dict>
+</plist>
+"#,
+        LAUNCHD_LABEL,
+        program_arguments,
+        escape_xml(&log_path.to_string_lossy())
+    );
+    let agents_dir = home.join("Library/LaunchAgents");
+    std::fs::create_dir_all(&agents_dir)
+        .map_err(|err| format!("Unable to create {}: {}", agents_dir.display(), err))?;
+    let plist_path = agents_dir.join(format!("{}.plist", LAUNCHD_LABEL));
+    std::fs::write(&plist_path, plist)
+        .map_err(|err| format!("Unable to write {}: {}", plist_path.display(), err))?;
+    Ok(format!(
+        "Installed launchd agent at {0}\nRun `launchctl load {0}` to start it now",
+        plist_path.display()
+    ))
+}
+
+#[cfg(windows)]
+const SERVICE_NAME: &str = "diagonator-server";
+
+/// Registers a Windows service that starts the server when the computer boots, which runs the
+/// executable with `run-service`. Returns instructions for starting it.
+#[cfg(windows)]
+pub fn install_service(config_path: Option<&Path>) -> Result<String, String> {
+    use std::ffi::OsString;
+    use windows_service::service::{
+        ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType,
+    };
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+    let exe = std::env::current_exe()
+        .map_err(|err| format!("Unable to determine path to executable: {}", err))?;
+    let mut launch_arguments = vec![OsString::from("run-service")];
+    if let Some(config_path) = config_path {
+        // the service doesn't run in the current directory
+        let config_path = std::path::absolute(config_path)
+            .map_err(|err| format!("Unable to determine path to config file: {}", err))?;
+        launch_arguments.push("--config".into());
+        launch_arguments.push(config_path.into());
+    }
+    let manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CREATE_SERVICE)
+            .map_err(|err| format!("Unable to connect to the service manager: {}", err))?;
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: "Diagonator server".into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: exe,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    manager
+        .create_service(&info, ServiceAccess::QUERY_STATUS)
+        .map_err(|err| format!("Unable to create service: {}", err))?;
+    Ok(format!(
+        "Installed Windows service {0}\nRun `sc start {0}` to start it now",
+        SERVICE_NAME
+    ))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn install_service(_config_path: Option<&Path>) -> Result<String, String> {
+    Err("install-service is only supported on macOS and Windows".to_owned())
+}
+
+/// The server that the service starts once the service control manager calls `service_main`,
+/// together with the runtime that it runs on
+#[cfg(windows)]
+static PENDING_SERVER: Mutex<Option<(tokio::runtime::Handle, DiagonatorConfig)>> = Mutex::new(None);
+
+#[cfg(windows)]
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+/// Runs the server as the Windows service installed by `install_service`, returning once the
+/// service is stopped.
+#[cfg(windows)]
+pub fn run_service(config: DiagonatorConfig) -> Result<(), String> {
+    *PENDING_SERVER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) =
+        Some((tokio::runtime::Handle::current(), config));
+    // the dispatcher blocks until the service stops, calling `service_main` on its own thread
+    tokio::task::block_in_place(|| {
+        windows_service::service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+    })
+    .map_err(|err| format!("Unable to start the service dispatcher: {}", err))
+}
+
+#[cfg(windows)]
+fn service_main(_arguments: Vec<std::ffi::OsString>) {
+    use std::sync::Arc;
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    let Some((runtime, config)) = PENDING_SERVER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take()
+    else {
+        return;
+    };
+    let stop = Arc::new(tokio::sync::Notify::new());
+    let stop_handler = stop.clone();
+    let status_handle =
+        match service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                stop_handler.notify_one();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }) {
+            Ok(status_handle) => status_handle,
+            Err(err) => {
+                eprintln!("Unable to register the service control handler: {}", err);
+                return;
+            }
+        };
+    let set_state = |current_state, controls_accepted| {
+        let status = ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: std::time::Duration::default(),
+            process_id: None,
+        };
+        if let Err(err) = status_handle.set_service_status(status) {
+            eprintln!("Unable to report the service status: {}", err);
+        }
+    };
+    set_state(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+    );
+    runtime.block_on(async {
+        tokio::select! {
+            _ = crate::server::launch_server(config) => {}
+            _ = stop.notified() => {}
+        }
+    });
+    set_state(ServiceState::Stopped, ServiceControlAccept::empty());
+}
+
+#[cfg(target_os = "macos")]
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
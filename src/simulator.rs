@@ -1,50 +1,79 @@
 use crate::manager::{CurrentState, CurrentStateReason};
 use crate::time::Timestamp;
+use std::collections::{BTreeMap, HashMap};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StateChangeKind {
     BreakTimerUnlockable,
     BreakTimerLocked,
     RangeLocked(u64),
     RangeUnlocked(u64),
     RequirementLocked(u64),
+    CriticalRequirementLocked(u64),
+    RequirementExpired(u64),
+    CriticalRequirementExpired(u64),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct StateChange {
     pub kind: StateChangeKind,
     pub time: Timestamp,
 }
 
+/// The active locks of one kind, in the order in which they were added.
+#[derive(Clone, Default)]
 struct Locks {
-    locks: Vec<u64>,
+    order: BTreeMap<u64, u64>,
+    positions: HashMap<u64, u64>,
+    next_position: u64,
 }
 
 impl Locks {
     fn add_lock(&mut self, id: u64) {
+        let position = self.next_position;
         assert!(
-            !self.locks.iter().any(|&lock_id| lock_id == id),
+            self.positions.insert(id, position).is_none(),
             "duplicate lock with id {}",
             id
         );
-        self.locks.push(id);
+        self.order.insert(position, id);
+        self.next_position += 1;
     }
     fn unlock(&mut self, id: u64) {
-        let index = self
-            .locks
-            .iter()
-            .position(|&lock_id| lock_id == id)
-            .expect(&format!("lock with id {} not found", id));
-        self.locks.remove(index);
+        let position = self
+            .positions
+            .remove(&id)
+            .unwrap_or_else(|| panic!("lock with id {} not found", id));
+        self.order.remove(&position);
     }
     fn is_empty(&self) -> bool {
-        self.locks.is_empty()
+        self.order.is_empty()
     }
     fn first(&self) -> Option<u64> {
-        self.locks.first().copied()
+        self.order.values().next().copied()
     }
-    fn new() -> Self {
-        Self { locks: Vec::new() }
+}
+
+/// The locks that are active at some point of the simulation.
+#[derive(Clone, Default)]
+struct LockState {
+    ranges: Locks,
+    requirements: Locks,
+    critical_requirements: Locks,
+}
+
+impl LockState {
+    fn apply(&mut self, kind: StateChangeKind) {
+        use StateChangeKind::*;
+        match kind {
+            BreakTimerUnlockable | BreakTimerLocked => {}
+            RangeLocked(id) => self.ranges.add_lock(id),
+            RangeUnlocked(id) => self.ranges.unlock(id),
+            RequirementLocked(id) => self.requirements.add_lock(id),
+            CriticalRequirementLocked(id) => self.critical_requirements.add_lock(id),
+            RequirementExpired(id) => self.requirements.unlock(id),
+            CriticalRequirementExpired(id) => self.critical_requirements.unlock(id),
+        }
     }
 }
 
@@ -55,89 +84,435 @@ pub struct SimulatorResult {
     pub reason: CurrentStateReason,
 }
 
+/// Simulates the state changes of the constraints to find the current state and how long it
+/// lasts. The state changes of the requirements and locked time ranges are only replaced when
+/// the constraints change, and a run continues from the locks that were active at the time of
+/// the previous run, so that refreshes stay cheap with many constraints.
+#[derive(Clone, Default)]
 pub struct Simulator {
+    /// The state changes of the requirements and locked time ranges, sorted by time
     changes: Vec<StateChange>,
+    /// How many of the sorted state changes were applied to `locks`
+    applied: usize,
+    /// The locks that were active at `simulated_until`
+    locks: LockState,
+    simulated_until: Timestamp,
 }
 
 impl Simulator {
     pub fn new() -> Self {
-        Self {
-            changes: Vec::new(),
+        Self::default()
+    }
+    /// Replaces the state changes of the requirements and locked time ranges, which are given in
+    /// order of priority, and starts the simulation over.
+    pub fn set_changes(&mut self, mut changes: Vec<StateChange>) {
+        // stable sort preserves original order of state changes with the same time
+        // state changes that were given earlier get higher priority when determining the reason
+        changes.sort_by_key(|sc| sc.time);
+        self.changes = changes;
+        self.reset();
+    }
+    /// Determines the state at the target time from the state changes of the constraints and
+    /// those of the break timer, which are not kept between runs.
+    pub fn run(
+        &mut self,
+        target_time: Timestamp,
+        break_timer_changes: &[StateChange],
+    ) -> SimulatorResult {
+        if target_time < self.simulated_until {
+            self.reset();
+        }
+        while let Some(change) = self.changes.get(self.applied) {
+            if change.time > target_time {
+                break;
+            }
+            self.locks.apply(change.kind);
+            self.applied += 1;
+        }
+        self.simulated_until = target_time;
+        // the break timer's changes are pushed after those of the constraints, so they come last
+        // among the changes with the same time
+        let mut break_timer_changes = break_timer_changes.to_vec();
+        break_timer_changes.sort_by_key(|sc| sc.time);
+        let mut break_timer_state = CurrentState::Unlocked;
+        let mut break_timer_applied = 0;
+        while let Some(change) = break_timer_changes.get(break_timer_applied) {
+            if change.time > target_time {
+                break;
+            }
+            break_timer_state = Self::break_timer_state(change.kind, break_timer_state);
+            break_timer_applied += 1;
+        }
+        let mut locks = self.locks.clone();
+        let simulator_state = Self::calc_state(&locks, break_timer_state);
+        let mut constraint_changes = self.changes[self.applied..].iter().peekable();
+        let mut break_timer_changes = break_timer_changes[break_timer_applied..].iter().peekable();
+        loop {
+            let change = match (constraint_changes.peek(), break_timer_changes.peek()) {
+                (Some(constraint), Some(break_timer)) if break_timer.time < constraint.time => {
+                    break_timer_changes.next()
+                }
+                (Some(_), _) => constraint_changes.next(),
+                (None, _) => break_timer_changes.next(),
+            };
+            let Some(change) = change else {
+                break;
+            };
+            locks.apply(change.kind);
+            break_timer_state = Self::break_timer_state(change.kind, break_timer_state);
+            if simulator_state != Self::calc_state(&locks, break_timer_state) {
+                return SimulatorResult {
+                    target_state: simulator_state,
+                    until: Some(change.time),
+                    reason: match change.kind {
+                        StateChangeKind::BreakTimerUnlockable
+                        | StateChangeKind::BreakTimerLocked => CurrentStateReason::BreakTimer,
+                        StateChangeKind::RangeLocked(id) | StateChangeKind::RangeUnlocked(id) => {
+                            CurrentStateReason::LockedTimeRange { id }
+                        }
+                        StateChangeKind::RequirementLocked(id)
+                        | StateChangeKind::RequirementExpired(id) => {
+                            CurrentStateReason::RequirementNotMet { id }
+                        }
+                        StateChangeKind::CriticalRequirementLocked(id)
+                        | StateChangeKind::CriticalRequirementExpired(id) => {
+                            CurrentStateReason::CriticalRequirementNotMet { id }
+                        }
+                    },
+                };
+            }
+        }
+        SimulatorResult {
+            target_state: simulator_state,
+            until: None,
+            reason: match simulator_state {
+                CurrentState::Unlocked => CurrentStateReason::NoConstraints,
+                CurrentState::Unlockable => CurrentStateReason::BreakTimer,
+                CurrentState::Locked => {
+                    if let Some(id) = locks.critical_requirements.first() {
+                        CurrentStateReason::CriticalRequirementNotMet { id }
+                    } else if let Some(id) = locks.requirements.first() {
+                        CurrentStateReason::RequirementNotMet { id }
+                    } else if let Some(id) = locks.ranges.first() {
+                        CurrentStateReason::LockedTimeRange { id }
+                    } else {
+                        CurrentStateReason::BreakTimer
+                    }
+                }
+            },
         }
     }
-    pub fn push(&mut self, change: StateChange) {
-        self.changes.push(change);
+    /// Starts the simulation over from the start of the sorted state changes.
+    fn reset(&mut self) {
+        self.applied = 0;
+        self.locks = LockState::default();
+        self.simulated_until = Timestamp::ZERO;
     }
-    pub fn run(&mut self, target_time: Timestamp) -> SimulatorResult {
-        // stable sort preserves original order of state changes with the same time
-        // state changes that were pushed earlier get higher priority when determining the reason
-        self.changes.sort_by_key(|sc| sc.time);
-        let mut locked_ranges = Locks::new();
-        let mut locked_requirements = Locks::new();
+    fn break_timer_state(kind: StateChangeKind, state: CurrentState) -> CurrentState {
+        match kind {
+            StateChangeKind::BreakTimerUnlockable => CurrentState::Unlockable,
+            StateChangeKind::BreakTimerLocked => CurrentState::Locked,
+            _ => state,
+        }
+    }
+    fn calc_state(locks: &LockState, break_timer_state: CurrentState) -> CurrentState {
+        if locks.ranges.is_empty()
+            && locks.requirements.is_empty()
+            && locks.critical_requirements.is_empty()
+        {
+            break_timer_state
+        } else {
+            CurrentState::Locked
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The simulator before it kept its state between runs, which sorted all state changes,
+    /// including those of the break timer, and replayed them from the start on every run.
+    fn reference_run(
+        constraint_changes: &[StateChange],
+        break_timer_changes: &[StateChange],
+        target_time: Timestamp,
+    ) -> SimulatorResult {
+        let mut changes: Vec<StateChange> = constraint_changes
+            .iter()
+            .chain(break_timer_changes)
+            .cloned()
+            .collect();
+        changes.sort_by_key(|sc| sc.time);
+        let mut ranges = Vec::new();
+        let mut requirements = Vec::new();
+        let mut critical_requirements = Vec::new();
+        let unlock = |locks: &mut Vec<u64>, id| {
+            let index = locks.iter().position(|&lock| lock == id).unwrap();
+            locks.remove(index);
+        };
         let mut break_timer_state = CurrentState::Unlocked;
         let mut simulator_state = CurrentState::Unlocked;
-        let mut simulator_result: Option<SimulatorResult> = None;
-        for change in &self.changes {
+        for change in &changes {
             use StateChangeKind::*;
             match change.kind {
                 BreakTimerUnlockable => break_timer_state = CurrentState::Unlockable,
                 BreakTimerLocked => break_timer_state = CurrentState::Locked,
-                RangeLocked(id) => locked_ranges.add_lock(id),
-                RangeUnlocked(id) => locked_ranges.unlock(id),
-                RequirementLocked(id) => locked_requirements.add_lock(id),
+                RangeLocked(id) => ranges.push(id),
+                RangeUnlocked(id) => unlock(&mut ranges, id),
+                RequirementLocked(id) => requirements.push(id),
+                CriticalRequirementLocked(id) => critical_requirements.push(id),
+                RequirementExpired(id) => unlock(&mut requirements, id),
+                CriticalRequirementExpired(id) => unlock(&mut critical_requirements, id),
             }
             let state_after_change =
-                Self::calc_state(&locked_ranges, &locked_requirements, break_timer_state);
+                if ranges.is_empty() && requirements.is_empty() && critical_requirements.is_empty()
+                {
+                    break_timer_state
+                } else {
+                    CurrentState::Locked
+                };
             if simulator_state != state_after_change {
                 if change.time > target_time {
-                    simulator_result = Some(SimulatorResult {
+                    return SimulatorResult {
                         target_state: simulator_state,
                         until: Some(change.time),
                         reason: match change.kind {
-                            StateChangeKind::BreakTimerUnlockable
-                            | StateChangeKind::BreakTimerLocked => CurrentStateReason::BreakTimer,
-                            StateChangeKind::RangeLocked(id)
-                            | StateChangeKind::RangeUnlocked(id) => {
+                            BreakTimerUnlockable | BreakTimerLocked => {
+                                CurrentStateReason::BreakTimer
+                            }
+                            RangeLocked(id) | RangeUnlocked(id) => {
                                 CurrentStateReason::LockedTimeRange { id }
                             }
-                            StateChangeKind::RequirementLocked(id) => {
+                            RequirementLocked(id) | RequirementExpired(id) => {
                                 CurrentStateReason::RequirementNotMet { id }
                             }
+                            CriticalRequirementLocked(id) | CriticalRequirementExpired(id) => {
+                                CurrentStateReason::CriticalRequirementNotMet { id }
+                            }
                         },
-                    });
-                    break;
-                } else {
-                    simulator_state = state_after_change;
+                    };
                 }
+                simulator_state = state_after_change;
             }
         }
-        simulator_result.unwrap_or(SimulatorResult {
+        SimulatorResult {
             target_state: simulator_state,
             until: None,
             reason: match simulator_state {
                 CurrentState::Unlocked => CurrentStateReason::NoConstraints,
                 CurrentState::Unlockable => CurrentStateReason::BreakTimer,
                 CurrentState::Locked => {
-                    if let Some(id) = locked_requirements.first() {
+                    if let Some(&id) = critical_requirements.first() {
+                        CurrentStateReason::CriticalRequirementNotMet { id }
+                    } else if let Some(&id) = requirements.first() {
                         CurrentStateReason::RequirementNotMet { id }
-                    } else if let Some(id) = locked_ranges.first() {
+                    } else if let Some(&id) = ranges.first() {
                         CurrentStateReason::LockedTimeRange { id }
                     } else {
                         CurrentStateReason::BreakTimer
                     }
                 }
             },
-        })
-    }
-    fn calc_state(
-        locked_ranges: &Locks,
-        locked_requirements: &Locks,
-        break_timer_state: CurrentState,
-    ) -> CurrentState {
-        if locked_ranges.is_empty() && locked_requirements.is_empty() {
-            break_timer_state
-        } else {
-            CurrentState::Locked
         }
     }
+
+    fn change(kind: StateChangeKind, time: i64) -> StateChange {
+        StateChange {
+            kind,
+            time: Timestamp::from_seconds(time),
+        }
+    }
+
+    fn assert_matches_reference(
+        simulator: &mut Simulator,
+        constraint_changes: &[StateChange],
+        break_timer_changes: &[StateChange],
+        target_time: i64,
+    ) -> SimulatorResult {
+        let target_time = Timestamp::from_seconds(target_time);
+        let result = simulator.run(target_time, break_timer_changes);
+        let expected = reference_run(constraint_changes, break_timer_changes, target_time);
+        assert_eq!(
+            (result.target_state, result.until, &result.reason),
+            (expected.target_state, expected.until, &expected.reason),
+            "at {:?} with {:?} and {:?}",
+            target_time,
+            constraint_changes,
+            break_timer_changes
+        );
+        result
+    }
+
+    /// A small linear congruential generator, so that the generated cases are reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+        /// A time on a coarse grid, so that many state changes happen at the same time.
+        fn time(&mut self) -> i64 {
+            self.below(11) as i64 * 10
+        }
+    }
+
+    /// Generates the state changes of a few requirements and locked time ranges, in the order in
+    /// which the manager lists them, and of the break timer.
+    fn generate(rng: &mut Lcg, first_id: u64) -> (Vec<StateChange>, Vec<StateChange>) {
+        let mut constraint_changes = Vec::new();
+        let mut id = first_id;
+        for _ in 0..rng.below(5) {
+            let critical = rng.below(4) == 0;
+            let due = if critical { 0 } else { rng.time() };
+            constraint_changes.push(change(
+                if critical {
+                    StateChangeKind::CriticalRequirementLocked(id)
+                } else {
+                    StateChangeKind::RequirementLocked(id)
+                },
+                due,
+            ));
+            if rng.below(2) == 0 {
+                let expires = due + rng.time();
+                constraint_changes.push(change(
+                    if critical {
+                        StateChangeKind::CriticalRequirementExpired(id)
+                    } else {
+                        StateChangeKind::RequirementExpired(id)
+                    },
+                    expires,
+                ));
+            }
+            id += 1;
+        }
+        for _ in 0..rng.below(4) {
+            let start = rng.time();
+            constraint_changes.push(change(StateChangeKind::RangeLocked(id), start));
+            if rng.below(3) != 0 {
+                let end = start + rng.time();
+                constraint_changes.push(change(StateChangeKind::RangeUnlocked(id), end));
+            }
+            id += 1;
+        }
+        let break_timer_changes = (0..rng.below(3))
+            .map(|_| {
+                let kind = if rng.below(2) == 0 {
+                    StateChangeKind::BreakTimerLocked
+                } else {
+                    StateChangeKind::BreakTimerUnlockable
+                };
+                change(kind, rng.time())
+            })
+            .collect();
+        (constraint_changes, break_timer_changes)
+    }
+
+    #[test]
+    fn matches_the_old_algorithm() {
+        for seed in 0..500 {
+            let mut rng = Lcg(seed);
+            let mut simulator = Simulator::new();
+            let (mut constraint_changes, mut break_timer_changes) = generate(&mut rng, 1);
+            simulator.set_changes(constraint_changes.clone());
+            // forwards, as the server refreshes, then backwards, as when the clock is adjusted
+            let targets = (0..=24).map(|step| step * 5).chain([60, 35, 0, 115]);
+            for target_time in targets {
+                assert_matches_reference(
+                    &mut simulator,
+                    &constraint_changes,
+                    &break_timer_changes,
+                    target_time,
+                );
+            }
+            // the constraints change in the middle of the day
+            (constraint_changes, break_timer_changes) = generate(&mut rng, 100);
+            simulator.set_changes(constraint_changes.clone());
+            for target_time in [50, 55, 100, 20] {
+                assert_matches_reference(
+                    &mut simulator,
+                    &constraint_changes,
+                    &break_timer_changes,
+                    target_time,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn same_time_ties_are_explained_by_requirements_then_ranges_then_the_break_timer() {
+        let constraint_changes = [
+            change(StateChangeKind::RangeLocked(2), 10),
+            change(StateChangeKind::RangeUnlocked(2), 20),
+            change(StateChangeKind::RequirementLocked(1), 10),
+        ];
+        let break_timer_changes = [change(StateChangeKind::BreakTimerLocked, 10)];
+        let mut simulator = Simulator::new();
+        simulator.set_changes(constraint_changes.to_vec());
+        let result =
+            assert_matches_reference(&mut simulator, &constraint_changes, &break_timer_changes, 5);
+        assert_eq!(result.until, Some(Timestamp::from_seconds(10)));
+        assert_eq!(result.reason, CurrentStateReason::LockedTimeRange { id: 2 });
+
+        // requirements are listed before ranges, so they win ties among themselves
+        let constraint_changes = [
+            change(StateChangeKind::RequirementLocked(1), 10),
+            change(StateChangeKind::RangeLocked(2), 10),
+        ];
+        simulator.set_changes(constraint_changes.to_vec());
+        let result =
+            assert_matches_reference(&mut simulator, &constraint_changes, &break_timer_changes, 5);
+        assert_eq!(
+            result.reason,
+            CurrentStateReason::RequirementNotMet { id: 1 }
+        );
+
+        // the break timer comes last among changes at the same time
+        let constraint_changes = [change(StateChangeKind::RangeLocked(2), 10)];
+        simulator.set_changes(constraint_changes.to_vec());
+        let result =
+            assert_matches_reference(&mut simulator, &constraint_changes, &break_timer_changes, 5);
+        assert_eq!(result.reason, CurrentStateReason::LockedTimeRange { id: 2 });
+    }
+
+    #[test]
+    fn locked_reason_follows_the_kind_and_order_of_the_locks() {
+        let constraint_changes = [
+            change(StateChangeKind::RequirementLocked(1), 20),
+            change(StateChangeKind::RequirementLocked(2), 10),
+            change(StateChangeKind::RangeLocked(3), 0),
+            change(StateChangeKind::CriticalRequirementLocked(4), 0),
+        ];
+        let mut simulator = Simulator::new();
+        let mut reason_at = |changes: &[StateChange], target_time| {
+            simulator.set_changes(changes.to_vec());
+            assert_matches_reference(&mut simulator, changes, &[], target_time).reason
+        };
+        assert_eq!(
+            reason_at(&constraint_changes, 25),
+            CurrentStateReason::CriticalRequirementNotMet { id: 4 }
+        );
+        // requirement 2 became due first, even though it is listed after requirement 1
+        assert_eq!(
+            reason_at(&constraint_changes[..3], 25),
+            CurrentStateReason::RequirementNotMet { id: 2 }
+        );
+        assert_eq!(
+            reason_at(&constraint_changes[2..3], 25),
+            CurrentStateReason::LockedTimeRange { id: 3 }
+        );
+        // while the state is going to change, the change that causes it is the reason
+        let expiring = [
+            change(StateChangeKind::RequirementLocked(1), 20),
+            change(StateChangeKind::RequirementExpired(1), 40),
+        ];
+        assert_eq!(
+            reason_at(&expiring, 25),
+            CurrentStateReason::RequirementNotMet { id: 1 }
+        );
+    }
 }
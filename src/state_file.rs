@@ -0,0 +1,186 @@
+use crate::config::{StateFormat, StateRecovery};
+use crate::manager::{DiagonatorManager, SavedState};
+use crate::time::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A way of encoding the saved state in the state file.
+trait StateEncoding {
+    fn encode(&self, state: &SavedState) -> Vec<u8>;
+    fn decode(&self, contents: &[u8]) -> Result<SavedState, String>;
+}
+
+struct JsonEncoding;
+
+impl StateEncoding for JsonEncoding {
+    fn encode(&self, state: &SavedState) -> Vec<u8> {
+        serde_json::to_vec(state).unwrap()
+    }
+    fn decode(&self, contents: &[u8]) -> Result<SavedState, String> {
+        serde_json::from_slice(contents).map_err(|err| err.to_string())
+    }
+}
+
+/// Encodes structs as maps rather than arrays, since some fields are skipped when they are
+/// empty.
+struct MessagePackEncoding;
+
+impl StateEncoding for MessagePackEncoding {
+    fn encode(&self, state: &SavedState) -> Vec<u8> {
+        rmp_serde::to_vec_named(state).unwrap()
+    }
+    fn decode(&self, contents: &[u8]) -> Result<SavedState, String> {
+        rmp_serde::from_slice(contents).map_err(|err| err.to_string())
+    }
+}
+
+/// A self-describing value that bincode can decode. bincode doesn't record the structure of what
+/// it encodes, so it can't decode the fields that are skipped when they are empty or the
+/// internally tagged enums of the saved state by themselves. The state is converted to a JSON
+/// value first, which keeps those attributes working, and then to this value, whose derived
+/// encoding bincode can decode.
+#[derive(Serialize, Deserialize)]
+enum BincodeValue {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<BincodeValue>),
+    Object(Vec<(String, BincodeValue)>),
+}
+
+impl From<serde_json::Value> for BincodeValue {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Self::Null,
+            serde_json::Value::Bool(value) => Self::Bool(value),
+            serde_json::Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+                (Some(value), _) => Self::Unsigned(value),
+                (None, Some(value)) => Self::Signed(value),
+                (None, None) => Self::Float(number.as_f64().unwrap_or_default()),
+            },
+            serde_json::Value::String(value) => Self::String(value),
+            serde_json::Value::Array(values) => {
+                Self::Array(values.into_iter().map(Self::from).collect())
+            }
+            serde_json::Value::Object(fields) => Self::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<BincodeValue> for serde_json::Value {
+    fn from(value: BincodeValue) -> Self {
+        match value {
+            BincodeValue::Null => Self::Null,
+            BincodeValue::Bool(value) => Self::Bool(value),
+            BincodeValue::Unsigned(value) => Self::from(value),
+            BincodeValue::Signed(value) => Self::from(value),
+            BincodeValue::Float(value) => Self::from(value),
+            BincodeValue::String(value) => Self::String(value),
+            BincodeValue::Array(values) => {
+                Self::Array(values.into_iter().map(Self::from).collect())
+            }
+            BincodeValue::Object(fields) => Self::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, Self::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+struct BincodeEncoding;
+
+impl StateEncoding for BincodeEncoding {
+    fn encode(&self, state: &SavedState) -> Vec<u8> {
+        let value = BincodeValue::from(serde_json::to_value(state).unwrap());
+        bincode::serialize(&value).unwrap()
+    }
+    fn decode(&self, contents: &[u8]) -> Result<SavedState, String> {
+        let value: BincodeValue = bincode::deserialize(contents).map_err(|err| err.to_string())?;
+        serde_json::from_value(value.into()).map_err(|err| err.to_string())
+    }
+}
+
+const FORMATS: [StateFormat; 3] = [
+    StateFormat::Json,
+    StateFormat::MessagePack,
+    StateFormat::Bincode,
+];
+
+fn encoding(format: StateFormat) -> &'static dyn StateEncoding {
+    match format {
+        StateFormat::Json => &JsonEncoding,
+        StateFormat::MessagePack => &MessagePackEncoding,
+        StateFormat::Bincode => &BincodeEncoding,
+    }
+}
+
+/// Loads the saved state, returning `None` if no state has been saved yet. A state file that was
+/// saved in another format is restored as well, so that the format can be changed at any time.
+fn load_state(path: &Path, format: StateFormat) -> Result<Option<SavedState>, String> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.to_string()),
+    };
+    let error = match encoding(format).decode(&contents) {
+        Ok(state) => return Ok(Some(state)),
+        Err(msg) => msg,
+    };
+    FORMATS
+        .into_iter()
+        .filter(|other| *other != format)
+        .find_map(|other| encoding(other).decode(&contents).ok())
+        .map(Some)
+        .ok_or(error)
+}
+
+/// Restores the manager's state from the state file. If the state file exists but can't be
+/// restored, e.g. because it's corrupt or was saved by an incompatible version, the configured
+/// recovery behavior is applied instead.
+pub fn restore_state(
+    manager: &mut DiagonatorManager,
+    path: &Path,
+    format: StateFormat,
+    recovery: StateRecovery,
+) {
+    let current_time = Timestamp::now();
+    let result = match load_state(path, format) {
+        Ok(None) => return,
+        Ok(Some(saved)) => manager.restore_state(current_time, saved),
+        Err(msg) => Err(msg),
+    };
+    match result {
+        Ok(()) => eprintln!("Restored state from {}", path.display()),
+        Err(msg) => {
+            eprintln!(
+                "Unable to restore state from {}: {}. Recovering with {:?}",
+                path.display(),
+                msg,
+                recovery
+            );
+            manager.recover(current_time, recovery);
+        }
+    }
+}
+
+/// Writes the state to a temporary file first and then replaces the state file with it, so that
+/// a crash while saving never leaves a half-written state file behind.
+pub async fn save_state(
+    path: &Path,
+    format: StateFormat,
+    state: &SavedState,
+) -> Result<(), std::io::Error> {
+    let temp_path = path.with_extension("tmp");
+    tokio::fs::write(&temp_path, encoding(format).encode(state)).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
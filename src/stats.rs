@@ -0,0 +1,330 @@
+use crate::config::FocusScoreConfig;
+use crate::time::{Duration, LocalDate, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WorkPeriod {
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub label: Option<String>,
+    pub intent: Option<String>,
+    pub too_short: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReviewAnswer {
+    pub prompt: String,
+    pub answer: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DayStats {
+    date: String,
+    focus_time: Duration,
+    focus_time_by_label: BTreeMap<String, Duration>,
+    work_periods: Vec<WorkPeriod>,
+    short_work_periods: u64,
+    review_answers: Vec<ReviewAnswer>,
+    completed_requirements: Vec<String>,
+    missed_requirements: Vec<String>,
+    deactivations: Vec<Duration>,
+    /// The alternative that each requirement with alternatives was completed by
+    #[serde(default)]
+    alternatives_taken: BTreeMap<String, String>,
+    /// The focus score that the day ended with, if a focus score is configured
+    #[serde(default)]
+    focus_score: Option<u32>,
+}
+
+/// How today compares with an earlier day, as the differences from that day, so that clients can
+/// show trend arrows.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    /// The earlier day, in YYYY-MM-DD format
+    pub date: String,
+    pub focus_minutes: i64,
+    pub completed_requirements: i64,
+}
+
+/// The totals of the current week so far.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WeekSummary {
+    /// The first day of the week, in YYYY-MM-DD format
+    pub start: String,
+    pub focus_minutes: i64,
+    pub completed_requirements: usize,
+    pub missed_requirements: usize,
+    pub deactivations: usize,
+}
+
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_minutes();
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+impl DayStats {
+    /// Scores the day from 0 to 100, as the weighted average of the share of the focus goal that
+    /// was reached, the share of work periods that ran their full length, the share of due
+    /// requirements that were completed on time, and a part that halves with the first
+    /// deactivation, becomes a third with the second, and so on.
+    pub fn compute_focus_score(
+        &self,
+        config: &FocusScoreConfig,
+        on_time: usize,
+        due: usize,
+    ) -> u32 {
+        let share = |part: usize, whole: usize| match whole {
+            0 => 1.0,
+            _ => part as f64 / whole as f64,
+        };
+        let focus_goal = Duration::from_minutes(config.focus_goal_minutes.unwrap_or(240));
+        let periods = self.work_periods.len();
+        let parts = [
+            (
+                config.focus_weight.unwrap_or(40),
+                (self.focus_time.as_seconds() as f64 / focus_goal.as_seconds() as f64).min(1.0),
+            ),
+            (
+                config.break_compliance_weight.unwrap_or(20),
+                share(periods - self.short_work_periods as usize, periods),
+            ),
+            (config.on_time_weight.unwrap_or(30), share(on_time, due)),
+            (
+                config.deactivation_weight.unwrap_or(10),
+                share(1, 1 + self.deactivations.len()),
+            ),
+        ];
+        let total_weight: u32 = parts.iter().map(|(weight, _)| weight).sum();
+        let score: f64 = parts
+            .iter()
+            .map(|(weight, part)| *weight as f64 * part)
+            .sum();
+        (100.0 * score / total_weight as f64).round() as u32
+    }
+    /// Renders a plain-text summary of the day, as sent in the daily digest email.
+    pub fn summary(&self) -> String {
+        let mut lines = vec![
+            format!("Summary for {}", self.date),
+            String::new(),
+            format!("Focus time: {}", format_duration(self.focus_time)),
+        ];
+        for (label, duration) in &self.focus_time_by_label {
+            lines.push(format!("  {}: {}", label, format_duration(*duration)));
+        }
+        let completed_periods = self.work_periods.len() as u64 - self.short_work_periods;
+        lines.push(format!(
+            "Break compliance: {} of {} work periods ran their full length",
+            completed_periods,
+            self.work_periods.len()
+        ));
+        lines.push(String::new());
+        lines.push(format!(
+            "Completed requirements: {}",
+            self.completed_requirements.len()
+        ));
+        for name in &self.completed_requirements {
+            match self.alternatives_taken.get(name) {
+                Some(alternative) => lines.push(format!("  {} (by {})", name, alternative)),
+                None => lines.push(format!("  {}", name)),
+            }
+        }
+        lines.push(format!(
+            "Missed requirements: {}",
+            self.missed_requirements.len()
+        ));
+        for name in &self.missed_requirements {
+            lines.push(format!("  {}", name));
+        }
+        if let Some(score) = self.focus_score {
+            lines.push(format!("Focus score: {}", score));
+        }
+        let mut deactivated = Duration::ZERO;
+        for duration in &self.deactivations {
+            deactivated += *duration;
+        }
+        lines.push(format!(
+            "Deactivations: {} ({} in total)",
+            self.deactivations.len(),
+            format_duration(deactivated)
+        ));
+        lines.join("\n") + "\n"
+    }
+}
+
+/// The parts of the statistics that only the GraphQL endpoint converts field by field.
+#[cfg(feature = "graphql")]
+impl DayStats {
+    pub fn date(&self) -> &str {
+        &self.date
+    }
+    pub fn focus_time(&self) -> Duration {
+        self.focus_time
+    }
+    pub fn focus_time_by_label(&self) -> &BTreeMap<String, Duration> {
+        &self.focus_time_by_label
+    }
+    pub fn work_periods(&self) -> &[WorkPeriod] {
+        &self.work_periods
+    }
+    pub fn short_work_periods(&self) -> u64 {
+        self.short_work_periods
+    }
+    pub fn review_answers(&self) -> &[ReviewAnswer] {
+        &self.review_answers
+    }
+    pub fn completed_requirements(&self) -> &[String] {
+        &self.completed_requirements
+    }
+    pub fn missed_requirements(&self) -> &[String] {
+        &self.missed_requirements
+    }
+    pub fn deactivations(&self) -> &[Duration] {
+        &self.deactivations
+    }
+    pub fn alternatives_taken(&self) -> &BTreeMap<String, String> {
+        &self.alternatives_taken
+    }
+    pub fn focus_score(&self) -> Option<u32> {
+        self.focus_score
+    }
+}
+
+pub struct Stats {
+    days: Vec<DayStats>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self { days: Vec::new() }
+    }
+    pub fn from_days(days: Vec<DayStats>) -> Self {
+        Self { days }
+    }
+    pub fn new_day(&mut self, date: &LocalDate) {
+        self.days.push(DayStats {
+            date: date.format("%Y-%m-%d").to_string(),
+            focus_time: Duration::ZERO,
+            focus_time_by_label: BTreeMap::new(),
+            work_periods: Vec::new(),
+            short_work_periods: 0,
+            review_answers: Vec::new(),
+            completed_requirements: Vec::new(),
+            missed_requirements: Vec::new(),
+            deactivations: Vec::new(),
+            alternatives_taken: BTreeMap::new(),
+            focus_score: None,
+        });
+    }
+    pub fn record_work_period(&mut self, period: WorkPeriod) {
+        if let Some(today) = self.days.last_mut() {
+            let duration = period.end - period.start;
+            today.focus_time += duration;
+            if let Some(label) = &period.label {
+                *today
+                    .focus_time_by_label
+                    .entry(label.clone())
+                    .or_insert(Duration::ZERO) += duration;
+            }
+            if period.too_short {
+                today.short_work_periods += 1;
+            }
+            today.work_periods.push(period);
+        }
+    }
+    pub fn record_activity_time(&mut self, name: &str, duration: Duration) {
+        if let Some(today) = self.days.last_mut() {
+            *today
+                .focus_time_by_label
+                .entry(name.to_owned())
+                .or_insert(Duration::ZERO) += duration;
+        }
+    }
+    pub fn record_review_answers(&mut self, answers: Vec<ReviewAnswer>) {
+        if let Some(today) = self.days.last_mut() {
+            today.review_answers.extend(answers);
+        }
+    }
+    /// Records whether a requirement was completed by the end of the day, and by which
+    /// alternative if it has alternatives.
+    pub fn record_requirement(&mut self, name: &str, complete: bool, completed_by: Option<&str>) {
+        if let Some(today) = self.days.last_mut() {
+            if complete {
+                today.completed_requirements.push(name.to_owned());
+                if let Some(alternative) = completed_by {
+                    today
+                        .alternatives_taken
+                        .insert(name.to_owned(), alternative.to_owned());
+                }
+            } else {
+                today.missed_requirements.push(name.to_owned());
+            }
+        }
+    }
+    pub fn record_focus_score(&mut self, score: u32) {
+        if let Some(today) = self.days.last_mut() {
+            today.focus_score = Some(score);
+        }
+    }
+    pub fn today(&self) -> Option<&DayStats> {
+        self.days.last()
+    }
+    pub fn record_deactivation(&mut self, duration: Duration) {
+        if let Some(today) = self.days.last_mut() {
+            today.deactivations.push(duration);
+        }
+    }
+    pub fn day(&self, date: &LocalDate) -> Option<&DayStats> {
+        let date = date.format("%Y-%m-%d").to_string();
+        self.days.iter().find(|day| day.date == date)
+    }
+    pub fn days(&self) -> &[DayStats] {
+        &self.days
+    }
+    /// Totals the days from the start of the week until today. Today's requirements are only
+    /// recorded once the day ends, so the number completed so far is given.
+    pub fn week(&self, start: &LocalDate, completed_today: usize) -> WeekSummary {
+        let start = start.format("%Y-%m-%d").to_string();
+        let mut summary = WeekSummary {
+            start,
+            focus_minutes: 0,
+            completed_requirements: completed_today,
+            missed_requirements: 0,
+            deactivations: 0,
+        };
+        for day in self.days.iter().filter(|day| day.date >= summary.start) {
+            summary.focus_minutes += day.focus_time.as_minutes();
+            summary.completed_requirements += day.completed_requirements.len();
+            summary.missed_requirements += day.missed_requirements.len();
+            summary.deactivations += day.deactivations.len();
+        }
+        summary
+    }
+    /// Totals the deactivations of the days since `start`, including today.
+    pub fn deactivated_since(&self, start: &LocalDate) -> Duration {
+        let start = start.format("%Y-%m-%d").to_string();
+        let mut total = Duration::ZERO;
+        for day in self.days.iter().filter(|day| day.date >= start) {
+            for duration in &day.deactivations {
+                total += *duration;
+            }
+        }
+        total
+    }
+    /// Compares today so far with an earlier day, if both were recorded. Today's requirements
+    /// are only recorded once the day ends, so the number completed so far is given.
+    pub fn compare(
+        &self,
+        today: &LocalDate,
+        completed_today: usize,
+        earlier: &LocalDate,
+    ) -> Option<Comparison> {
+        let (today, earlier) = (self.day(today)?, self.day(earlier)?);
+        Some(Comparison {
+            date: earlier.date.clone(),
+            focus_minutes: today.focus_time.as_minutes() - earlier.focus_time.as_minutes(),
+            completed_requirements: completed_today as i64
+                - earlier.completed_requirements.len() as i64,
+        })
+    }
+}
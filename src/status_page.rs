@@ -0,0 +1,105 @@
+use crate::config::StatusPageConfig;
+use crate::manager::{CurrentInfo, CurrentState};
+use crate::time::Timestamp;
+use serde_json::json;
+use std::path::Path;
+
+const DEFAULT_INTERVAL_SECONDS: u64 = 60;
+
+fn state_name(state: CurrentState) -> &'static str {
+    match state {
+        CurrentState::Unlocked => "Unlocked",
+        CurrentState::Locked => "Locked",
+        CurrentState::Unlockable => "Unlockable",
+    }
+}
+
+fn render_json(info: &CurrentInfo, now: Timestamp) -> String {
+    json!({
+        "state": info.state(),
+        "until": info.until(),
+        "updated": now,
+    })
+    .to_string()
+}
+
+/// Renders a page that counts down to the end of the current state in the browser, and
+/// reloads itself whenever the page is regenerated.
+fn render_html(info: &CurrentInfo, now: Timestamp, interval_seconds: u64) -> String {
+    let state = state_name(info.state());
+    let (until_text, until_seconds) = match info.until() {
+        Some(until) => (
+            format!(" until {}", until.format_local("%H:%M")),
+            until.as_seconds().to_string(),
+        ),
+        None => (String::new(), "null".to_owned()),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="{interval_seconds}">
+<title>{state}</title>
+</head>
+<body>
+<h1>{state}{until_text}</h1>
+<p id="countdown"></p>
+<p>Updated at {updated}</p>
+<script>
+const until = {until_seconds};
+function tick() {{
+  if (until === null) return;
+  const remaining = Math.max(0, until - Math.floor(Date.now() / 1000));
+  const minutes = Math.floor(remaining / 60);
+  const seconds = String(remaining % 60).padStart(2, "0");
+  document.getElementById("countdown").textContent = minutes + ":" + seconds + " remaining";
+}}
+tick();
+setInterval(tick, 1000);
+</script>
+</body>
+</html>
+"#,
+        updated = now.format_local("%Y-%m-%d %H:%M:%S"),
+    )
+}
+
+/// Replaces the file's contents at once, so that a web server never serves a half-written page.
+async fn write_atomically(path: &Path, contents: String) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    tokio::fs::write(&temp_path, contents).await?;
+    tokio::fs::rename(&temp_path, path).await
+}
+
+/// Periodically writes `status.html` and `status.json` with the current state to the
+/// configured directory.
+pub async fn run_status_page(config: StatusPageConfig, current_info: impl Fn() -> CurrentInfo) {
+    let interval_seconds = config
+        .interval_seconds
+        .unwrap_or(DEFAULT_INTERVAL_SECONDS)
+        .max(1);
+    if let Err(err) = tokio::fs::create_dir_all(&config.directory).await {
+        eprintln!(
+            "Unable to create the status page directory {}: {}",
+            config.directory.display(),
+            err
+        );
+        return;
+    }
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(interval_seconds));
+    loop {
+        interval.tick().await;
+        let now = Timestamp::now();
+        let info = current_info();
+        for (name, contents) in [
+            ("status.json", render_json(&info, now)),
+            ("status.html", render_html(&info, now, interval_seconds)),
+        ] {
+            let path = config.directory.join(name);
+            if let Err(err) = write_atomically(&path, contents).await {
+                eprintln!("Unable to write {}: {}", path.display(), err);
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use crate::config::Location;
+use crate::time::{LocalDate, Timestamp};
+
+#[derive(Debug, Clone, Copy)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+}
+
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+const J2000_JULIAN_DAY: f64 = 2451545.0;
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+fn sin_deg(x: f64) -> f64 {
+    x.to_radians().sin()
+}
+
+fn to_julian_day(time: Timestamp) -> f64 {
+    time.as_seconds() as f64 / SECONDS_PER_DAY + UNIX_EPOCH_JULIAN_DAY
+}
+
+fn from_julian_day(julian_day: f64) -> Timestamp {
+    Timestamp::from_seconds(((julian_day - UNIX_EPOCH_JULIAN_DAY) * SECONDS_PER_DAY).round() as i64)
+}
+
+/// Computes the time of sunrise or sunset on the given date using the sunrise equation.
+/// Returns `None` if the sun does not rise or set on that date (polar day or polar night).
+pub fn event_time(date: &LocalDate, event: SunEvent, location: &Location) -> Option<Timestamp> {
+    let local_noon = Timestamp::from_seconds(date.and_hms(12, 0, 0).timestamp());
+    // number of the day since J2000 whose solar noon is closest to local noon
+    let day = (to_julian_day(local_noon) - J2000_JULIAN_DAY + location.longitude / 360.0).round();
+    let mean_solar_time = day - location.longitude / 360.0;
+    let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+    let center = 1.9148 * sin_deg(mean_anomaly)
+        + 0.02 * sin_deg(2.0 * mean_anomaly)
+        + 0.0003 * sin_deg(3.0 * mean_anomaly);
+    let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let solar_transit = J2000_JULIAN_DAY + mean_solar_time + 0.0053 * sin_deg(mean_anomaly)
+        - 0.0069 * sin_deg(2.0 * ecliptic_longitude);
+    let declination_sin = sin_deg(ecliptic_longitude) * sin_deg(23.4397);
+    let declination_cos = declination_sin.asin().cos();
+    let hour_angle_cos = (sin_deg(-0.833) - sin_deg(location.latitude) * declination_sin)
+        / (location.latitude.to_radians().cos() * declination_cos);
+    if !(-1.0..=1.0).contains(&hour_angle_cos) {
+        return None;
+    }
+    let hour_angle = hour_angle_cos.acos().to_degrees();
+    Some(from_julian_day(match event {
+        SunEvent::Sunrise => solar_transit - hour_angle / 360.0,
+        SunEvent::Sunset => solar_transit + hour_angle / 360.0,
+    }))
+}
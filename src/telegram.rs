@@ -0,0 +1,173 @@
+use crate::chat::{describe_pending_deactivation, describe_response, parse_command};
+use crate::clients::Role;
+use crate::config::TelegramConfig;
+use crate::server::{Request, Response};
+use crate::time::{Duration, Timestamp};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+const POLL_TIMEOUT_SECONDS: u64 = 30;
+const RETRY_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(10);
+const CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+
+struct TelegramClient {
+    http: reqwest::Client,
+    config: TelegramConfig,
+}
+
+impl TelegramClient {
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let api_url = self.config.api_url.as_deref().unwrap_or(DEFAULT_API_URL);
+        let response: Value = self
+            .http
+            .post(format!(
+                "{}/bot{}/{}",
+                api_url, self.config.bot_token, method
+            ))
+            .json(&params)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?
+            .json()
+            .await
+            .map_err(|err| err.to_string())?;
+        if response["ok"].as_bool() != Some(true) {
+            return Err(response["description"]
+                .as_str()
+                .unwrap_or("unknown error")
+                .to_owned());
+        }
+        Ok(response["result"].clone())
+    }
+    async fn send(&self, chat_id: i64, text: &str) {
+        if let Err(err) = self
+            .call("sendMessage", json!({"chat_id": chat_id, "text": text}))
+            .await
+        {
+            eprintln!("Unable to send Telegram message: {}", err);
+        }
+    }
+    fn role_of(&self, user_id: i64) -> Option<Role> {
+        self.config
+            .users
+            .iter()
+            .find(|user| user.user_id == user_id.to_string())
+            .map(|user| user.role)
+    }
+    async fn answer_commands(&self, handle: &impl Fn(String, Role, Request) -> Response) {
+        let mut offset: Option<i64> = None;
+        loop {
+            // the first poll only finds out which updates are already waiting, so that commands
+            // sent while the server wasn't running are not answered
+            let timeout = if offset.is_some() {
+                POLL_TIMEOUT_SECONDS
+            } else {
+                0
+            };
+            let updates = match self
+                .call(
+                    "getUpdates",
+                    json!({"offset": offset, "timeout": timeout, "allowed_updates": ["message"]}),
+                )
+                .await
+            {
+                Ok(updates) => updates.as_array().cloned().unwrap_or_default(),
+                Err(err) => {
+                    eprintln!("Unable to receive Telegram updates: {}", err);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            for update in &updates {
+                if offset.is_some() {
+                    self.answer(&update["message"], handle).await;
+                }
+            }
+            let last_update_id = updates
+                .iter()
+                .filter_map(|update| update["update_id"].as_i64())
+                .max();
+            if let Some(last_update_id) = last_update_id {
+                offset = Some(last_update_id + 1);
+            } else if offset.is_none() {
+                offset = Some(0);
+            }
+        }
+    }
+    async fn answer(&self, message: &Value, handle: &impl Fn(String, Role, Request) -> Response) {
+        let (Some(chat_id), Some(user_id), Some(text)) = (
+            message["chat"]["id"].as_i64(),
+            message["from"]["id"].as_i64(),
+            message["text"].as_str(),
+        ) else {
+            return;
+        };
+        let Some(request) = parse_command(text, '/') else {
+            return;
+        };
+        let reply = match (self.role_of(user_id), request) {
+            (None, _) => "You are not allowed to use this bot.".to_owned(),
+            (Some(_), Err(msg)) => msg,
+            (Some(role), Ok(request)) => {
+                describe_response(&handle(format!("telegram:{}", user_id), role, request))
+            }
+        };
+        self.send(chat_id, &reply).await;
+    }
+    /// Reminds the chat of requirements that are almost due, and announces deactivations that
+    /// are waiting for approval.
+    async fn send_notifications(&self, handle: &impl Fn(String, Role, Request) -> Response) {
+        let remind_before = self
+            .config
+            .remind_before_due_minutes
+            .map(Duration::from_minutes);
+        let mut reminded: HashSet<u64> = HashSet::new();
+        let mut announced: HashSet<u64> = HashSet::new();
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Response::Info { info } =
+                handle("telegram".to_owned(), Role::Observer, Request::GetInfo)
+            else {
+                continue;
+            };
+            let now = Timestamp::now();
+            for req in info.requirements() {
+                let almost_due =
+                    remind_before.is_some_and(|remind_before| now + remind_before >= req.due());
+                if !req.is_complete() && almost_due && reminded.insert(req.id()) {
+                    let text = format!(
+                        "Reminder: '{}' is due at {}. Send /complete {} when it's done.",
+                        req.name(),
+                        req.due().format_local("%H:%M"),
+                        req.id()
+                    );
+                    self.send(self.config.chat_id, &text).await;
+                }
+            }
+            for pending in info.pending_deactivations() {
+                if announced.insert(pending.id()) {
+                    let text = describe_pending_deactivation(pending, '/');
+                    self.send(self.config.chat_id, &text).await;
+                }
+            }
+        }
+    }
+}
+
+/// Runs a Telegram bot that answers commands from the configured users, with the permissions of
+/// their roles, and sends reminders and approval requests to the configured chat.
+pub async fn run_telegram_bot(
+    config: TelegramConfig,
+    handle: impl Fn(String, Role, Request) -> Response,
+) {
+    let client = TelegramClient {
+        http: reqwest::Client::new(),
+        config,
+    };
+    tokio::join!(
+        client.answer_commands(&handle),
+        client.send_notifications(&handle)
+    );
+}
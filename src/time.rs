@@ -1,17 +1,21 @@
-use chrono::TimeZone;
+use crate::config::Location;
+use crate::sun::{self, SunEvent};
+use chrono::{Datelike, TimeZone};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::ops::Add;
+use std::fmt::Display;
+use std::ops::{Add, AddAssign, Sub};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct HourMinute {
     hour: u32,
     minute: u32,
 }
 
 impl HourMinute {
+    pub const START_OF_DAY: Self = Self { hour: 0, minute: 0 };
     pub fn new(hour: u32, minute: u32) -> Option<Self> {
         if (0..=23).contains(&hour) && (0..=59).contains(&minute) {
             Some(Self { hour, minute })
@@ -21,12 +25,18 @@ impl HourMinute {
     }
 }
 
+impl Display for HourMinute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
 impl Serialize for HourMinute {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        serializer.serialize_str(&format!("{:02}:{:02}", self.hour, self.minute))
+        serializer.serialize_str(&self.to_string())
     }
 }
 
@@ -57,15 +67,110 @@ impl<'de> Deserialize<'de> for HourMinute {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+/// A time of day that is either a fixed clock time or an offset from sunrise/sunset,
+/// written as `"HH:MM"`, `"sunset"`, `"sunset+01:00"`, or `"sunrise-00:30"`.
+#[derive(Debug, Clone)]
+pub enum TimeOfDay {
+    Clock(HourMinute),
+    Sun { event: SunEvent, offset: Duration },
+}
+
+impl TimeOfDay {
+    pub fn is_sun_relative(&self) -> bool {
+        matches!(self, Self::Sun { .. })
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Self::Clock(hm) => hm.serialize(serializer),
+            Self::Sun { event, offset } => {
+                let minutes = offset.0 / 60;
+                let event = match event {
+                    SunEvent::Sunrise => "sunrise",
+                    SunEvent::Sunset => "sunset",
+                };
+                if minutes == 0 {
+                    serializer.serialize_str(event)
+                } else {
+                    serializer.serialize_str(&format!(
+                        "{}{}{:02}:{:02}",
+                        event,
+                        if minutes < 0 { '-' } else { '+' },
+                        minutes.abs() / 60,
+                        minutes.abs() % 60
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let val = String::deserialize(deserializer)?;
+        lazy_static! {
+            static ref RE: Regex =
+                Regex::new(r"^(sunrise|sunset)(?:([+-])(\d?\d):(\d\d))?$").unwrap();
+        }
+        if let Some(captured) = RE.captures(&val) {
+            let event = if &captured[1] == "sunrise" {
+                SunEvent::Sunrise
+            } else {
+                SunEvent::Sunset
+            };
+            let mut minutes = 0;
+            if let (Some(sign), Some(h), Some(m)) =
+                (captured.get(2), captured.get(3), captured.get(4))
+            {
+                let m: i64 = m.as_str().parse().unwrap();
+                if m > 59 {
+                    return Err(D::Error::custom(format!(
+                        "Offset is out of range: '{}'",
+                        val
+                    )));
+                }
+                minutes = h.as_str().parse::<i64>().unwrap() * 60 + m;
+                if sign.as_str() == "-" {
+                    minutes = -minutes;
+                }
+            }
+            return Ok(Self::Sun {
+                event,
+                offset: Duration::from_minutes(minutes),
+            });
+        }
+        HourMinute::deserialize(serde::de::value::StrDeserializer::<D::Error>::new(&val))
+            .map(Self::Clock)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Duration(i64);
 
 impl Duration {
+    pub const ZERO: Self = Self(0);
     pub fn from_minutes(minutes: i64) -> Self {
         Self(minutes * 60)
     }
+    pub fn from_seconds(seconds: i64) -> Self {
+        Self(seconds)
+    }
+    pub fn as_seconds(self) -> i64 {
+        self.0
+    }
+    pub fn as_minutes(self) -> i64 {
+        self.0 / 60
+    }
 }
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Timestamp(i64);
 
 impl Timestamp {
@@ -73,19 +178,41 @@ impl Timestamp {
     pub fn from_date_hm(date: &LocalDate, hm: &HourMinute) -> Self {
         Self(date.and_hms(hm.hour, hm.minute, 0).timestamp())
     }
-    pub fn from_date_hm_opt(date: &LocalDate, hm: &Option<HourMinute>) -> Option<Self> {
-        if let Some(hm) = hm {
-            Some(Self(date.and_hms(hm.hour, hm.minute, 0).timestamp()))
-        } else {
-            None
+    pub fn from_date_time_of_day(
+        date: &LocalDate,
+        time: &TimeOfDay,
+        location: Option<&Location>,
+    ) -> Option<Self> {
+        match time {
+            TimeOfDay::Clock(hm) => Some(Self::from_date_hm(date, hm)),
+            TimeOfDay::Sun { event, offset } => {
+                // offsets that would cross midnight stop at the start or the end of the day
+                let day_start = Self::from_date_hm(date, &HourMinute::START_OF_DAY);
+                let day_end = Self::from_date_hm(&date.succ(), &HourMinute::START_OF_DAY);
+                let time = sun::event_time(date, *event, location?)? + *offset;
+                Some(time.clamp(day_start, day_end))
+            }
         }
     }
+    pub fn from_seconds(seconds: i64) -> Self {
+        Self(seconds)
+    }
+    pub fn as_seconds(self) -> i64 {
+        self.0
+    }
     pub fn now() -> Self {
         Self(chrono::Local::now().timestamp())
     }
     pub fn get_date(self) -> LocalDate {
         chrono::Local.timestamp(self.0, 0).date()
     }
+    /// Formats the timestamp as a local time, e.g. `"14:30"` for the format `"%H:%M"`.
+    pub fn format_local(self, format: &str) -> String {
+        chrono::Local
+            .timestamp(self.0, 0)
+            .format(format)
+            .to_string()
+    }
 }
 
 impl Add<Duration> for Timestamp {
@@ -95,4 +222,30 @@ impl Add<Duration> for Timestamp {
     }
 }
 
+impl Sub for Timestamp {
+    type Output = Duration;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Duration(self.0 - rhs.0)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Self) {
+        self.0 += rhs.0;
+    }
+}
+
 pub type LocalDate = chrono::Date<chrono::Local>;
+
+/// Parses a date in the `YYYY-MM-DD` format.
+pub fn parse_date(date: &str) -> Option<LocalDate> {
+    let naive_date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    chrono::Local.from_local_date(&naive_date).single()
+}
+
+/// Finds the first day of the week containing the date, for weeks that start on `first_day`.
+pub fn start_of_week(date: &LocalDate, first_day: chrono::Weekday) -> LocalDate {
+    let days_since_start =
+        (date.weekday().num_days_from_monday() + 7 - first_day.num_days_from_monday()) % 7;
+    *date - chrono::Duration::days(days_since_start as i64)
+}
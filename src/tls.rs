@@ -0,0 +1,51 @@
+use crate::config::TlsConfig;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|err| {
+        format!(
+            "Received error '{}' when reading from file {}",
+            err,
+            path.display()
+        )
+    })
+}
+
+/// Creates a TLS acceptor from the PEM-encoded certificate chain and private key.
+pub fn load_tls_acceptor(config: &TlsConfig) -> Result<TlsAcceptor, String> {
+    let certs = rustls_pemfile::certs(&mut read_file(&config.certificate)?.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| {
+            format!(
+                "Invalid certificate in {}: {}",
+                config.certificate.display(),
+                err
+            )
+        })?;
+    if certs.is_empty() {
+        return Err(format!(
+            "No certificate found in {}",
+            config.certificate.display()
+        ));
+    }
+    let key = rustls_pemfile::private_key(&mut read_file(&config.private_key)?.as_slice())
+        .map_err(|err| {
+            format!(
+                "Invalid private key in {}: {}",
+                config.private_key.display(),
+                err
+            )
+        })?
+        .ok_or_else(|| format!("No private key found in {}", config.private_key.display()))?;
+    let server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| format!("Unable to use the TLS certificate: {}", err))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
@@ -0,0 +1,232 @@
+#[cfg(unix)]
+use crate::config::systemd_socket_index;
+use crate::connections::ConnectionLimits;
+use crate::metrics::Metrics;
+use hyper_util::rt::TokioIo;
+#[cfg(unix)]
+use lazy_static::lazy_static;
+#[cfg(unix)]
+use listenfd::ListenFd;
+use std::future::Future;
+use std::io;
+#[cfg(unix)]
+use std::sync::{Mutex, PoisonError};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+
+/// A kind of endpoint that the server listens on for connections, such as a TCP address, a Unix
+/// domain socket, or a Windows named pipe.
+pub trait Transport {
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+    /// Waits for the next connection, returning it together with a description of the peer for
+    /// error messages.
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Stream, String)>>;
+}
+
+impl Transport for TcpListener {
+    type Stream = TcpStream;
+    async fn accept(&self) -> io::Result<(TcpStream, String)> {
+        let (stream, peer) = TcpListener::accept(self).await?;
+        Ok((stream, peer.to_string()))
+    }
+}
+
+#[cfg(unix)]
+impl Transport for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+    async fn accept(&self) -> io::Result<(tokio::net::UnixStream, String)> {
+        let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+        Ok((stream, "a Unix domain socket client".to_owned()))
+    }
+}
+
+/// A named pipe that accepts connections by creating a new instance of the pipe for the next
+/// client whenever one connects, which is the Windows equivalent of a Unix domain socket.
+#[cfg(windows)]
+struct NamedPipeListener {
+    path: String,
+    next: tokio::sync::Mutex<tokio::net::windows::named_pipe::NamedPipeServer>,
+}
+
+#[cfg(windows)]
+impl NamedPipeListener {
+    fn bind(name: &str) -> io::Result<Self> {
+        let path = format!(r"\\.\pipe\{}", name);
+        // another process that created the pipe first could otherwise receive our clients
+        let first = tokio::net::windows::named_pipe::ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&path)?;
+        Ok(Self {
+            path,
+            next: tokio::sync::Mutex::new(first),
+        })
+    }
+}
+
+#[cfg(windows)]
+impl Transport for NamedPipeListener {
+    type Stream = tokio::net::windows::named_pipe::NamedPipeServer;
+    async fn accept(&self) -> io::Result<(Self::Stream, String)> {
+        let mut next = self.next.lock().await;
+        let result = next.connect().await;
+        // a new instance is needed after a failed connection as well, since the old one may be
+        // left in a state where it can't accept clients anymore
+        let replacement =
+            tokio::net::windows::named_pipe::ServerOptions::new().create(&self.path)?;
+        let connected = std::mem::replace(&mut *next, replacement);
+        result?;
+        Ok((connected, "a named pipe client".to_owned()))
+    }
+}
+
+/// Binds to a Unix domain socket, so that access can be controlled with the socket file's
+/// permissions.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    // a socket left behind by a previous run would prevent binding to the same path
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    tokio::net::UnixListener::bind(path)
+}
+
+#[cfg(unix)]
+lazy_static! {
+    /// The sockets that systemd passed to the server if it was socket-activated, which are taken
+    /// by the listeners that refer to them
+    static ref ACTIVATED_SOCKETS: Mutex<ListenFd> = Mutex::new(ListenFd::from_env());
+}
+
+#[cfg(unix)]
+enum ActivatedSocket {
+    Tcp(TcpListener),
+    Unix(tokio::net::UnixListener),
+}
+
+/// Takes the listening socket at `index` of those that systemd passed to the server, which is
+/// either a TCP or a Unix domain socket.
+#[cfg(unix)]
+fn take_activated_socket(index: usize) -> io::Result<ActivatedSocket> {
+    let mut sockets = ACTIVATED_SOCKETS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let not_passed = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("systemd didn't pass a socket with index {}", index),
+        )
+    };
+    // a socket of the wrong kind is left in place, so that it can be taken as the other kind
+    match sockets.take_tcp_listener(index) {
+        Ok(Some(listener)) => {
+            listener.set_nonblocking(true)?;
+            Ok(ActivatedSocket::Tcp(TcpListener::from_std(listener)?))
+        }
+        Ok(None) => Err(not_passed()),
+        Err(_) => {
+            let listener = sockets.take_unix_listener(index)?.ok_or_else(not_passed)?;
+            listener.set_nonblocking(true)?;
+            Ok(ActivatedSocket::Unix(tokio::net::UnixListener::from_std(
+                listener,
+            )?))
+        }
+    }
+}
+
+/// Listens on `bind_on`, which is either a TCP address, `unix:` followed by the path of a Unix
+/// domain socket, `pipe:` followed by the name of a Windows named pipe, or `systemd[:<index>]`
+/// for a socket passed by systemd, and serves the app on every connection that it accepts.
+pub async fn listen(
+    bind_on: &str,
+    tls: Option<TlsAcceptor>,
+    app: axum::Router,
+    limits: ConnectionLimits,
+    metrics: &'static Metrics,
+) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(index) = systemd_socket_index(bind_on) {
+        let index = index.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        match take_activated_socket(index)? {
+            ActivatedSocket::Tcp(listener) => serve(listener, tls, app, limits, metrics).await,
+            ActivatedSocket::Unix(listener) => serve(listener, tls, app, limits, metrics).await,
+        }
+        return Ok(());
+    }
+    #[cfg(unix)]
+    if let Some(path) = bind_on.strip_prefix("unix:") {
+        let listener = bind_unix(std::path::Path::new(path))?;
+        serve(listener, tls, app, limits, metrics).await;
+        return Ok(());
+    }
+    #[cfg(windows)]
+    if let Some(name) = bind_on.strip_prefix("pipe:") {
+        let listener = NamedPipeListener::bind(name)?;
+        serve(listener, tls, app, limits, metrics).await;
+        return Ok(());
+    }
+    let listener = TcpListener::bind(bind_on).await?;
+    serve(listener, tls, app, limits, metrics).await;
+    Ok(())
+}
+
+async fn serve<T: Transport>(
+    transport: T,
+    tls: Option<TlsAcceptor>,
+    app: axum::Router,
+    limits: ConnectionLimits,
+    metrics: &'static Metrics,
+) {
+    loop {
+        let (stream, peer) = match transport.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                eprintln!("Received error '{}' when accepting a connection", err);
+                continue;
+            }
+        };
+        // connections beyond the limit are closed right away instead of waiting in the backlog
+        let Some(slot) = limits.admit() else {
+            metrics.record_refused_connection();
+            continue;
+        };
+        let (tls, app, limits) = (tls.clone(), app.clone(), limits.clone());
+        tokio::spawn(async move {
+            // the TLS handshake counts towards the idle timeout as well
+            let idle = limits
+                .serve(stream, slot, |stream| async move {
+                    let Some(acceptor) = tls else {
+                        return serve_connection(stream, app).await;
+                    };
+                    match acceptor.accept(stream).await {
+                        Ok(stream) => serve_connection(stream, app).await,
+                        Err(err) => eprintln!(
+                            "Received error '{}' during the TLS handshake with {}",
+                            err, peer
+                        ),
+                    }
+                })
+                .await;
+            if idle {
+                metrics.record_idle_connection_closed();
+            }
+        });
+    }
+}
+
+/// Serves HTTP and Socket.IO requests on a single connection, such as a TCP connection, a Unix
+/// domain socket or named pipe connection, or a TLS stream.
+async fn serve_connection<S>(stream: S, app: axum::Router)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let service = hyper_util::service::TowerToHyperService::new(app);
+    let result = hyper::server::conn::http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .with_upgrades()
+        .await;
+    if let Err(err) = result {
+        eprintln!("Received error '{}' when serving a connection", err);
+    }
+}
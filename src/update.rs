@@ -0,0 +1,43 @@
+use crate::version::VERSION;
+use serde::{Deserialize, Serialize};
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/yaxollum/diagonator/releases/latest";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpdateStatus {
+    pub current_version: String,
+    pub latest_version: String,
+    pub update_available: bool,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Compares the running version against the latest release on GitHub.
+pub async fn check_for_update() -> Result<UpdateStatus, String> {
+    let release: Release = reqwest::Client::new()
+        .get(LATEST_RELEASE_URL)
+        .header(reqwest::header::USER_AGENT, "diagonator-server")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|err| format!("Unable to fetch the latest release: {}", err))?
+        .json()
+        .await
+        .map_err(|err| format!("Unable to parse the latest release: {}", err))?;
+    let latest_version = release.tag_name.trim_start_matches('v').to_owned();
+    Ok(UpdateStatus {
+        update_available: parse_version(&latest_version) > parse_version(VERSION),
+        current_version: VERSION.to_owned(),
+        latest_version,
+    })
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
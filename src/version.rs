@@ -0,0 +1,33 @@
+use crate::time::Timestamp;
+use serde::{Deserialize, Serialize};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Incremented whenever requests or responses change in a way that clients need to know about.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// The oldest protocol version that clients can still use.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VersionInfo {
+    version: String,
+    git_hash: String,
+    build_time: Timestamp,
+    features: Vec<String>,
+    protocol_version: u32,
+}
+
+impl VersionInfo {
+    pub fn current() -> Self {
+        Self {
+            version: VERSION.to_owned(),
+            git_hash: env!("DIAGONATOR_GIT_HASH").to_owned(),
+            build_time: Timestamp::from_seconds(env!("DIAGONATOR_BUILD_TIME").parse().unwrap()),
+            features: env!("DIAGONATOR_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+}